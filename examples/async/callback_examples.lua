@@ -84,7 +84,7 @@ collection:UpdateOneAsync(
         if err then
             print("Error updating document: " .. err)
         else
-            print("Updated " .. result .. " document(s)")
+            print("Updated " .. result.modified .. " document(s)")
         end
     end
 )
@@ -99,7 +99,7 @@ collection:UpdateManyAsync(
         if err then
             print("Error updating documents: " .. err)
         else
-            print("Updated " .. result .. " document(s)")
+            print("Updated " .. result.modified .. " document(s)")
         end
     end
 )
@@ -112,7 +112,7 @@ collection:DeleteOneAsync(
         if err then
             print("Error deleting document: " .. err)
         else
-            print("Deleted " .. result .. " document(s)")
+            print("Deleted " .. result.deleted .. " document(s)")
         end
     end
 )
@@ -125,7 +125,7 @@ collection:DeleteManyAsync(
         if err then
             print("Error deleting documents: " .. err)
         else
-            print("Deleted " .. result .. " document(s)")
+            print("Deleted " .. result.deleted .. " document(s)")
         end
     end
 )
@@ -196,13 +196,13 @@ collection:InsertOneAsync(
                         { name = "Charlie" },
                         { ["$set"] = { age = 29 } },
                         false,
-                        function(err, count)
+                        function(err, result)
                             if err then
                                 print("Error in update: " .. err)
                                 return
                             end
 
-                            print("Updated " .. count .. " document(s)")
+                            print("Updated " .. result.modified .. " document(s)")
                             print("Callback chain completed successfully!")
                         end
                     )
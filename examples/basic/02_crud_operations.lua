@@ -79,7 +79,7 @@ local updated = players:UpdateOne(
     { steamid = "STEAM_0:1:12345678" },
     { ["$set"] = { level = 2, credits = 1500 } }
 )
-print("   Updated", updated, "document(s)")
+print("   Updated", updated.modified, "document(s)")
 
 -- UPDATE: Update many documents
 print("\n8. UPDATE MANY")
@@ -87,17 +87,17 @@ local updatedMany = players:UpdateMany(
     { level = { ["$lt"] = 5 } },
     { ["$inc"] = { credits = 100 } }
 )
-print("   Updated", updatedMany, "document(s)")
+print("   Updated", updatedMany.modified, "document(s)")
 
 -- DELETE: Delete one document
 print("\n9. DELETE ONE")
 local deleted = players:DeleteOne({ steamid = "STEAM_0:1:11111111" })
-print("   Deleted", deleted, "document(s)")
+print("   Deleted", deleted.deleted, "document(s)")
 
 -- DELETE: Delete many documents
 print("\n10. DELETE MANY")
 local deletedMany = players:DeleteMany({ level = { ["$lt"] = 2 } })
-print("   Deleted", deletedMany, "document(s)")
+print("   Deleted", deletedMany.deleted, "document(s)")
 
 print("\n=== CRUD Operations Complete ===")
 
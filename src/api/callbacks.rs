@@ -1,74 +1,305 @@
-use crate::core::worker::{decrease_callbacks_pending, get_callbacks_pending, mark_hook_unregistered, JobResult, CALLBACK_QUEUE, LUA_REGISTRYINDEX};
-use crate::types::bson_to_lua_table;
+use crate::api::collection::{push_bulk_write_result, push_values, push_write_result};
+use crate::core::worker::{
+    decrease_callbacks_pending, get_callbacks_pending, is_hook_registered, mark_hook_unregistered, Job, JobResult, LuaReference, CALLBACK_QUEUE,
+    LUA_REGISTRYINDEX,
+};
+use crate::types::{bson_to_lua_table, bson_value_to_lua, Int64ModeGuard};
+use crate::utils::{push_bytes, push_error_object};
 use log::{error, info};
-use rglua::lua::LuaState;
+use mongodb::bson::Document;
+use rglua::lua::{self, LuaState};
 use rglua::prelude::*;
+use std::cell::{Cell, RefCell};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
-pub unsafe extern "C" fn poll_callbacks(l: LuaState) -> i32 {
-    let guard = match CALLBACK_QUEUE.lock() {
-        Ok(g) => g,
-        Err(_) => {
-            error!("Failed to lock callback queue");
-            return 0;
-        }
+/// How much work one tick may spend delivering results; whatever is left
+/// carries over to the next tick
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DispatchBudget {
+    /// `None` delivers until the queue is empty
+    pub time: Option<Duration>,
+    /// Callback invocations per tick; `None` for no limit
+    pub callbacks: Option<usize>,
+    /// Documents converted per step when a result is assembled across ticks
+    pub chunk_size: usize,
+}
+
+impl DispatchBudget {
+    pub const UNLIMITED: Self = Self {
+        time: None,
+        callbacks: None,
+        chunk_size: usize::MAX,
     };
 
+    fn exhausted(&self, elapsed: Duration, callbacks: usize) -> bool {
+        self.time.is_some_and(|time| elapsed >= time) || self.callbacks.is_some_and(|max| callbacks >= max)
+    }
+}
+
+impl Default for DispatchBudget {
+    fn default() -> Self {
+        Self {
+            time: Some(Duration::from_millis(5)),
+            callbacks: None,
+            chunk_size: 1000,
+        }
+    }
+}
+
+static BUDGET: Mutex<Option<DispatchBudget>> = Mutex::new(None);
+
+pub fn dispatch_budget() -> DispatchBudget {
+    BUDGET.lock().unwrap_or_else(|e| e.into_inner()).unwrap_or_default()
+}
+
+pub fn set_dispatch_budget(budget: DispatchBudget) {
+    *BUDGET.lock().unwrap_or_else(|e| e.into_inner()) = Some(budget);
+}
+
+/// A large list of documents being converted a chunk at a time
+struct Assembly {
+    job: Job,
+    documents: std::vec::IntoIter<Document>,
+    table: LuaReference,
+    next: i32,
+}
+
+impl Assembly {
+    /// Converts up to `chunk_size` documents; true once all are in the table
+    unsafe fn step(&mut self, l: LuaState, chunk_size: usize) -> bool {
+        let _int64 = Int64ModeGuard::new(self.job.settings.int64);
+        lua_rawgeti(l, LUA_REGISTRYINDEX, self.table);
+        for doc in self.documents.by_ref().take(chunk_size) {
+            bson_to_lua_table(l, &doc);
+            lua_rawseti(l, -2, self.next);
+            self.next += 1;
+        }
+        lua_pop(l, 1);
+        self.documents.len() == 0
+    }
+}
+
+thread_local! {
+    /// Set while callbacks run, so a `Wait` inside a callback does not
+    /// re-enter the loop; the outer loop keeps delivering
+    static DELIVERING: Cell<bool> = const { Cell::new(false) };
+    static ASSEMBLING: RefCell<Option<Assembly>> = const { RefCell::new(None) };
+}
+
+/// Think hook: delivers finished jobs within the configured budget
+pub unsafe extern "C" fn poll_callbacks(l: LuaState) -> i32 {
+    run_callbacks(l, dispatch_budget());
+    0
+}
+
+/// Delivers everything that is ready, regardless of the budget
+pub(crate) unsafe fn flush_callbacks(l: LuaState) {
+    run_callbacks(l, DispatchBudget::UNLIMITED);
+}
+
+unsafe fn run_callbacks(l: LuaState, budget: DispatchBudget) {
+    if DELIVERING.with(|delivering| delivering.replace(true)) {
+        return;
+    }
+
+    let started = Instant::now();
+    let mut calls = 0;
+    let mut steps = 0;
     let mut processed = 0;
 
     loop {
-        match guard.1.try_recv() {
-            Ok(job) => {
-                processed += 1;
-
-                if let Some(callback_ref) = job.callback {
-                    // Get the callback function from registry
-                    lua_rawgeti(l, LUA_REGISTRYINDEX, callback_ref);
+        if steps > 0 && budget.exhausted(started.elapsed(), calls) {
+            break;
+        }
 
-                    // Check if it's a function
-                    if !lua_isfunction(l, -1) {
-                        lua_pop(l, 1);
-                        luaL_unref(l, LUA_REGISTRYINDEX, callback_ref);
-                        continue;
-                    }
+        // Finish the result being assembled before taking the next job, so
+        // callbacks still run in completion order
+        if let Some(mut assembly) = ASSEMBLING.with(|assembling| assembling.borrow_mut().take()) {
+            steps += 1;
+            if assembly.step(l, budget.chunk_size) {
+                lua_pushnil(l);
+                lua_rawgeti(l, LUA_REGISTRYINDEX, assembly.table);
+                luaL_unref(l, LUA_REGISTRYINDEX, assembly.table);
+                dispatch(l, &assembly.job, false);
+                calls += 1;
+                processed += 1;
+            } else {
+                ASSEMBLING.with(|assembling| *assembling.borrow_mut() = Some(assembly));
+            }
+            continue;
+        }
 
-                    // Push callback arguments based on result type
-                    if let Some(result) = job.result {
-                        push_job_result(l, result);
-                    } else {
-                        lua_pushnil(l); // error
-                        lua_pushstring(l, cstr!("No result"));
-                    }
+        // Only hold the queue lock while receiving, so workers are never
+        // blocked behind Lua callbacks
+        let next = match CALLBACK_QUEUE.lock() {
+            Ok(guard) => guard.1.try_recv(),
+            Err(_) => {
+                error!("Failed to lock callback queue");
+                break;
+            }
+        };
 
-                    // Call the callback
-                    if lua_pcall(l, 2, 0, 0) != 0 {
-                        error!("Error calling callback: {}",
-                            std::ffi::CStr::from_ptr(lua_tostring(l, -1))
-                                .to_string_lossy());
-                        lua_pop(l, 1);
-                    }
+        match next {
+            Ok(mut job) => {
+                if let Some(documents) = large_document_list(&mut job, budget.chunk_size) {
+                    lua_createtable(l, documents.len().min(i32::MAX as usize) as i32, 0);
+                    let table = luaL_ref(l, LUA_REGISTRYINDEX);
+                    let assembly = Assembly {
+                        job,
+                        documents: documents.into_iter(),
+                        table,
+                        next: 1,
+                    };
+                    ASSEMBLING.with(|assembling| *assembling.borrow_mut() = Some(assembly));
+                    continue;
+                }
 
-                    // Unreference the callback
-                    luaL_unref(l, LUA_REGISTRYINDEX, callback_ref);
+                let intermediate = job.result.as_ref().is_some_and(|r| r.is_intermediate());
+                if !intermediate {
+                    processed += 1;
                 }
+                deliver(l, job, intermediate);
+                steps += 1;
+                calls += 1;
             }
             Err(std::sync::mpsc::TryRecvError::Empty) => break,
             Err(std::sync::mpsc::TryRecvError::Disconnected) => {
                 error!("Callback queue disconnected");
                 deafen(l);
-                return 0;
+                break;
             }
         }
     }
 
     if processed > 0 {
         decrease_callbacks_pending(processed);
+    }
+    // Cancelled jobs lower the count without passing through the queue
+    if get_callbacks_pending() == 0 && is_hook_registered() {
+        deafen(l);
+    }
 
-        if get_callbacks_pending() == 0 {
-            deafen(l);
+    DELIVERING.with(|delivering| delivering.set(false));
+}
+
+/// Takes the documents of a successful list result that is too large to
+/// convert in one step
+fn large_document_list(job: &mut Job, chunk_size: usize) -> Option<Vec<Document>> {
+    let documents = match job.result.as_mut()? {
+        JobResult::Find(Ok(documents)) | JobResult::Aggregate(Ok(documents)) | JobResult::CursorNext(Ok(documents)) |
+        JobResult::ListIndexes(Ok(documents)) => documents,
+        #[cfg(feature = "gridfs")]
+        JobResult::GridFsFind(Ok(documents)) => documents,
+        _ => return None,
+    };
+    if documents.len() <= chunk_size {
+        return None;
+    }
+
+    let documents = std::mem::take(documents);
+    job.result = None;
+    Some(documents)
+}
+
+/// Calls the callbacks of a finished job with its result
+unsafe fn deliver(l: LuaState, mut job: Job, intermediate: bool) {
+    let Some(result) = job.result.take() else {
+        return;
+    };
+
+    // A change stream that was stopped has nothing left to report
+    #[cfg(feature = "change-streams")]
+    if let JobResult::ChangeStreamClosed(Ok(())) = result {
+        let refs = std::mem::take(&mut job.handle.callbacks().refs);
+        for callback in refs {
+            luaL_unref(l, LUA_REGISTRYINDEX, callback);
         }
+        return;
     }
 
-    0
+    let _int64 = Int64ModeGuard::new(job.settings.int64);
+    push_job_result(l, result);
+    dispatch(l, &job, intermediate);
+}
+
+/// Calls the callbacks of `job` with the error and result on top of the
+/// stack, then pops them
+unsafe fn dispatch(l: LuaState, job: &Job, intermediate: bool) {
+    if intermediate {
+        let refs = job.handle.callbacks().refs.clone();
+        call_with_results(l, &refs, false);
+        lua_pop(l, 2);
+        return;
+    }
+
+    // Keep the results for `Then` calls made later, including from inside
+    // the callbacks below
+    let (refs, keep) = {
+        let mut callbacks = job.handle.callbacks();
+        callbacks.delivered = true;
+        (std::mem::take(&mut callbacks.refs), !callbacks.released)
+    };
+    if keep {
+        lua_createtable(l, 2, 0);
+        lua_pushvalue(l, -3);
+        lua_rawseti(l, -2, 1);
+        lua_pushvalue(l, -2);
+        lua_rawseti(l, -2, 2);
+        job.handle.callbacks().results = Some(luaL_ref(l, LUA_REGISTRYINDEX));
+    }
+
+    call_with_results(l, &refs, true);
+    lua_pop(l, 2);
+}
+
+/// Calls each callback with the error and result on top of the stack,
+/// leaving them in place; `release` drops the references afterwards.
+/// Coroutines suspended in `MongoDB.Await` are resumed with them instead.
+pub(crate) unsafe fn call_with_results(l: LuaState, refs: &[LuaReference], release: bool) {
+    for &callback in refs {
+        lua_rawgeti(l, LUA_REGISTRYINDEX, callback);
+        if lua_isthread(l, -1) {
+            resume_awaiting(l);
+        } else if lua_isfunction(l, -1) {
+            lua_pushvalue(l, -3);
+            lua_pushvalue(l, -3);
+            if lua_pcall(l, 2, 0, 0) != 0 {
+                error!("Error calling callback: {}",
+                    std::ffi::CStr::from_ptr(lua_tostring(l, -1))
+                        .to_string_lossy());
+                lua_pop(l, 1);
+            }
+        } else {
+            lua_pop(l, 1);
+        }
+
+        if release {
+            luaL_unref(l, LUA_REGISTRYINDEX, callback);
+        }
+    }
+}
+
+/// Resumes the coroutine on top of the stack with the error and result
+/// below it, then pops the coroutine
+unsafe fn resume_awaiting(l: LuaState) {
+    let thread = lua_tothread(l, -1);
+    if lua_status(thread) != lua::YIELD {
+        lua_pop(l, 1);
+        return;
+    }
+
+    lua_pushvalue(l, -3);
+    lua_pushvalue(l, -3);
+    lua_xmove(l, thread, 2);
+    let status = lua_resume(thread, 2);
+    if status != lua::OK && status != lua::YIELD {
+        error!("Error resuming coroutine: {}",
+            std::ffi::CStr::from_ptr(lua_tostring(thread, -1))
+                .to_string_lossy());
+    }
+    lua_settop(thread, 0);
+    lua_pop(l, 1);
 }
 
 unsafe fn push_job_result(l: LuaState, result: JobResult) {
@@ -77,10 +308,10 @@ unsafe fn push_job_result(l: LuaState, result: JobResult) {
             match res {
                 Ok(id) => {
                     lua_pushnil(l); // no error
-                    lua_pushstring(l, std::ffi::CString::new(id).unwrap().as_ptr());
+                    bson_value_to_lua(l, &id);
                 }
                 Err(e) => {
-                    lua_pushstring(l, std::ffi::CString::new(e).unwrap().as_ptr());
+                    push_error_object(l, &e);
                     lua_pushnil(l);
                 }
             }
@@ -91,12 +322,12 @@ unsafe fn push_job_result(l: LuaState, result: JobResult) {
                     lua_pushnil(l);
                     lua_newtable(l);
                     for (i, id) in ids.iter().enumerate() {
-                        lua_pushstring(l, std::ffi::CString::new(id.as_str()).unwrap().as_ptr());
+                        bson_value_to_lua(l, id);
                         lua_rawseti(l, -2, (i + 1) as i32);
                     }
                 }
                 Err(e) => {
-                    lua_pushstring(l, std::ffi::CString::new(e).unwrap().as_ptr());
+                    push_error_object(l, &e);
                     lua_pushnil(l);
                 }
             }
@@ -112,7 +343,7 @@ unsafe fn push_job_result(l: LuaState, result: JobResult) {
                     }
                 }
                 Err(e) => {
-                    lua_pushstring(l, std::ffi::CString::new(e).unwrap().as_ptr());
+                    push_error_object(l, &e);
                     lua_pushnil(l);
                 }
             }
@@ -128,13 +359,36 @@ unsafe fn push_job_result(l: LuaState, result: JobResult) {
                     lua_pushnil(l);
                 }
                 Err(e) => {
-                    lua_pushstring(l, std::ffi::CString::new(e).unwrap().as_ptr());
+                    push_error_object(l, &e);
+                    lua_pushnil(l);
+                }
+            }
+        }
+        JobResult::UpdateOne(res) | JobResult::UpdateMany(res) | JobResult::ReplaceOne(res) |
+        JobResult::DeleteOne(res) | JobResult::DeleteMany(res) => {
+            match res {
+                Ok(result) => {
+                    lua_pushnil(l);
+                    push_write_result(l, &result);
+                }
+                Err(e) => {
+                    push_error_object(l, &e);
+                    lua_pushnil(l);
+                }
+            }
+        }
+        JobResult::BulkWrite(res) => {
+            match res {
+                Ok(summary) => {
+                    lua_pushnil(l);
+                    push_bulk_write_result(l, &summary);
+                }
+                Err(e) => {
+                    push_error_object(l, &e);
                     lua_pushnil(l);
                 }
             }
         }
-        JobResult::UpdateOne(res) | JobResult::UpdateMany(res) |
-        JobResult::DeleteOne(res) | JobResult::DeleteMany(res) |
         JobResult::CountDocuments(res) => {
             match res {
                 Ok(count) => {
@@ -142,13 +396,24 @@ unsafe fn push_job_result(l: LuaState, result: JobResult) {
                     lua_pushnumber(l, count as f64);
                 }
                 Err(e) => {
-                    let cstr = std::ffi::CString::new(e).unwrap();
-                    lua_pushstring(l, cstr.as_ptr());
+                    push_error_object(l, &e);
                     lua_pushnil(l);
                 }
             }
         }
-        JobResult::Aggregate(res) => {
+        JobResult::Distinct(res) => {
+            match res {
+                Ok(values) => {
+                    lua_pushnil(l);
+                    push_values(l, &values);
+                }
+                Err(e) => {
+                    push_error_object(l, &e);
+                    lua_pushnil(l);
+                }
+            }
+        }
+        JobResult::Aggregate(res) | JobResult::CursorNext(res) | JobResult::ListIndexes(res) => {
             match res {
                 Ok(documents) => {
                     lua_pushnil(l);
@@ -159,12 +424,154 @@ unsafe fn push_job_result(l: LuaState, result: JobResult) {
                     }
                 }
                 Err(e) => {
-                    let cstr = std::ffi::CString::new(e).unwrap();
-                    lua_pushstring(l, cstr.as_ptr());
+                    push_error_object(l, &e);
+                    lua_pushnil(l);
+                }
+            }
+        }
+        JobResult::CreateIndexes(res) | JobResult::ListDatabases(res) | JobResult::ListCollections(res) => {
+            match res {
+                Ok(names) => {
+                    lua_pushnil(l);
+                    lua_newtable(l);
+                    for (i, name) in names.iter().enumerate() {
+                        push_bytes(l, name.as_bytes());
+                        lua_rawseti(l, -2, (i + 1) as i32);
+                    }
+                }
+                Err(e) => {
+                    push_error_object(l, &e);
+                    lua_pushnil(l);
+                }
+            }
+        }
+        JobResult::CreateIndex(res) => {
+            match res {
+                Ok(name) => {
+                    lua_pushnil(l);
+                    push_bytes(l, name.as_bytes());
+                }
+                Err(e) => {
+                    push_error_object(l, &e);
+                    lua_pushnil(l);
+                }
+            }
+        }
+        JobResult::DropIndex(res) | JobResult::DropIndexes(res) | JobResult::RenameCollection(res) |
+        JobResult::CreateCollection(res) | JobResult::DropCollection(res) | JobResult::DropDatabase(res) => {
+            match res {
+                Ok(()) => {
+                    lua_pushnil(l);
+                    lua_pushboolean(l, 1);
+                }
+                Err(e) => {
+                    push_error_object(l, &e);
+                    lua_pushnil(l);
+                }
+            }
+        }
+        JobResult::CollectionStats(res) => {
+            match res {
+                Ok(stats) => {
+                    lua_pushnil(l);
+                    bson_to_lua_table(l, &stats);
+                }
+                Err(e) => {
+                    push_error_object(l, &e);
+                    lua_pushnil(l);
+                }
+            }
+        }
+        JobResult::CollectionExists(res) => {
+            match res {
+                Ok(exists) => {
+                    lua_pushnil(l);
+                    lua_pushboolean(l, exists as i32);
+                }
+                Err(e) => {
+                    push_error_object(l, &e);
+                    lua_pushnil(l);
+                }
+            }
+        }
+        #[cfg(feature = "gridfs")]
+        JobResult::GridFsUpload(res) => {
+            match res {
+                Ok(id) => {
+                    lua_pushnil(l);
+                    bson_value_to_lua(l, &id);
+                }
+                Err(e) => {
+                    push_error_object(l, &e);
+                    lua_pushnil(l);
+                }
+            }
+        }
+        #[cfg(feature = "gridfs")]
+        JobResult::GridFsDownload(res) => {
+            match res {
+                Ok(data) => {
+                    lua_pushnil(l);
+                    push_bytes(l, &data);
+                }
+                Err(e) => {
+                    push_error_object(l, &e);
+                    lua_pushnil(l);
+                }
+            }
+        }
+        #[cfg(feature = "gridfs")]
+        JobResult::GridFsDelete(res) => {
+            match res {
+                Ok(()) => {
+                    lua_pushnil(l);
+                    lua_pushboolean(l, 1);
+                }
+                Err(e) => {
+                    push_error_object(l, &e);
+                    lua_pushnil(l);
+                }
+            }
+        }
+        #[cfg(feature = "gridfs")]
+        JobResult::GridFsFind(res) => {
+            match res {
+                Ok(files) => {
+                    lua_pushnil(l);
+                    lua_newtable(l);
+                    for (i, file) in files.iter().enumerate() {
+                        bson_to_lua_table(l, file);
+                        lua_rawseti(l, -2, (i + 1) as i32);
+                    }
+                }
+                Err(e) => {
+                    push_error_object(l, &e);
+                    lua_pushnil(l);
+                }
+            }
+        }
+        #[cfg(feature = "change-streams")]
+        JobResult::ChangeEvent(event) => {
+            lua_pushnil(l);
+            bson_to_lua_table(l, &event);
+        }
+        #[cfg(feature = "change-streams")]
+        JobResult::ChangeStreamClosed(res) => {
+            match res {
+                Ok(()) => {
+                    lua_pushnil(l);
+                    lua_pushnil(l);
+                }
+                Err(e) => {
+                    push_error_object(l, &e);
                     lua_pushnil(l);
                 }
             }
         }
+        JobResult::Failed(e) => {
+            push_error_object(l, &e);
+            lua_pushnil(l);
+        }
     }
 }
 
@@ -190,4 +597,20 @@ fn deafen(l: LuaState) {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    #[test]
+    fn test_dispatch_budget() {
+        let budget = DispatchBudget {
+            time: Some(Duration::from_millis(5)),
+            callbacks: Some(10),
+            chunk_size: 100,
+        };
+        assert!(!budget.exhausted(Duration::from_millis(4), 9));
+        assert!(budget.exhausted(Duration::from_millis(5), 0));
+        assert!(budget.exhausted(Duration::ZERO, 10));
+        assert!(!DispatchBudget::UNLIMITED.exhausted(Duration::from_secs(60), usize::MAX));
+    }
+}
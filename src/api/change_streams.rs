@@ -0,0 +1,193 @@
+use crate::api::collection_async::maybe_register_hook;
+use crate::core::job::JobHandle;
+use crate::core::worker::{submit_job, Job, Operation, LUA_REGISTRYINDEX};
+use crate::error::{LuaError, LuaResult};
+use crate::operations::change_streams::{change_stream_options, WatchControl, WatchTarget};
+use crate::types::{bson_to_lua_table, lua_table_to_bson};
+use crate::utils::{gc_userdata, is_udata, push_error, push_failure, read_settings, read_userdata, write_userdata};
+use mongodb::bson::{Bson, Document};
+use mongodb::options::ChangeStreamOptions;
+use mongodb::{Client, Collection, Database};
+use rglua::lua::LuaState;
+use rglua::prelude::*;
+
+unsafe fn check_pipeline(l: LuaState, index: i32) -> LuaResult<Vec<Document>> {
+    let mut pipeline = Vec::new();
+    if lua_isnoneornil(l, index) {
+        return Ok(pipeline);
+    }
+    if !lua_istable(l, index) {
+        return Err(LuaError::InvalidArgument {
+            position: index as usize,
+            message: "Expected table for pipeline".to_string(),
+        });
+    }
+
+    let mut stage_index = 1;
+    loop {
+        lua_rawgeti(l, index, stage_index);
+        if lua_isnil(l, -1) {
+            lua_pop(l, 1);
+            break;
+        }
+
+        let stage = lua_table_to_bson(l, -1);
+        lua_pop(l, 1);
+        pipeline.push(stage.map_err(|e| LuaError::InvalidArgument {
+            position: index as usize,
+            message: format!("Invalid pipeline stage {}: {}", stage_index, e),
+        })?);
+
+        stage_index += 1;
+    }
+
+    Ok(pipeline)
+}
+
+unsafe fn opt_watch_options(l: LuaState, index: i32) -> LuaResult<ChangeStreamOptions> {
+    if !lua_istable(l, index) {
+        return Ok(ChangeStreamOptions::default());
+    }
+
+    let doc = lua_table_to_bson(l, index).map_err(|e| LuaError::InvalidArgument {
+        position: index as usize,
+        message: e.to_string(),
+    })?;
+
+    change_stream_options(&doc).map_err(|message| LuaError::InvalidArgument { position: index as usize, message })
+}
+
+unsafe fn check_watch_control(l: LuaState, index: i32) -> LuaResult<WatchControl> {
+    if !is_udata(l, index, cstr!("MongoDBChangeStream")) {
+        return Err(LuaError::InvalidUserdata("Expected MongoDBChangeStream".to_string()));
+    }
+    read_userdata(l, index)
+}
+
+/// Shared body of `Watch` on clients, databases and collections: `(pipeline, options, callback)`
+unsafe fn watch(l: LuaState, target: WatchTarget) -> i32 {
+    let pipeline = match check_pipeline(l, 2) {
+        Ok(pipeline) => pipeline,
+        Err(e) => return push_error(l, e),
+    };
+
+    let options = match opt_watch_options(l, 3) {
+        Ok(options) => options,
+        Err(e) => return push_error(l, e),
+    };
+
+    if !lua_isfunction(l, 4) {
+        return push_error(l, LuaError::InvalidArgument {
+            position: 4,
+            message: "Expected callback function".to_string(),
+        });
+    }
+
+    maybe_register_hook(l);
+    lua_pushvalue(l, 4);
+    let callback = luaL_ref(l, LUA_REGISTRYINDEX);
+
+    let settings = read_settings(l, 1);
+    let control = WatchControl::default();
+    let job = Job {
+        operation: Operation::Watch {
+            target,
+            pipeline,
+            options: Box::new(options),
+            control: control.clone(),
+        },
+        handle: JobHandle::new(Some(callback)),
+        session: None,
+        settings: settings.clone(),
+        result: None,
+    };
+
+    if let Err(e) = submit_job(job) {
+        luaL_unref(l, LUA_REGISTRYINDEX, callback);
+        return push_failure(l, &settings, "Failed to start change stream", e.to_string());
+    }
+
+    write_userdata(l, control);
+    luaL_getmetatable(l, cstr!("MongoDBChangeStream"));
+    lua_setmetatable(l, -2);
+
+    1
+}
+
+#[lua_function]
+pub unsafe fn client_watch(l: LuaState) -> i32 {
+    let client: Client = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    watch(l, WatchTarget::Client(client))
+}
+
+#[lua_function]
+pub unsafe fn database_watch(l: LuaState) -> i32 {
+    let database: Database = match read_userdata(l, 1) {
+        Ok(db) => db,
+        Err(e) => return push_error(l, e),
+    };
+
+    watch(l, WatchTarget::Database(database))
+}
+
+#[lua_function]
+pub unsafe fn collection_watch(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    watch(l, WatchTarget::Collection(collection))
+}
+
+/// `__gc` of change stream handles: once the handle is gone nothing can stop
+/// the stream any more, so it is stopped here
+pub extern "C" fn gc_change_stream(l: LuaState) -> i32 {
+    let ptr = lua_touserdata(l, 1) as *const WatchControl;
+    if !ptr.is_null() {
+        unsafe { (*ptr).stop() };
+    }
+    gc_userdata::<WatchControl>(l)
+}
+
+#[lua_function]
+pub unsafe fn change_stream_stop(l: LuaState) -> i32 {
+    let control = match check_watch_control(l, 1) {
+        Ok(control) => control,
+        Err(e) => return push_error(l, e),
+    };
+
+    control.stop();
+    0
+}
+
+#[lua_function]
+pub unsafe fn change_stream_is_active(l: LuaState) -> i32 {
+    let control = match check_watch_control(l, 1) {
+        Ok(control) => control,
+        Err(e) => return push_error(l, e),
+    };
+
+    lua_pushboolean(l, control.is_active() as i32);
+    1
+}
+
+/// Returns the token of the last delivered event, to be passed back as `resume_after`
+#[lua_function]
+pub unsafe fn change_stream_resume_token(l: LuaState) -> i32 {
+    let control = match check_watch_control(l, 1) {
+        Ok(control) => control,
+        Err(e) => return push_error(l, e),
+    };
+
+    match control.resume_token().map(|token| mongodb::bson::to_bson(&token)) {
+        Some(Ok(Bson::Document(doc))) => bson_to_lua_table(l, &doc),
+        _ => lua_pushnil(l),
+    }
+
+    1
+}
@@ -1,8 +1,12 @@
-use crate::config::ConnectionConfig;
+use crate::api::collection_async::submit_operation;
+use crate::config::{ClientSettings, ConnectionConfig};
 use crate::core::connection::MongoConnection;
+use crate::core::queue::{Limiter, DEFAULT_MAX_CONCURRENT_JOBS};
+use crate::core::runtime::{block_on_timed, CallTimeoutGuard};
+use crate::core::worker::Operation;
+use crate::error::MongoError;
 use crate::log_info;
-use crate::utils::{check_string, push_error, write_userdata};
-use log::error;
+use crate::utils::{attach_settings, call_settings, check_string, push_error, push_failure, push_invalid, read_settings, read_settings_table, read_userdata, write_userdata};
 use mongodb::Client;
 use rglua::lua::LuaState;
 use rglua::prelude::*;
@@ -15,28 +19,19 @@ pub extern "C" fn new_client(l: LuaState) -> i32 {
             Err(e) => return push_error(l, e),
         };
 
+        let settings = ClientSettings::default();
         let config = match ConnectionConfig::new(&connection_string) {
             Ok(cfg) => cfg,
-            Err(e) => {
-                error!("Invalid connection string: {}", e);
-                lua_pushnil(l);
-                return 1;
-            }
+            Err(e) => return push_invalid(l, &settings, "Invalid connection string", e),
         };
 
         let connection = match MongoConnection::new(config) {
             Ok(conn) => conn,
-            Err(e) => {
-                error!("Failed to create connection: {}", e);
-                lua_pushnil(l);
-                return 1;
-            }
+            Err(e) => return push_failure(l, &settings, "Failed to create connection", e),
         };
 
         if let Err(e) = connection.test_connection() {
-            error!("Connection test failed: {}", e);
-            lua_pushnil(l);
-            return 1;
+            return push_failure(l, &settings, "Connection test failed", e);
         }
 
         log_info!("Successfully connected to MongoDB");
@@ -44,6 +39,10 @@ pub extern "C" fn new_client(l: LuaState) -> i32 {
         write_userdata(l, connection.client().clone());
         luaL_getmetatable(l, cstr!("MongoDBClient"));
         lua_setmetatable(l, -2);
+        attach_settings(l, &ClientSettings {
+            limiter: Some(Limiter::new(DEFAULT_MAX_CONCURRENT_JOBS)),
+            ..settings
+        }, None);
 
         1
     }
@@ -56,15 +55,20 @@ pub unsafe fn new_client_with_options(l: LuaState) -> i32 {
         Err(e) => return push_error(l, e),
     };
 
+    let mut settings = ClientSettings::default();
+    if lua_istable(l, 2) {
+        if let Err(e) = read_settings_table(l, 2, &mut settings) {
+            return push_error(l, e);
+        }
+    }
+
     let mut config = match ConnectionConfig::new(&connection_string) {
         Ok(cfg) => cfg,
-        Err(e) => {
-            error!("Invalid connection string: {}", e);
-            lua_pushnil(l);
-            return 1;
-        }
+        Err(e) => return push_invalid(l, &settings, "Invalid connection string", e),
     };
 
+    let mut max_concurrent_jobs = DEFAULT_MAX_CONCURRENT_JOBS;
+
     if lua_istable(l, 2) {
         lua_pushstring(l, cstr!("app_name"));
         lua_gettable(l, 2);
@@ -90,50 +94,52 @@ pub unsafe fn new_client_with_options(l: LuaState) -> i32 {
             config = config.with_retry_writes(enabled);
         }
         lua_pop(l, 1);
+
+        lua_pushstring(l, cstr!("max_concurrent_jobs"));
+        lua_gettable(l, 2);
+        if lua_isnumber(l, -1) != 0 {
+            max_concurrent_jobs = lua_tonumber(l, -1).max(1.0) as usize;
+        }
+        lua_pop(l, 1);
     }
 
     let connection = match MongoConnection::new(config) {
         Ok(conn) => conn,
-        Err(e) => {
-            error!("Failed to create connection: {}", e);
-            lua_pushnil(l);
-            return 1;
-        }
+        Err(e) => return push_failure(l, &settings, "Failed to create connection", e),
     };
 
     if let Err(e) = connection.test_connection() {
-        error!("Connection test failed: {}", e);
-        lua_pushnil(l);
-        return 1;
+        return push_failure(l, &settings, "Connection test failed", e);
     }
 
     log_info!("Successfully connected to MongoDB with custom options");
 
+    settings.limiter = Some(Limiter::new(max_concurrent_jobs));
     write_userdata(l, connection.client().clone());
     luaL_getmetatable(l, cstr!("MongoDBClient"));
     lua_setmetatable(l, -2);
+    attach_settings(l, &settings, None);
 
     1
 }
 
 #[lua_function]
 pub unsafe fn list_databases(l: LuaState) -> i32 {
-    use crate::utils::read_userdata;
-
     let client: Client = match read_userdata(l, 1) {
         Ok(c) => c,
         Err(e) => return push_error(l, e),
     };
 
-    let databases = match crate::core::runtime::block_on(async move {
-        client.list_database_names().await
+    let settings = match call_settings(l, 1, 2) {
+        Ok(settings) => settings,
+        Err(e) => return push_error(l, e),
+    };
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    let databases = match block_on_timed(async move {
+        client.list_database_names().await.map_err(|e| MongoError::driver("List databases failed", e))
     }) {
         Ok(dbs) => dbs,
-        Err(e) => {
-            error!("Failed to list databases: {}", e);
-            lua_pushnil(l);
-            return 1;
-        }
+        Err(e) => return push_failure(l, &settings, "Failed to list databases", e),
     };
 
     lua_newtable(l);
@@ -147,6 +153,17 @@ pub unsafe fn list_databases(l: LuaState) -> i32 {
     1
 }
 
+/// Async version of list_databases with callback
+#[lua_function]
+pub unsafe fn list_databases_async(l: LuaState) -> i32 {
+    let client: Client = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    submit_operation(l, Operation::ListDatabases { client }, read_settings(l, 1), 2)
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -1,9 +1,15 @@
+use crate::config::ClientSettings;
+use crate::core::runtime::CallTimeoutGuard;
+use crate::error::MongoResult;
 use crate::log_info;
-use crate::operations;
-use crate::types::{bson_to_lua_table, lua_table_to_bson};
-use crate::utils::{check_string, opt_boolean, push_error, read_userdata, write_userdata};
-use log::error;
-use mongodb::bson::Document;
+use crate::operations::{self, BulkWriteSummary, WriteResult};
+use crate::types::{bson_to_lua_table, bson_value_to_lua, lua_table_to_bson, Int64ModeGuard};
+use crate::utils::{
+    call_options, call_settings, check_string, inherit_settings, opt_boolean, opt_string, push_bytes, push_error, push_failure, push_invalid, read_settings,
+    read_userdata, write_userdata,
+};
+use mongodb::bson::{Bson, Document};
+use mongodb::options::{BulkWriteOptions, FindOneOptions, FindOptions, WriteModel};
 use mongodb::{Collection, Database};
 use rglua::lua::LuaState;
 use rglua::prelude::*;
@@ -26,6 +32,7 @@ pub extern "C" fn get_collection(l: LuaState) -> i32 {
         write_userdata(l, collection);
         luaL_getmetatable(l, cstr!("MongoDBCollection"));
         lua_setmetatable(l, -2);
+        inherit_settings(l, 1);
 
         1
     }
@@ -40,25 +47,21 @@ pub extern "C" fn insert_one(l: LuaState) -> i32 {
             Err(e) => return push_error(l, e),
         };
 
+        let (settings, session) = match call_options(l, read_settings(l, 1), 3) {
+            Ok(options) => options,
+            Err(e) => return push_error(l, e),
+        };
+
         let document = match lua_table_to_bson(l, 2) {
             Ok(doc) => doc,
-            Err(e) => {
-                error!("Failed to convert document: {}", e);
-                lua_pushnil(l);
-                return 1;
-            }
+            Err(e) => return push_invalid(l, &settings, "Failed to convert document", e),
         };
 
-        match operations::insert_one(collection.clone(), document) {
-            Ok(id) => {
-                use std::ffi::CString;
-                let cstr = CString::new(id).unwrap();
-                lua_pushstring(l, cstr.as_ptr());
-            }
-            Err(e) => {
-                error!("Failed to insert document: {}", e);
-                lua_pushnil(l);
-            }
+        let _int64 = Int64ModeGuard::new(settings.int64);
+        let _timeout = CallTimeoutGuard::new(settings.timeout);
+        match operations::insert_one(collection.clone(), document, session) {
+            Ok(id) => bson_value_to_lua(l, &id),
+            Err(e) => return push_failure(l, &settings, "Failed to insert document", e),
         }
 
         1
@@ -73,10 +76,13 @@ pub extern "C" fn insert_many(l: LuaState) -> i32 {
             Err(e) => return push_error(l, e),
         };
 
+        let (settings, session) = match call_options(l, read_settings(l, 1), 3) {
+            Ok(options) => options,
+            Err(e) => return push_error(l, e),
+        };
+
         if !lua_istable(l, 2) {
-            error!("Expected table of documents");
-            lua_pushnil(l);
-            return 1;
+            return push_invalid(l, &settings, "Failed to convert documents", "expected a table");
         }
 
         let mut documents = Vec::new();
@@ -92,10 +98,8 @@ pub extern "C" fn insert_many(l: LuaState) -> i32 {
             match lua_table_to_bson(l, -1) {
                 Ok(doc) => documents.push(doc),
                 Err(e) => {
-                    error!("Failed to convert document at index {}: {}", index, e);
                     lua_pop(l, 1);
-                    lua_pushnil(l);
-                    return 1;
+                    return push_invalid(l, &settings, &format!("Failed to convert document at index {}", index), e);
                 }
             }
 
@@ -103,26 +107,44 @@ pub extern "C" fn insert_many(l: LuaState) -> i32 {
             index += 1;
         }
 
-        match operations::insert_many(collection.clone(), documents) {
+        let _int64 = Int64ModeGuard::new(settings.int64);
+        let _timeout = CallTimeoutGuard::new(settings.timeout);
+        match operations::insert_many(collection.clone(), documents, session) {
             Ok(ids) => {
                 lua_newtable(l);
                 for (i, id) in ids.iter().enumerate() {
-                    use std::ffi::CString;
-                    let cstr = CString::new(id.as_str()).unwrap();
-                    lua_pushstring(l, cstr.as_ptr());
+                    bson_value_to_lua(l, id);
                     lua_rawseti(l, -2, (i + 1) as i32);
                 }
             }
-            Err(e) => {
-                error!("Failed to insert documents: {}", e);
-                lua_pushnil(l);
-            }
+            Err(e) => return push_failure(l, &settings, "Failed to insert documents", e),
         }
 
         1
     }
 }
 
+/// Reads the find options at `index`: an options table, or a plain number as the limit
+pub(crate) unsafe fn opt_find_options(l: LuaState, index: i32) -> Result<FindOptions, String> {
+    if lua_istable(l, index) {
+        let doc = lua_table_to_bson(l, index).map_err(|e| e.to_string())?;
+        operations::find_options(&doc)
+    } else if lua_isnumber(l, index) != 0 {
+        Ok(FindOptions::builder().limit(lua_tonumber(l, index) as i64).build())
+    } else {
+        Ok(FindOptions::default())
+    }
+}
+
+pub(crate) unsafe fn opt_find_one_options(l: LuaState, index: i32) -> Result<FindOneOptions, String> {
+    if lua_istable(l, index) {
+        let doc = lua_table_to_bson(l, index).map_err(|e| e.to_string())?;
+        operations::find_one_options(&doc)
+    } else {
+        Ok(FindOneOptions::default())
+    }
+}
+
 #[lua_function]
 pub extern "C" fn find(l: LuaState) -> i32 {
     unsafe {
@@ -131,22 +153,30 @@ pub extern "C" fn find(l: LuaState) -> i32 {
             Err(e) => return push_error(l, e),
         };
 
+        let settings = match call_settings(l, 1, 3) {
+            Ok(settings) => settings,
+            Err(e) => return push_error(l, e),
+        };
+        let (settings, session) = match call_options(l, settings, 4) {
+            Ok(options) => options,
+            Err(e) => return push_error(l, e),
+        };
+
         let filter = match lua_table_to_bson(l, 2) {
             Ok(doc) => doc,
-            Err(e) => {
-                error!("Failed to convert filter: {}", e);
-                lua_pushnil(l);
-                return 1;
-            }
+            Err(e) => return push_invalid(l, &settings, "Failed to convert filter", e),
         };
 
-        let limit = if lua_isnumber(l, 3) != 0 {
-            Some(lua_tonumber(l, 3) as i64)
-        } else {
-            None
+        let mut options = match opt_find_options(l, 3) {
+            Ok(options) => options,
+            Err(e) => return push_invalid(l, &settings, "Failed to convert find options", e),
         };
 
-        match operations::find(collection.clone(), filter, limit) {
+        let _int64 = Int64ModeGuard::new(settings.int64);
+        let _timeout = CallTimeoutGuard::new(settings.timeout);
+        options.max_time = options.max_time.or(settings.timeout);
+
+        match operations::find(collection.clone(), filter, options, session) {
             Ok(documents) => {
                 lua_newtable(l);
                 for (i, doc) in documents.iter().enumerate() {
@@ -154,10 +184,7 @@ pub extern "C" fn find(l: LuaState) -> i32 {
                     lua_rawseti(l, -2, (i + 1) as i32);
                 }
             }
-            Err(e) => {
-                error!("Failed to find documents: {}", e);
-                lua_pushnil(l);
-            }
+            Err(e) => return push_failure(l, &settings, "Failed to find documents", e),
         }
 
         1
@@ -171,31 +198,161 @@ pub unsafe fn find_one(l: LuaState) -> i32 {
         Err(e) => return push_error(l, e),
     };
 
+    let settings = match call_settings(l, 1, 3) {
+        Ok(settings) => settings,
+        Err(e) => return push_error(l, e),
+    };
+    let (settings, session) = match call_options(l, settings, 4) {
+        Ok(options) => options,
+        Err(e) => return push_error(l, e),
+    };
+
     let filter = match lua_table_to_bson(l, 2) {
         Ok(doc) => doc,
-        Err(e) => {
-            error!("Failed to convert filter: {}", e);
-            lua_pushnil(l);
-            return 1;
-        }
+        Err(e) => return push_invalid(l, &settings, "Failed to convert filter", e),
+    };
+
+    let mut options = match opt_find_one_options(l, 3) {
+        Ok(options) => options,
+        Err(e) => return push_invalid(l, &settings, "Failed to convert find options", e),
     };
 
-    match operations::find_one(collection.clone(), filter) {
+    let _int64 = Int64ModeGuard::new(settings.int64);
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    options.max_time = options.max_time.or(settings.timeout);
+
+    match operations::find_one(collection.clone(), filter, options, session) {
         Ok(Some(doc)) => {
             bson_to_lua_table(l, &doc);
         }
         Ok(None) => {
             lua_pushnil(l);
         }
-        Err(e) => {
-            error!("Failed to find document: {}", e);
-            lua_pushnil(l);
-        }
+        Err(e) => return push_failure(l, &settings, "Failed to find document", e),
     }
 
     1
 }
 
+/// Parses the optional option table at `index` with `parse`
+pub(crate) unsafe fn opt_options<T: Default>(l: LuaState, index: i32, parse: fn(&Document) -> Result<T, String>) -> Result<T, String> {
+    if lua_istable(l, index) {
+        let doc = lua_table_to_bson(l, index).map_err(|e| e.to_string())?;
+        parse(&doc)
+    } else {
+        Ok(T::default())
+    }
+}
+
+/// Pushes the document returned by a `findOneAnd*` command, or nil
+unsafe fn push_found_document(l: LuaState, settings: &ClientSettings, result: MongoResult<Option<Document>>) -> i32 {
+    match result {
+        Ok(Some(doc)) => bson_to_lua_table(l, &doc),
+        Ok(None) => lua_pushnil(l),
+        Err(e) => return push_failure(l, settings, "Failed to find and modify document", e),
+    }
+    1
+}
+
+#[lua_function]
+pub unsafe fn find_one_and_update(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    let settings = match call_settings(l, 1, 4) {
+        Ok(settings) => settings,
+        Err(e) => return push_error(l, e),
+    };
+    let (settings, session) = match call_options(l, settings, 5) {
+        Ok(options) => options,
+        Err(e) => return push_error(l, e),
+    };
+
+    let (filter, update) = match (lua_table_to_bson(l, 2), lua_table_to_bson(l, 3)) {
+        (Ok(filter), Ok(update)) => (filter, update),
+        (Err(e), _) | (_, Err(e)) => return push_invalid(l, &settings, "Failed to convert filter or update", e),
+    };
+
+    let mut options = match opt_options(l, 4, operations::find_one_and_update_options) {
+        Ok(options) => options,
+        Err(e) => return push_invalid(l, &settings, "Failed to convert find one and update options", e),
+    };
+
+    let _int64 = Int64ModeGuard::new(settings.int64);
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    options.max_time = options.max_time.or(settings.timeout);
+
+    push_found_document(l, &settings, operations::find_one_and_update(collection, filter, update, options, session))
+}
+
+#[lua_function]
+pub unsafe fn find_one_and_replace(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    let settings = match call_settings(l, 1, 4) {
+        Ok(settings) => settings,
+        Err(e) => return push_error(l, e),
+    };
+    let (settings, session) = match call_options(l, settings, 5) {
+        Ok(options) => options,
+        Err(e) => return push_error(l, e),
+    };
+
+    let (filter, replacement) = match (lua_table_to_bson(l, 2), lua_table_to_bson(l, 3)) {
+        (Ok(filter), Ok(replacement)) => (filter, replacement),
+        (Err(e), _) | (_, Err(e)) => return push_invalid(l, &settings, "Failed to convert filter or replacement", e),
+    };
+
+    let mut options = match opt_options(l, 4, operations::find_one_and_replace_options) {
+        Ok(options) => options,
+        Err(e) => return push_invalid(l, &settings, "Failed to convert find one and replace options", e),
+    };
+
+    let _int64 = Int64ModeGuard::new(settings.int64);
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    options.max_time = options.max_time.or(settings.timeout);
+
+    push_found_document(l, &settings, operations::find_one_and_replace(collection, filter, replacement, options, session))
+}
+
+#[lua_function]
+pub unsafe fn find_one_and_delete(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    let settings = match call_settings(l, 1, 3) {
+        Ok(settings) => settings,
+        Err(e) => return push_error(l, e),
+    };
+    let (settings, session) = match call_options(l, settings, 4) {
+        Ok(options) => options,
+        Err(e) => return push_error(l, e),
+    };
+
+    let filter = match lua_table_to_bson(l, 2) {
+        Ok(doc) => doc,
+        Err(e) => return push_invalid(l, &settings, "Failed to convert filter", e),
+    };
+
+    let mut options = match opt_options(l, 3, operations::find_one_and_delete_options) {
+        Ok(options) => options,
+        Err(e) => return push_invalid(l, &settings, "Failed to convert find one and delete options", e),
+    };
+
+    let _int64 = Int64ModeGuard::new(settings.int64);
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    options.max_time = options.max_time.or(settings.timeout);
+
+    push_found_document(l, &settings, operations::find_one_and_delete(collection, filter, options, session))
+}
+
 #[lua_function]
 pub unsafe fn update_one(l: LuaState) -> i32 {
     let collection: Collection<Document> = match read_userdata(l, 1) {
@@ -203,34 +360,28 @@ pub unsafe fn update_one(l: LuaState) -> i32 {
         Err(e) => return push_error(l, e),
     };
 
+    let (settings, session) = match call_options(l, read_settings(l, 1), 5) {
+        Ok(options) => options,
+        Err(e) => return push_error(l, e),
+    };
+
     let filter = match lua_table_to_bson(l, 2) {
         Ok(doc) => doc,
-        Err(e) => {
-            error!("Failed to convert filter: {}", e);
-            lua_pushnumber(l, 0.0);
-            return 1;
-        }
+        Err(e) => return push_invalid(l, &settings, "Failed to convert filter", e),
     };
 
     let update = match lua_table_to_bson(l, 3) {
         Ok(doc) => doc,
-        Err(e) => {
-            error!("Failed to convert update: {}", e);
-            lua_pushnumber(l, 0.0);
-            return 1;
-        }
+        Err(e) => return push_invalid(l, &settings, "Failed to convert update", e),
     };
 
     let upsert = opt_boolean(l, 4, false);
 
-    match operations::update_one(collection.clone(), filter, update, upsert) {
-        Ok(count) => {
-            lua_pushnumber(l, count as f64);
-        }
-        Err(e) => {
-            error!("Failed to update document: {}", e);
-            lua_pushnumber(l, 0.0);
-        }
+    let _int64 = Int64ModeGuard::new(settings.int64);
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    match operations::update_one(collection.clone(), filter, update, upsert, session) {
+        Ok(result) => push_write_result(l, &result),
+        Err(e) => return push_failure(l, &settings, "Failed to update document", e),
     }
 
     1
@@ -243,34 +394,28 @@ pub unsafe fn update_many(l: LuaState) -> i32 {
         Err(e) => return push_error(l, e),
     };
 
+    let (settings, session) = match call_options(l, read_settings(l, 1), 5) {
+        Ok(options) => options,
+        Err(e) => return push_error(l, e),
+    };
+
     let filter = match lua_table_to_bson(l, 2) {
         Ok(doc) => doc,
-        Err(e) => {
-            error!("Failed to convert filter: {}", e);
-            lua_pushnumber(l, 0.0);
-            return 1;
-        }
+        Err(e) => return push_invalid(l, &settings, "Failed to convert filter", e),
     };
 
     let update = match lua_table_to_bson(l, 3) {
         Ok(doc) => doc,
-        Err(e) => {
-            error!("Failed to convert update: {}", e);
-            lua_pushnumber(l, 0.0);
-            return 1;
-        }
+        Err(e) => return push_invalid(l, &settings, "Failed to convert update", e),
     };
 
     let upsert = opt_boolean(l, 4, false);
 
-    match operations::update_many(collection.clone(), filter, update, upsert) {
-        Ok(count) => {
-            lua_pushnumber(l, count as f64);
-        }
-        Err(e) => {
-            error!("Failed to update documents: {}", e);
-            lua_pushnumber(l, 0.0);
-        }
+    let _int64 = Int64ModeGuard::new(settings.int64);
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    match operations::update_many(collection.clone(), filter, update, upsert, session) {
+        Ok(result) => push_write_result(l, &result),
+        Err(e) => return push_failure(l, &settings, "Failed to update documents", e),
     }
 
     1
@@ -283,23 +428,21 @@ pub unsafe fn delete_one(l: LuaState) -> i32 {
         Err(e) => return push_error(l, e),
     };
 
+    let (settings, session) = match call_options(l, read_settings(l, 1), 3) {
+        Ok(options) => options,
+        Err(e) => return push_error(l, e),
+    };
+
     let filter = match lua_table_to_bson(l, 2) {
         Ok(doc) => doc,
-        Err(e) => {
-            error!("Failed to convert filter: {}", e);
-            lua_pushnumber(l, 0.0);
-            return 1;
-        }
+        Err(e) => return push_invalid(l, &settings, "Failed to convert filter", e),
     };
 
-    match operations::delete_one(collection.clone(), filter) {
-        Ok(count) => {
-            lua_pushnumber(l, count as f64);
-        }
-        Err(e) => {
-            error!("Failed to delete document: {}", e);
-            lua_pushnumber(l, 0.0);
-        }
+    let _int64 = Int64ModeGuard::new(settings.int64);
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    match operations::delete_one(collection.clone(), filter, session) {
+        Ok(result) => push_write_result(l, &result),
+        Err(e) => return push_failure(l, &settings, "Failed to delete document", e),
     }
 
     1
@@ -312,23 +455,21 @@ pub unsafe fn delete_many(l: LuaState) -> i32 {
         Err(e) => return push_error(l, e),
     };
 
+    let (settings, session) = match call_options(l, read_settings(l, 1), 3) {
+        Ok(options) => options,
+        Err(e) => return push_error(l, e),
+    };
+
     let filter = match lua_table_to_bson(l, 2) {
         Ok(doc) => doc,
-        Err(e) => {
-            error!("Failed to convert filter: {}", e);
-            lua_pushnumber(l, 0.0);
-            return 1;
-        }
+        Err(e) => return push_invalid(l, &settings, "Failed to convert filter", e),
     };
 
-    match operations::delete_many(collection.clone(), filter) {
-        Ok(count) => {
-            lua_pushnumber(l, count as f64);
-        }
-        Err(e) => {
-            error!("Failed to delete documents: {}", e);
-            lua_pushnumber(l, 0.0);
-        }
+    let _int64 = Int64ModeGuard::new(settings.int64);
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    match operations::delete_many(collection.clone(), filter, session) {
+        Ok(result) => push_write_result(l, &result),
+        Err(e) => return push_failure(l, &settings, "Failed to delete documents", e),
     }
 
     1
@@ -341,23 +482,158 @@ pub unsafe fn count_documents(l: LuaState) -> i32 {
         Err(e) => return push_error(l, e),
     };
 
+    let (settings, session) = match call_options(l, read_settings(l, 1), 3) {
+        Ok(options) => options,
+        Err(e) => return push_error(l, e),
+    };
+
     let filter = match lua_table_to_bson(l, 2) {
         Ok(doc) => doc,
-        Err(e) => {
-            error!("Failed to convert filter: {}", e);
-            lua_pushnumber(l, 0.0);
-            return 1;
-        }
+        Err(e) => return push_invalid(l, &settings, "Failed to convert filter", e),
     };
 
-    match operations::count_documents(collection.clone(), filter) {
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    match operations::count_documents(collection.clone(), filter, session, settings.timeout) {
         Ok(count) => {
             lua_pushnumber(l, count as f64);
         }
-        Err(e) => {
-            error!("Failed to count documents: {}", e);
-            lua_pushnumber(l, 0.0);
-        }
+        Err(e) => return push_failure(l, &settings, "Failed to count documents", e),
+    }
+
+    1
+}
+
+/// Pushes `values` as an array, e.g. the result of `Distinct`
+pub(crate) unsafe fn push_values(l: LuaState, values: &[Bson]) {
+    lua_newtable(l);
+    for (i, value) in values.iter().enumerate() {
+        bson_value_to_lua(l, value);
+        lua_rawseti(l, -2, (i + 1) as i32);
+    }
+}
+
+/// Reads the optional filter at `index`; nil matches every document
+pub(crate) unsafe fn opt_filter(l: LuaState, index: i32) -> Result<Document, String> {
+    if lua_isnoneornil(l, index) {
+        Ok(Document::new())
+    } else {
+        lua_table_to_bson(l, index).map_err(|e| e.to_string())
+    }
+}
+
+#[lua_function]
+pub unsafe fn distinct(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    let (settings, session) = match call_options(l, read_settings(l, 1), 4) {
+        Ok(options) => options,
+        Err(e) => return push_error(l, e),
+    };
+
+    let field = match check_string(l, 2) {
+        Ok(s) => s,
+        Err(e) => return push_error(l, e),
+    };
+
+    let filter = match opt_filter(l, 3) {
+        Ok(doc) => doc,
+        Err(e) => return push_invalid(l, &settings, "Failed to convert filter", e),
+    };
+
+    let _int64 = Int64ModeGuard::new(settings.int64);
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    match operations::distinct(collection, field, filter, session, settings.timeout) {
+        Ok(values) => push_values(l, &values),
+        Err(e) => return push_failure(l, &settings, "Failed to get distinct values", e),
+    }
+
+    1
+}
+
+#[lua_function]
+pub unsafe fn estimated_count(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    let settings = match call_settings(l, 1, 2) {
+        Ok(settings) => settings,
+        Err(e) => return push_error(l, e),
+    };
+
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    match operations::estimated_document_count(collection, settings.timeout) {
+        Ok(count) => lua_pushnumber(l, count as f64),
+        Err(e) => return push_failure(l, &settings, "Failed to estimate document count", e),
+    }
+
+    1
+}
+
+/// Reads the array of bulk operations at `index` into driver write models
+pub(crate) unsafe fn check_bulk_models(l: LuaState, index: i32, collection: &Collection<Document>) -> Result<Vec<WriteModel>, String> {
+    if !lua_istable(l, index) {
+        return Err("Expected an array of operations".to_string());
+    }
+
+    let namespace = collection.namespace();
+    let len = lua_objlen(l, index);
+    let mut models = Vec::with_capacity(len as usize);
+
+    for i in 1..=len {
+        lua_rawgeti(l, index, i as i32);
+        let operation = lua_table_to_bson(l, -1);
+        lua_pop(l, 1);
+
+        let model = operation
+            .map_err(|e| e.to_string())
+            .and_then(|operation| operations::write_model(&namespace, &operation))
+            .map_err(|e| format!("Operation {}: {}", i, e))?;
+        models.push(model);
+    }
+
+    Ok(models)
+}
+
+pub(crate) unsafe fn opt_bulk_write_options(l: LuaState, index: i32) -> Result<BulkWriteOptions, String> {
+    if lua_istable(l, index) {
+        let doc = lua_table_to_bson(l, index).map_err(|e| e.to_string())?;
+        operations::bulk_write_options(&doc)
+    } else {
+        Ok(BulkWriteOptions::default())
+    }
+}
+
+#[lua_function]
+pub unsafe fn bulk_write(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    let (settings, session) = match call_options(l, read_settings(l, 1), 4) {
+        Ok(options) => options,
+        Err(e) => return push_error(l, e),
+    };
+
+    let models = match check_bulk_models(l, 2, &collection) {
+        Ok(models) => models,
+        Err(e) => return push_invalid(l, &settings, "Failed to convert bulk operations", e),
+    };
+
+    let options = match opt_bulk_write_options(l, 3) {
+        Ok(options) => options,
+        Err(e) => return push_invalid(l, &settings, "Failed to convert bulk write options", e),
+    };
+
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    match operations::bulk_write(collection, models, options, session) {
+        Ok(summary) => push_bulk_write_result(l, &summary),
+        Err(e) => return push_failure(l, &settings, "Failed to run bulk write", e),
     }
 
     1
@@ -370,11 +646,14 @@ pub unsafe fn aggregate(l: LuaState) -> i32 {
         Err(e) => return push_error(l, e),
     };
 
+    let (settings, session) = match call_options(l, read_settings(l, 1), 3) {
+        Ok(options) => options,
+        Err(e) => return push_error(l, e),
+    };
+
     // Convert pipeline array
     if !lua_istable(l, 2) {
-        error!("Expected table for pipeline");
-        lua_pushnil(l);
-        return 1;
+        return push_invalid(l, &settings, "Failed to convert pipeline", "expected a table");
     }
 
     let mut pipeline = Vec::new();
@@ -390,10 +669,8 @@ pub unsafe fn aggregate(l: LuaState) -> i32 {
         match lua_table_to_bson(l, -1) {
             Ok(stage) => pipeline.push(stage),
             Err(e) => {
-                error!("Failed to convert pipeline stage {}: {}", index, e);
                 lua_pop(l, 1);
-                lua_pushnil(l);
-                return 1;
+                return push_invalid(l, &settings, &format!("Failed to convert pipeline stage {}", index), e);
             }
         }
 
@@ -410,7 +687,9 @@ pub unsafe fn aggregate(l: LuaState) -> i32 {
         }
     }
 
-    match operations::aggregate(&collection, pipeline) {
+    let _int64 = Int64ModeGuard::new(settings.int64);
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    match operations::aggregate(&collection, pipeline, session, settings.timeout) {
         Ok(documents) => {
             lua_newtable(l);
             for (i, doc) in documents.iter().enumerate() {
@@ -418,10 +697,7 @@ pub unsafe fn aggregate(l: LuaState) -> i32 {
                 lua_rawseti(l, -2, (i + 1) as i32);
             }
         }
-        Err(e) => {
-            error!("Failed to aggregate: {}", e);
-            lua_pushnil(l);
-        }
+        Err(e) => return push_failure(l, &settings, "Failed to aggregate", e),
     }
 
     1
@@ -434,13 +710,14 @@ pub unsafe fn create_index(l: LuaState) -> i32 {
         Err(e) => return push_error(l, e),
     };
 
+    let settings = match call_settings(l, 1, 5) {
+        Ok(settings) => settings,
+        Err(e) => return push_error(l, e),
+    };
+
     let keys = match lua_table_to_bson(l, 2) {
         Ok(doc) => doc,
-        Err(e) => {
-            error!("Failed to convert index keys: {}", e);
-            lua_pushnil(l);
-            return 1;
-        }
+        Err(e) => return push_invalid(l, &settings, "Failed to convert index keys", e),
     };
 
     let unique = opt_boolean(l, 3, false);
@@ -450,16 +727,14 @@ pub unsafe fn create_index(l: LuaState) -> i32 {
         None
     };
 
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
     match operations::create_index(&collection, keys, unique, name) {
         Ok(index_name) => {
             use std::ffi::CString;
             let cstr = CString::new(index_name).unwrap();
             lua_pushstring(l, cstr.as_ptr());
         }
-        Err(e) => {
-            error!("Failed to create index: {}", e);
-            lua_pushnil(l);
-        }
+        Err(e) => return push_failure(l, &settings, "Failed to create index", e),
     }
 
     1
@@ -472,6 +747,13 @@ pub unsafe fn list_indexes(l: LuaState) -> i32 {
         Err(e) => return push_error(l, e),
     };
 
+    let settings = match call_settings(l, 1, 2) {
+        Ok(settings) => settings,
+        Err(e) => return push_error(l, e),
+    };
+
+    let _int64 = Int64ModeGuard::new(settings.int64);
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
     match operations::list_indexes(&collection) {
         Ok(indexes) => {
             lua_newtable(l);
@@ -480,10 +762,7 @@ pub unsafe fn list_indexes(l: LuaState) -> i32 {
                 lua_rawseti(l, -2, (i + 1) as i32);
             }
         }
-        Err(e) => {
-            error!("Failed to list indexes: {}", e);
-            lua_pushnil(l);
-        }
+        Err(e) => return push_failure(l, &settings, "Failed to list indexes", e),
     }
 
     1
@@ -496,25 +775,303 @@ pub unsafe fn drop_index(l: LuaState) -> i32 {
         Err(e) => return push_error(l, e),
     };
 
+    let settings = match call_settings(l, 1, 3) {
+        Ok(settings) => settings,
+        Err(e) => return push_error(l, e),
+    };
+
     let index_name = match check_string(l, 2) {
         Ok(s) => s,
         Err(e) => return push_error(l, e),
     };
 
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
     match operations::drop_index(&collection, &index_name) {
         Ok(_) => {
             log_info!("Dropped index: {}", index_name);
             lua_pushboolean(l, 1);
         }
-        Err(e) => {
-            error!("Failed to drop index: {}", e);
-            lua_pushboolean(l, 0);
+        Err(e) => return push_failure(l, &settings, "Failed to drop index", e),
+    }
+
+    1
+}
+
+#[lua_function]
+pub unsafe fn replace_one(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    let (settings, session) = match call_options(l, read_settings(l, 1), 5) {
+        Ok(options) => options,
+        Err(e) => return push_error(l, e),
+    };
+
+    let filter = match lua_table_to_bson(l, 2) {
+        Ok(doc) => doc,
+        Err(e) => return push_invalid(l, &settings, "Failed to convert filter", e),
+    };
+
+    let replacement = match lua_table_to_bson(l, 3) {
+        Ok(doc) => doc,
+        Err(e) => return push_invalid(l, &settings, "Failed to convert replacement", e),
+    };
+
+    let upsert = opt_boolean(l, 4, false);
+
+    let _int64 = Int64ModeGuard::new(settings.int64);
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    match operations::replace_one(collection.clone(), filter, replacement, upsert, session) {
+        Ok(result) => push_write_result(l, &result),
+        Err(e) => return push_failure(l, &settings, "Failed to replace document", e),
+    }
+
+    1
+}
+
+/// Reads the array of `{ keys, unique, name }` index tables at `index`
+pub(crate) unsafe fn check_index_specs(l: LuaState, index: i32) -> Result<Vec<(Document, bool, Option<String>)>, String> {
+    if !lua_istable(l, index) {
+        return Err("Expected an array of indexes".to_string());
+    }
+
+    let len = lua_objlen(l, index);
+    let mut indexes = Vec::with_capacity(len as usize);
+
+    for i in 1..=len {
+        lua_rawgeti(l, index, i as i32);
+        let spec = lua_table_to_bson(l, -1);
+        lua_pop(l, 1);
+
+        let spec = spec
+            .map_err(|e| e.to_string())
+            .and_then(|spec| operations::index_spec(&spec))
+            .map_err(|e| format!("Index {}: {}", i, e))?;
+        indexes.push(spec);
+    }
+
+    Ok(indexes)
+}
+
+/// Reads the array of field names at `index`
+pub(crate) unsafe fn check_string_array(l: LuaState, index: i32) -> Result<Vec<String>, String> {
+    if !lua_istable(l, index) {
+        return Err("Expected an array of strings".to_string());
+    }
+
+    let len = lua_objlen(l, index);
+    let mut strings = Vec::with_capacity(len as usize);
+
+    for i in 1..=len {
+        lua_rawgeti(l, index, i as i32);
+        let string = check_string(l, -1);
+        lua_pop(l, 1);
+        strings.push(string.map_err(|_| format!("Entry {} is not a string", i))?);
+    }
+
+    Ok(strings)
+}
+
+unsafe fn push_strings(l: LuaState, strings: &[String]) {
+    lua_newtable(l);
+    for (i, string) in strings.iter().enumerate() {
+        push_bytes(l, string.as_bytes());
+        lua_rawseti(l, -2, (i + 1) as i32);
+    }
+}
+
+#[lua_function]
+pub unsafe fn create_indexes(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    let settings = match call_settings(l, 1, 3) {
+        Ok(settings) => settings,
+        Err(e) => return push_error(l, e),
+    };
+
+    let indexes = match check_index_specs(l, 2) {
+        Ok(indexes) => indexes,
+        Err(e) => return push_invalid(l, &settings, "Failed to convert indexes", e),
+    };
+
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    match operations::create_indexes(&collection, indexes) {
+        Ok(names) => push_strings(l, &names),
+        Err(e) => return push_failure(l, &settings, "Failed to create indexes", e),
+    }
+
+    1
+}
+
+#[lua_function]
+pub unsafe fn create_text_index(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    let settings = match call_settings(l, 1, 4) {
+        Ok(settings) => settings,
+        Err(e) => return push_error(l, e),
+    };
+
+    let fields = match check_string_array(l, 2) {
+        Ok(fields) => fields,
+        Err(e) => return push_invalid(l, &settings, "Failed to convert text index fields", e),
+    };
+
+    let name = match opt_string(l, 3) {
+        Ok(name) => name,
+        Err(e) => return push_error(l, e),
+    };
+
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    match operations::create_text_index(&collection, fields, name) {
+        Ok(index_name) => push_bytes(l, index_name.as_bytes()),
+        Err(e) => return push_failure(l, &settings, "Failed to create text index", e),
+    }
+
+    1
+}
+
+#[lua_function]
+pub unsafe fn drop_indexes(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    let settings = match call_settings(l, 1, 2) {
+        Ok(settings) => settings,
+        Err(e) => return push_error(l, e),
+    };
+
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    match operations::drop_all_indexes(&collection) {
+        Ok(_) => {
+            log_info!("Dropped all indexes on: {}", collection.name());
+            lua_pushboolean(l, 1);
+        }
+        Err(e) => return push_failure(l, &settings, "Failed to drop indexes", e),
+    }
+
+    1
+}
+
+#[lua_function]
+pub unsafe fn count_aggregate(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    let settings = match call_settings(l, 1, 3) {
+        Ok(settings) => settings,
+        Err(e) => return push_error(l, e),
+    };
+
+    let filter = match lua_table_to_bson(l, 2) {
+        Ok(doc) => doc,
+        Err(e) => return push_invalid(l, &settings, "Failed to convert filter", e),
+    };
+
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    match operations::count_aggregate(&collection, filter, settings.timeout) {
+        Ok(count) => lua_pushnumber(l, count as f64),
+        Err(e) => return push_failure(l, &settings, "Failed to count documents", e),
+    }
+
+    1
+}
+
+#[lua_function]
+pub unsafe fn group_by(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    let settings = match call_settings(l, 1, 4) {
+        Ok(settings) => settings,
+        Err(e) => return push_error(l, e),
+    };
+
+    let group_field = match check_string(l, 2) {
+        Ok(s) => s,
+        Err(e) => return push_error(l, e),
+    };
+
+    let accumulator_field = match opt_string(l, 3) {
+        Ok(s) => s,
+        Err(e) => return push_error(l, e),
+    };
+
+    let _int64 = Int64ModeGuard::new(settings.int64);
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    match operations::group_by(&collection, &group_field, accumulator_field.as_deref(), settings.timeout) {
+        Ok(groups) => {
+            lua_newtable(l);
+            for (i, group) in groups.iter().enumerate() {
+                bson_to_lua_table(l, group);
+                lua_rawseti(l, -2, (i + 1) as i32);
+            }
         }
+        Err(e) => return push_failure(l, &settings, "Failed to group documents", e),
     }
 
     1
 }
 
+/// Pushes the outcome of an update or delete as a table
+pub(crate) unsafe fn push_write_result(l: LuaState, result: &WriteResult) {
+    lua_newtable(l);
+    lua_pushnumber(l, result.matched as f64);
+    lua_setfield(l, -2, cstr!("matched"));
+    lua_pushnumber(l, result.modified as f64);
+    lua_setfield(l, -2, cstr!("modified"));
+    lua_pushnumber(l, result.deleted as f64);
+    lua_setfield(l, -2, cstr!("deleted"));
+    if let Some(id) = &result.upserted_id {
+        bson_value_to_lua(l, id);
+        lua_setfield(l, -2, cstr!("upserted_id"));
+    }
+    lua_pushboolean(l, result.acknowledged as i32);
+    lua_setfield(l, -2, cstr!("acknowledged"));
+}
+
+/// Pushes the counts of a bulk write and the operations that failed
+pub(crate) unsafe fn push_bulk_write_result(l: LuaState, summary: &BulkWriteSummary) {
+    lua_newtable(l);
+    for (name, count) in [
+        (cstr!("inserted"), summary.inserted),
+        (cstr!("matched"), summary.matched),
+        (cstr!("modified"), summary.modified),
+        (cstr!("upserted"), summary.upserted),
+        (cstr!("deleted"), summary.deleted),
+    ] {
+        lua_pushnumber(l, count as f64);
+        lua_setfield(l, -2, name);
+    }
+
+    lua_newtable(l);
+    for (i, failure) in summary.failures.iter().enumerate() {
+        lua_newtable(l);
+        lua_pushnumber(l, (failure.index + 1) as f64);
+        lua_setfield(l, -2, cstr!("index"));
+        lua_pushnumber(l, failure.code as f64);
+        lua_setfield(l, -2, cstr!("code"));
+        lua_pushlstring(l, failure.message.as_ptr() as _, failure.message.len());
+        lua_setfield(l, -2, cstr!("message"));
+        lua_rawseti(l, -2, (i + 1) as i32);
+    }
+    lua_setfield(l, -2, cstr!("failed"));
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -1,13 +1,18 @@
 use crate::api::callbacks::listen;
-use crate::core::worker::{should_register_hook, submit_job, Job, Operation, LUA_REGISTRYINDEX};
+use crate::config::ClientSettings;
+use crate::api::collection::{
+    check_bulk_models, check_index_specs, check_string_array, opt_bulk_write_options, opt_filter, opt_find_one_options, opt_find_options, opt_options,
+};
+use crate::operations;
+use crate::api::job::submit_with_handle;
+use crate::core::worker::{should_register_hook, Operation, LUA_REGISTRYINDEX};
 use crate::types::lua_table_to_bson;
-use crate::utils::{push_error, read_userdata};
-use log::error;
+use crate::utils::{call_options, call_settings, check_string, opt_boolean, opt_string, push_error, push_rejected, read_settings, read_userdata};
 use mongodb::{bson::Document, Collection};
 use rglua::lua::LuaState;
 use rglua::prelude::*;
 
-fn maybe_register_hook(l: LuaState) {
+pub(crate) fn maybe_register_hook(l: LuaState) {
     if should_register_hook() {
         unsafe { listen(l); }
     }
@@ -24,39 +29,14 @@ pub extern "C" fn insert_one_async(l: LuaState) -> i32 {
 
         let document = match lua_table_to_bson(l, 2) {
             Ok(doc) => doc,
-            Err(e) => {
-                error!("Failed to convert document: {}", e);
-                lua_pushboolean(l, 0);
-                return 1;
-            }
+            Err(e) => return reject(l, read_settings(l, 1), 3, "Failed to convert document", e),
         };
 
-        let callback = if lua_isfunction(l, 3) {
-            maybe_register_hook(l);
-            lua_pushvalue(l, 3);
-            Some(luaL_ref(l, LUA_REGISTRYINDEX))
-        } else {
-            None
-        };
-
-        let job = Job {
-            operation: Operation::InsertOne {
-                collection: collection.clone(),
-                document,
-            },
-            callback,
-            result: None,
+        let operation = Operation::InsertOne {
+            collection: collection.clone(),
+            document,
         };
-
-        match submit_job(job) {
-            Ok(_) => lua_pushboolean(l, 1),
-            Err(e) => {
-                error!("Failed to submit job: {}", e);
-                lua_pushboolean(l, 0);
-            }
-        }
-
-        1
+        submit_operation(l, operation, read_settings(l, 1), 3)
     }
 }
 
@@ -70,9 +50,7 @@ pub extern "C" fn insert_many_async(l: LuaState) -> i32 {
         };
 
         if !lua_istable(l, 2) {
-            error!("Expected table of documents");
-            lua_pushboolean(l, 0);
-            return 1;
+            return reject(l, read_settings(l, 1), 3, "Failed to convert documents", "expected a table");
         }
 
         let mut documents = Vec::new();
@@ -88,10 +66,8 @@ pub extern "C" fn insert_many_async(l: LuaState) -> i32 {
             match lua_table_to_bson(l, -1) {
                 Ok(doc) => documents.push(doc),
                 Err(e) => {
-                    error!("Failed to convert document at index {}: {}", index, e);
                     lua_pop(l, 1);
-                    lua_pushboolean(l, 0);
-                    return 1;
+                    return reject(l, read_settings(l, 1), 3, &format!("Failed to convert document at index {}", index), e);
                 }
             }
 
@@ -99,32 +75,11 @@ pub extern "C" fn insert_many_async(l: LuaState) -> i32 {
             index += 1;
         }
 
-        let callback = if lua_isfunction(l, 3) {
-            maybe_register_hook(l);
-            lua_pushvalue(l, 3);
-            Some(luaL_ref(l, LUA_REGISTRYINDEX))
-        } else {
-            None
-        };
-
-        let job = Job {
-            operation: Operation::InsertMany {
-                collection: collection.clone(),
-                documents,
-            },
-            callback,
-            result: None,
+        let operation = Operation::InsertMany {
+            collection: collection.clone(),
+            documents,
         };
-
-        match submit_job(job) {
-            Ok(_) => lua_pushboolean(l, 1),
-            Err(e) => {
-                error!("Failed to submit job: {}", e);
-                lua_pushboolean(l, 0);
-            }
-        }
-
-        1
+        submit_operation(l, operation, read_settings(l, 1), 3)
     }
 }
 
@@ -137,48 +92,29 @@ pub extern "C" fn find_async(l: LuaState) -> i32 {
             Err(e) => return push_error(l, e),
         };
 
-        let filter = match lua_table_to_bson(l, 2) {
-            Ok(doc) => doc,
-            Err(e) => {
-                error!("Failed to convert filter: {}", e);
-                lua_pushboolean(l, 0);
-                return 1;
-            }
+        // The options argument may be left out entirely: FindAsync(filter, callback)
+        let callback_index = if lua_isfunction(l, 3) { 3 } else { 4 };
+        let settings = match call_settings(l, 1, 3) {
+            Ok(settings) => settings,
+            Err(e) => return push_error(l, e),
         };
 
-        let limit = if lua_isnumber(l, 3) != 0 {
-            Some(lua_tonumber(l, 3) as i64)
-        } else {
-            None
+        let filter = match lua_table_to_bson(l, 2) {
+            Ok(doc) => doc,
+            Err(e) => return reject(l, settings, callback_index, "Failed to convert filter", e),
         };
 
-        let callback = if lua_isfunction(l, 4) {
-            maybe_register_hook(l);
-            lua_pushvalue(l, 4);
-            Some(luaL_ref(l, LUA_REGISTRYINDEX))
-        } else {
-            None
+        let options = match opt_find_options(l, 3) {
+            Ok(options) => options,
+            Err(e) => return reject(l, settings, callback_index, "Failed to convert find options", e),
         };
 
-        let job = Job {
-            operation: Operation::Find {
-                collection: collection.clone(),
-                filter,
-                limit,
-            },
-            callback,
-            result: None,
+        let operation = Operation::Find {
+            collection: collection.clone(),
+            filter,
+            options: Box::new(options),
         };
-
-        match submit_job(job) {
-            Ok(_) => lua_pushboolean(l, 1),
-            Err(e) => {
-                error!("Failed to submit job: {}", e);
-                lua_pushboolean(l, 0);
-            }
-        }
-
-        1
+        submit_operation(l, operation, settings, callback_index)
     }
 }
 
@@ -190,41 +126,154 @@ pub unsafe fn find_one_async(l: LuaState) -> i32 {
         Err(e) => return push_error(l, e),
     };
 
+    // The options argument may be left out entirely: FindOneAsync(filter, callback)
+    let callback_index = if lua_isfunction(l, 3) { 3 } else { 4 };
+    let settings = match call_settings(l, 1, 3) {
+        Ok(settings) => settings,
+        Err(e) => return push_error(l, e),
+    };
+
     let filter = match lua_table_to_bson(l, 2) {
         Ok(doc) => doc,
-        Err(e) => {
-            error!("Failed to convert filter: {}", e);
-            lua_pushboolean(l, 0);
-            return 1;
-        }
+        Err(e) => return reject(l, settings, callback_index, "Failed to convert filter", e),
     };
 
-    let callback = if lua_isfunction(l, 3) {
-        maybe_register_hook(l);
-        lua_pushvalue(l, 3);
-        Some(luaL_ref(l, LUA_REGISTRYINDEX))
-    } else {
-        None
+    let options = match opt_find_one_options(l, 3) {
+        Ok(options) => options,
+        Err(e) => return reject(l, settings, callback_index, "Failed to convert find options", e),
     };
 
-    let job = Job {
-        operation: Operation::FindOne {
-            collection: collection.clone(),
-            filter,
-        },
-        callback,
-        result: None,
+    let operation = Operation::FindOne {
+        collection: collection.clone(),
+        filter,
+        options: Box::new(options),
     };
+    submit_operation(l, operation, settings, callback_index)
+}
 
-    match submit_job(job) {
-        Ok(_) => lua_pushboolean(l, 1),
-        Err(e) => {
-            error!("Failed to submit job: {}", e);
-            lua_pushboolean(l, 0);
-        }
-    }
+/// Async version of find_one_and_update with callback
+#[lua_function]
+pub unsafe fn find_one_and_update_async(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    // The options argument may be left out entirely: FindOneAndUpdateAsync(filter, update, callback)
+    let callback_index = if lua_isfunction(l, 4) { 4 } else { 5 };
+    let settings = match call_settings(l, 1, 4) {
+        Ok(settings) => settings,
+        Err(e) => return push_error(l, e),
+    };
+
+    let (filter, update) = match (lua_table_to_bson(l, 2), lua_table_to_bson(l, 3)) {
+        (Ok(filter), Ok(update)) => (filter, update),
+        (Err(e), _) | (_, Err(e)) => return reject(l, settings, callback_index, "Failed to convert filter or update", e),
+    };
+
+    let options = match opt_options(l, 4, operations::find_one_and_update_options) {
+        Ok(options) => options,
+        Err(e) => return reject(l, settings, callback_index, "Failed to convert find one and update options", e),
+    };
+
+    let operation = Operation::FindOneAndUpdate {
+        collection,
+        filter,
+        update,
+        options: Box::new(options),
+    };
+    submit_operation(l, operation, settings, callback_index)
+}
+
+/// Async version of find_one_and_replace with callback
+#[lua_function]
+pub unsafe fn find_one_and_replace_async(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    let callback_index = if lua_isfunction(l, 4) { 4 } else { 5 };
+    let settings = match call_settings(l, 1, 4) {
+        Ok(settings) => settings,
+        Err(e) => return push_error(l, e),
+    };
+
+    let (filter, replacement) = match (lua_table_to_bson(l, 2), lua_table_to_bson(l, 3)) {
+        (Ok(filter), Ok(replacement)) => (filter, replacement),
+        (Err(e), _) | (_, Err(e)) => return reject(l, settings, callback_index, "Failed to convert filter or replacement", e),
+    };
+
+    let options = match opt_options(l, 4, operations::find_one_and_replace_options) {
+        Ok(options) => options,
+        Err(e) => return reject(l, settings, callback_index, "Failed to convert find one and replace options", e),
+    };
+
+    let operation = Operation::FindOneAndReplace {
+        collection,
+        filter,
+        replacement,
+        options: Box::new(options),
+    };
+    submit_operation(l, operation, settings, callback_index)
+}
+
+/// Async version of find_one_and_delete with callback
+#[lua_function]
+pub unsafe fn find_one_and_delete_async(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    let callback_index = if lua_isfunction(l, 3) { 3 } else { 4 };
+    let settings = match call_settings(l, 1, 3) {
+        Ok(settings) => settings,
+        Err(e) => return push_error(l, e),
+    };
+
+    let filter = match lua_table_to_bson(l, 2) {
+        Ok(doc) => doc,
+        Err(e) => return reject(l, settings, callback_index, "Failed to convert filter", e),
+    };
+
+    let options = match opt_options(l, 3, operations::find_one_and_delete_options) {
+        Ok(options) => options,
+        Err(e) => return reject(l, settings, callback_index, "Failed to convert find one and delete options", e),
+    };
+
+    let operation = Operation::FindOneAndDelete {
+        collection,
+        filter,
+        options: Box::new(options),
+    };
+    submit_operation(l, operation, settings, callback_index)
+}
+
+/// Turns away an async call whose arguments could not be converted with
+/// `false, err`; `log_errors` also comes from the call options after the
+/// callback at `callback_index`
+pub(crate) unsafe fn reject(l: LuaState, settings: ClientSettings, callback_index: i32, context: &str, error: impl std::fmt::Display) -> i32 {
+    let settings = call_options(l, settings.clone(), callback_index + 1).map_or(settings, |(settings, _)| settings);
+    push_rejected(l, &settings, context, error)
+}
 
-    1
+/// Queues `operation` with the callback at `callback_index` and the
+/// optional session or call options right after it, and pushes the job handle
+pub(crate) unsafe fn submit_operation(l: LuaState, operation: Operation, settings: ClientSettings, callback_index: i32) -> i32 {
+    let (settings, session) = match call_options(l, settings, callback_index + 1) {
+        Ok(options) => options,
+        Err(e) => return push_error(l, e),
+    };
+
+    let callback = if lua_isfunction(l, callback_index) {
+        lua_pushvalue(l, callback_index);
+        Some(luaL_ref(l, LUA_REGISTRYINDEX))
+    } else {
+        None
+    };
+
+    submit_with_handle(l, operation, settings, callback, session)
 }
 
 /// Async version of update_one with callback
@@ -237,20 +286,12 @@ pub unsafe fn update_one_async(l: LuaState) -> i32 {
 
     let filter = match lua_table_to_bson(l, 2) {
         Ok(doc) => doc,
-        Err(e) => {
-            error!("Failed to convert filter: {}", e);
-            lua_pushboolean(l, 0);
-            return 1;
-        }
+        Err(e) => return reject(l, read_settings(l, 1), 5, "Failed to convert filter", e),
     };
 
     let update = match lua_table_to_bson(l, 3) {
         Ok(doc) => doc,
-        Err(e) => {
-            error!("Failed to convert update: {}", e);
-            lua_pushboolean(l, 0);
-            return 1;
-        }
+        Err(e) => return reject(l, read_settings(l, 1), 5, "Failed to convert update", e),
     };
 
     let upsert = if lua_isboolean(l, 4) {
@@ -259,34 +300,13 @@ pub unsafe fn update_one_async(l: LuaState) -> i32 {
         false
     };
 
-    let callback = if lua_isfunction(l, 5) {
-        maybe_register_hook(l);
-        lua_pushvalue(l, 5);
-        Some(luaL_ref(l, LUA_REGISTRYINDEX))
-    } else {
-        None
+    let operation = Operation::UpdateOne {
+        collection: collection.clone(),
+        filter,
+        update,
+        upsert,
     };
-
-    let job = Job {
-        operation: Operation::UpdateOne {
-            collection: collection.clone(),
-            filter,
-            update,
-            upsert,
-        },
-        callback,
-        result: None,
-    };
-
-    match submit_job(job) {
-        Ok(_) => lua_pushboolean(l, 1),
-        Err(e) => {
-            error!("Failed to submit job: {}", e);
-            lua_pushboolean(l, 0);
-        }
-    }
-
-    1
+    submit_operation(l, operation, read_settings(l, 1), 5)
 }
 
 /// Async version of update_many with callback
@@ -299,20 +319,12 @@ pub unsafe fn update_many_async(l: LuaState) -> i32 {
 
     let filter = match lua_table_to_bson(l, 2) {
         Ok(doc) => doc,
-        Err(e) => {
-            error!("Failed to convert filter: {}", e);
-            lua_pushboolean(l, 0);
-            return 1;
-        }
+        Err(e) => return reject(l, read_settings(l, 1), 5, "Failed to convert filter", e),
     };
 
     let update = match lua_table_to_bson(l, 3) {
         Ok(doc) => doc,
-        Err(e) => {
-            error!("Failed to convert update: {}", e);
-            lua_pushboolean(l, 0);
-            return 1;
-        }
+        Err(e) => return reject(l, read_settings(l, 1), 5, "Failed to convert update", e),
     };
 
     let upsert = if lua_isboolean(l, 4) {
@@ -321,39 +333,18 @@ pub unsafe fn update_many_async(l: LuaState) -> i32 {
         false
     };
 
-    let callback = if lua_isfunction(l, 5) {
-        maybe_register_hook(l);
-        lua_pushvalue(l, 5);
-        Some(luaL_ref(l, LUA_REGISTRYINDEX))
-    } else {
-        None
+    let operation = Operation::UpdateMany {
+        collection: collection.clone(),
+        filter,
+        update,
+        upsert,
     };
-
-    let job = Job {
-        operation: Operation::UpdateMany {
-            collection: collection.clone(),
-            filter,
-            update,
-            upsert,
-        },
-        callback,
-        result: None,
-    };
-
-    match submit_job(job) {
-        Ok(_) => lua_pushboolean(l, 1),
-        Err(e) => {
-            error!("Failed to submit job: {}", e);
-            lua_pushboolean(l, 0);
-        }
-    }
-
-    1
+    submit_operation(l, operation, read_settings(l, 1), 5)
 }
 
-/// Async version of delete_one with callback
+/// Async version of replace_one with callback
 #[lua_function]
-pub unsafe fn delete_one_async(l: LuaState) -> i32 {
+pub unsafe fn replace_one_async(l: LuaState) -> i32 {
     let collection: Collection<Document> = match read_userdata(l, 1) {
         Ok(c) => c,
         Err(e) => return push_error(l, e),
@@ -361,44 +352,28 @@ pub unsafe fn delete_one_async(l: LuaState) -> i32 {
 
     let filter = match lua_table_to_bson(l, 2) {
         Ok(doc) => doc,
-        Err(e) => {
-            error!("Failed to convert filter: {}", e);
-            lua_pushboolean(l, 0);
-            return 1;
-        }
-    };
-
-    let callback = if lua_isfunction(l, 3) {
-        maybe_register_hook(l);
-        lua_pushvalue(l, 3);
-        Some(luaL_ref(l, LUA_REGISTRYINDEX))
-    } else {
-        None
+        Err(e) => return reject(l, read_settings(l, 1), 5, "Failed to convert filter", e),
     };
 
-    let job = Job {
-        operation: Operation::DeleteOne {
-            collection: collection.clone(),
-            filter,
-        },
-        callback,
-        result: None,
+    let replacement = match lua_table_to_bson(l, 3) {
+        Ok(doc) => doc,
+        Err(e) => return reject(l, read_settings(l, 1), 5, "Failed to convert replacement", e),
     };
 
-    match submit_job(job) {
-        Ok(_) => lua_pushboolean(l, 1),
-        Err(e) => {
-            error!("Failed to submit job: {}", e);
-            lua_pushboolean(l, 0);
-        }
-    }
+    let upsert = lua_isboolean(l, 4) && lua_toboolean(l, 4) != 0;
 
-    1
+    let operation = Operation::ReplaceOne {
+        collection,
+        filter,
+        replacement,
+        upsert,
+    };
+    submit_operation(l, operation, read_settings(l, 1), 5)
 }
 
-/// Async version of delete_many with callback
+/// Async version of delete_one with callback
 #[lua_function]
-pub unsafe fn delete_many_async(l: LuaState) -> i32 {
+pub unsafe fn delete_one_async(l: LuaState) -> i32 {
     let collection: Collection<Document> = match read_userdata(l, 1) {
         Ok(c) => c,
         Err(e) => return push_error(l, e),
@@ -406,39 +381,34 @@ pub unsafe fn delete_many_async(l: LuaState) -> i32 {
 
     let filter = match lua_table_to_bson(l, 2) {
         Ok(doc) => doc,
-        Err(e) => {
-            error!("Failed to convert filter: {}", e);
-            lua_pushboolean(l, 0);
-            return 1;
-        }
+        Err(e) => return reject(l, read_settings(l, 1), 3, "Failed to convert filter", e),
     };
 
-    let callback = if lua_isfunction(l, 3) {
-        maybe_register_hook(l);
-        lua_pushvalue(l, 3);
-        Some(luaL_ref(l, LUA_REGISTRYINDEX))
-    } else {
-        None
+    let operation = Operation::DeleteOne {
+        collection: collection.clone(),
+        filter,
     };
+    submit_operation(l, operation, read_settings(l, 1), 3)
+}
 
-    let job = Job {
-        operation: Operation::DeleteMany {
-            collection: collection.clone(),
-            filter,
-        },
-        callback,
-        result: None,
+/// Async version of delete_many with callback
+#[lua_function]
+pub unsafe fn delete_many_async(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
     };
 
-    match submit_job(job) {
-        Ok(_) => lua_pushboolean(l, 1),
-        Err(e) => {
-            error!("Failed to submit job: {}", e);
-            lua_pushboolean(l, 0);
-        }
-    }
+    let filter = match lua_table_to_bson(l, 2) {
+        Ok(doc) => doc,
+        Err(e) => return reject(l, read_settings(l, 1), 3, "Failed to convert filter", e),
+    };
 
-    1
+    let operation = Operation::DeleteMany {
+        collection: collection.clone(),
+        filter,
+    };
+    submit_operation(l, operation, read_settings(l, 1), 3)
 }
 
 /// Async version of count_documents with callback
@@ -451,39 +421,57 @@ pub unsafe fn count_documents_async(l: LuaState) -> i32 {
 
     let filter = match lua_table_to_bson(l, 2) {
         Ok(doc) => doc,
-        Err(e) => {
-            error!("Failed to convert filter: {}", e);
-            lua_pushboolean(l, 0);
-            return 1;
-        }
+        Err(e) => return reject(l, read_settings(l, 1), 3, "Failed to convert filter", e),
     };
 
-    let callback = if lua_isfunction(l, 3) {
-        maybe_register_hook(l);
-        lua_pushvalue(l, 3);
-        Some(luaL_ref(l, LUA_REGISTRYINDEX))
-    } else {
-        None
+    let operation = Operation::CountDocuments {
+        collection: collection.clone(),
+        filter,
     };
+    submit_operation(l, operation, read_settings(l, 1), 3)
+}
 
-    let job = Job {
-        operation: Operation::CountDocuments {
-            collection: collection.clone(),
-            filter,
-        },
-        callback,
-        result: None,
+/// Async version of distinct with callback
+#[lua_function]
+pub unsafe fn distinct_async(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    let field = match check_string(l, 2) {
+        Ok(s) => s,
+        Err(e) => return push_error(l, e),
     };
 
-    match submit_job(job) {
-        Ok(_) => lua_pushboolean(l, 1),
-        Err(e) => {
-            error!("Failed to submit job: {}", e);
-            lua_pushboolean(l, 0);
+    // The filter may be left out: DistinctAsync(field, callback)
+    let callback_index = if lua_isfunction(l, 3) { 3 } else { 4 };
+    let filter = if callback_index == 4 {
+        match opt_filter(l, 3) {
+            Ok(doc) => doc,
+            Err(e) => return reject(l, read_settings(l, 1), callback_index, "Failed to convert filter", e),
         }
-    }
+    } else {
+        Document::new()
+    };
 
-    1
+    let operation = Operation::Distinct {
+        collection,
+        field,
+        filter,
+    };
+    submit_operation(l, operation, read_settings(l, 1), callback_index)
+}
+
+/// Async version of estimated_count with callback
+#[lua_function]
+pub unsafe fn estimated_count_async(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    submit_operation(l, Operation::EstimatedCount { collection }, read_settings(l, 1), 2)
 }
 
 /// Async version of aggregate with callback
@@ -496,9 +484,7 @@ pub unsafe fn aggregate_async(l: LuaState) -> i32 {
 
     // Convert pipeline array
     if !lua_istable(l, 2) {
-        error!("Expected table for pipeline");
-        lua_pushboolean(l, 0);
-        return 1;
+        return reject(l, read_settings(l, 1), 3, "Failed to convert pipeline", "expected a table");
     }
 
     let mut pipeline = Vec::new();
@@ -514,10 +500,8 @@ pub unsafe fn aggregate_async(l: LuaState) -> i32 {
         match lua_table_to_bson(l, -1) {
             Ok(stage) => pipeline.push(stage),
             Err(e) => {
-                error!("Failed to convert pipeline stage {}: {}", index, e);
                 lua_pop(l, 1);
-                lua_pushboolean(l, 0);
-                return 1;
+                return reject(l, read_settings(l, 1), 3, &format!("Failed to convert pipeline stage {}", index), e);
             }
         }
 
@@ -534,30 +518,208 @@ pub unsafe fn aggregate_async(l: LuaState) -> i32 {
         }
     }
 
-    let callback = if lua_isfunction(l, 3) {
-        maybe_register_hook(l);
-        lua_pushvalue(l, 3);
-        Some(luaL_ref(l, LUA_REGISTRYINDEX))
+    let operation = Operation::Aggregate {
+        collection: collection.clone(),
+        pipeline,
+    };
+    submit_operation(l, operation, read_settings(l, 1), 3)
+}
+
+/// Async version of bulk_write with callback
+#[lua_function]
+pub unsafe fn bulk_write_async(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    // The options argument may be left out entirely: BulkWriteAsync(ops, callback)
+    let callback_index = if lua_isfunction(l, 3) { 3 } else { 4 };
+
+    let models = match check_bulk_models(l, 2, &collection) {
+        Ok(models) => models,
+        Err(e) => return reject(l, read_settings(l, 1), callback_index, "Failed to convert bulk operations", e),
+    };
+
+    let options = match opt_bulk_write_options(l, 3) {
+        Ok(options) => options,
+        Err(e) => return reject(l, read_settings(l, 1), callback_index, "Failed to convert bulk write options", e),
+    };
+
+    let operation = Operation::BulkWrite {
+        collection,
+        models,
+        options: Box::new(options),
+    };
+    submit_operation(l, operation, read_settings(l, 1), callback_index)
+}
+
+/// Async version of count_aggregate with callback
+#[lua_function]
+pub unsafe fn count_aggregate_async(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    let filter = match lua_table_to_bson(l, 2) {
+        Ok(doc) => doc,
+        Err(e) => return reject(l, read_settings(l, 1), 3, "Failed to convert filter", e),
+    };
+
+    submit_operation(l, Operation::CountAggregate { collection, filter }, read_settings(l, 1), 3)
+}
+
+/// Async version of group_by with callback
+#[lua_function]
+pub unsafe fn group_by_async(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    let group_field = match check_string(l, 2) {
+        Ok(s) => s,
+        Err(e) => return push_error(l, e),
+    };
+
+    // The summed field may be left out: GroupByAsync(field, callback)
+    let callback_index = if lua_isfunction(l, 3) { 3 } else { 4 };
+    let accumulator_field = if callback_index == 4 {
+        match opt_string(l, 3) {
+            Ok(s) => s,
+            Err(e) => return push_error(l, e),
+        }
     } else {
         None
     };
 
-    let job = Job {
-        operation: Operation::Aggregate {
-            collection: collection.clone(),
-            pipeline,
-        },
-        callback,
-        result: None,
+    let operation = Operation::GroupBy {
+        collection,
+        group_field,
+        accumulator_field,
+    };
+    submit_operation(l, operation, read_settings(l, 1), callback_index)
+}
+
+/// Async version of create_index with callback
+#[lua_function]
+pub unsafe fn create_index_async(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
     };
 
-    match submit_job(job) {
-        Ok(_) => lua_pushboolean(l, 1),
-        Err(e) => {
-            error!("Failed to submit job: {}", e);
-            lua_pushboolean(l, 0);
+    // unique and name are optional, so the callback is the first function
+    // after the keys: CreateIndexAsync(keys [, unique [, name]], callback)
+    let callback_index = (3..=5).find(|&i| lua_isfunction(l, i)).unwrap_or(5);
+
+    let keys = match lua_table_to_bson(l, 2) {
+        Ok(doc) => doc,
+        Err(e) => return reject(l, read_settings(l, 1), callback_index, "Failed to convert index keys", e),
+    };
+
+    let unique = callback_index > 3 && opt_boolean(l, 3, false);
+    let name = if callback_index > 4 {
+        match opt_string(l, 4) {
+            Ok(s) => s,
+            Err(e) => return push_error(l, e),
         }
-    }
+    } else {
+        None
+    };
+
+    let operation = Operation::CreateIndex {
+        collection,
+        keys,
+        unique,
+        name,
+    };
+    submit_operation(l, operation, read_settings(l, 1), callback_index)
+}
+
+/// Async version of list_indexes with callback
+#[lua_function]
+pub unsafe fn list_indexes_async(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    submit_operation(l, Operation::ListIndexes { collection }, read_settings(l, 1), 2)
+}
+
+/// Async version of drop_index with callback
+#[lua_function]
+pub unsafe fn drop_index_async(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    let name = match check_string(l, 2) {
+        Ok(s) => s,
+        Err(e) => return push_error(l, e),
+    };
+
+    submit_operation(l, Operation::DropIndex { collection, name }, read_settings(l, 1), 3)
+}
+
+/// Async version of create_indexes with callback
+#[lua_function]
+pub unsafe fn create_indexes_async(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    let indexes = match check_index_specs(l, 2) {
+        Ok(indexes) => indexes,
+        Err(e) => return reject(l, read_settings(l, 1), 3, "Failed to convert indexes", e),
+    };
+
+    submit_operation(l, Operation::CreateIndexes { collection, indexes }, read_settings(l, 1), 3)
+}
+
+/// Async version of create_text_index with callback
+#[lua_function]
+pub unsafe fn create_text_index_async(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    let callback_index = if lua_isfunction(l, 3) { 3 } else { 4 };
+
+    let fields = match check_string_array(l, 2) {
+        Ok(fields) => fields,
+        Err(e) => return reject(l, read_settings(l, 1), callback_index, "Failed to convert text index fields", e),
+    };
+
+    let name = if callback_index == 4 {
+        match opt_string(l, 3) {
+            Ok(s) => s,
+            Err(e) => return push_error(l, e),
+        }
+    } else {
+        None
+    };
+
+    let operation = Operation::CreateTextIndex {
+        collection,
+        fields,
+        name,
+    };
+    submit_operation(l, operation, read_settings(l, 1), callback_index)
+}
+
+/// Async version of drop_indexes with callback
+#[lua_function]
+pub unsafe fn drop_indexes_async(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
 
-    1
+    submit_operation(l, Operation::DropIndexes { collection }, read_settings(l, 1), 2)
 }
@@ -0,0 +1,194 @@
+use crate::api::collection::opt_find_options;
+use crate::api::collection_async::submit_operation;
+use crate::config::ClientSettings;
+use crate::core::cursor::CursorHandle;
+use crate::core::runtime::CallTimeoutGuard;
+use crate::core::worker::Operation;
+use crate::error::{LuaError, LuaResult, MongoResult};
+use crate::operations::{self, DEFAULT_CURSOR_BATCH};
+use crate::types::{bson_to_lua_table, lua_table_to_bson, Int64ModeGuard};
+use crate::utils::{attach_settings, call_settings, is_udata, push_error, push_failure, push_invalid, read_settings, read_userdata, write_userdata};
+use mongodb::bson::Document;
+use mongodb::Collection;
+use rglua::lua::LuaState;
+use rglua::prelude::*;
+
+unsafe fn check_cursor(l: LuaState, index: i32) -> LuaResult<CursorHandle> {
+    if !is_udata(l, index, cstr!("MongoDBCursor")) {
+        return Err(LuaError::InvalidUserdata("Expected MongoDBCursor".to_string()));
+    }
+    read_userdata(l, index)
+}
+
+/// Reads the batch size argument, falling back to [`DEFAULT_CURSOR_BATCH`]
+unsafe fn opt_count(l: LuaState, index: i32) -> usize {
+    if lua_isnumber(l, index) != 0 {
+        (lua_tonumber(l, index) as usize).max(1)
+    } else {
+        DEFAULT_CURSOR_BATCH
+    }
+}
+
+unsafe fn push_documents(l: LuaState, documents: &[Document]) {
+    lua_newtable(l);
+    for (i, doc) in documents.iter().enumerate() {
+        bson_to_lua_table(l, doc);
+        lua_rawseti(l, -2, (i + 1) as i32);
+    }
+}
+
+#[lua_function]
+pub unsafe fn find_cursor(l: LuaState) -> i32 {
+    let collection: Collection<Document> = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    let settings = match call_settings(l, 1, 3) {
+        Ok(settings) => settings,
+        Err(e) => return push_error(l, e),
+    };
+
+    let filter = if lua_istable(l, 2) {
+        match lua_table_to_bson(l, 2) {
+            Ok(doc) => doc,
+            Err(e) => return push_invalid(l, &settings, "Failed to convert filter", e),
+        }
+    } else {
+        Document::new()
+    };
+
+    let mut options = match opt_find_options(l, 3) {
+        Ok(options) => options,
+        Err(e) => return push_invalid(l, &settings, "Failed to convert cursor options", e),
+    };
+
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    options.max_time = options.max_time.or(settings.timeout);
+
+    match operations::open_cursor(collection, filter, options) {
+        Ok(cursor) => {
+            write_userdata(l, cursor);
+            luaL_getmetatable(l, cstr!("MongoDBCursor"));
+            lua_setmetatable(l, -2);
+            attach_settings(l, &settings, Some(1));
+            1
+        }
+        Err(e) => push_failure(l, &settings, "Failed to open cursor", e),
+    }
+}
+
+/// Pushes the next document of `cursor`, or nil once it is exhausted
+unsafe fn push_next(l: LuaState, cursor: &CursorHandle, settings: &ClientSettings) -> MongoResult<()> {
+    let _int64 = Int64ModeGuard::new(settings.int64);
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    match operations::cursor_next_batch(cursor, 1)?.first() {
+        Some(doc) => bson_to_lua_table(l, doc),
+        None => lua_pushnil(l),
+    }
+    Ok(())
+}
+
+/// Returns the next document, or nil once the cursor is exhausted.
+/// A read error returns `nil, err`.
+#[lua_function]
+pub unsafe fn cursor_next(l: LuaState) -> i32 {
+    let cursor = match check_cursor(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    let settings = read_settings(l, 1);
+    match push_next(l, &cursor, &settings) {
+        Ok(()) => 1,
+        Err(e) => push_failure(l, &settings, "Failed to read cursor", e),
+    }
+}
+
+/// Iterator function returned by `Iterate()`. A `for` loop would take
+/// `nil, err` for the end of the results, so a read error is raised instead.
+#[lua_function]
+pub unsafe fn cursor_iterate_next(l: LuaState) -> i32 {
+    let cursor = match check_cursor(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    match push_next(l, &cursor, &read_settings(l, 1)) {
+        Ok(()) => 1,
+        Err(e) => push_error(l, format!("Failed to read cursor: {}", e)),
+    }
+}
+
+#[lua_function]
+pub unsafe fn cursor_next_batch(l: LuaState) -> i32 {
+    let cursor = match check_cursor(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    let settings = read_settings(l, 1);
+    let _int64 = Int64ModeGuard::new(settings.int64);
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    match operations::cursor_next_batch(&cursor, opt_count(l, 2)) {
+        Ok(documents) => {
+            push_documents(l, &documents);
+            1
+        }
+        Err(e) => push_failure(l, &settings, "Failed to read cursor", e),
+    }
+}
+
+#[lua_function]
+pub unsafe fn cursor_has_next(l: LuaState) -> i32 {
+    let cursor = match check_cursor(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    let settings = read_settings(l, 1);
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    match operations::cursor_has_next(&cursor) {
+        Ok(has_next) => {
+            lua_pushboolean(l, has_next as i32);
+            1
+        }
+        Err(e) => push_failure(l, &settings, "Failed to read cursor", e),
+    }
+}
+
+#[lua_function]
+pub unsafe fn cursor_close(l: LuaState) -> i32 {
+    let cursor = match check_cursor(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    operations::cursor_close(&cursor);
+    0
+}
+
+/// `for doc in cursor:Iterate() do ... end`
+#[lua_function]
+pub unsafe fn cursor_iterate(l: LuaState) -> i32 {
+    if let Err(e) = check_cursor(l, 1) {
+        return push_error(l, e);
+    }
+
+    lua_pushcfunction(l, std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(cursor_iterate_next));
+    lua_pushvalue(l, 1);
+    2
+}
+
+/// Async version of cursor_next_batch with callback
+#[lua_function]
+pub unsafe fn cursor_next_batch_async(l: LuaState) -> i32 {
+    let cursor = match check_cursor(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    let count = opt_count(l, 2);
+
+    submit_operation(l, Operation::CursorNext { cursor, count }, read_settings(l, 1), 3)
+}
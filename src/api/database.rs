@@ -1,7 +1,10 @@
+use crate::api::collection_async::submit_operation;
+use crate::core::runtime::CallTimeoutGuard;
+use crate::core::worker::Operation;
 use crate::log_info;
 use crate::operations;
-use crate::utils::{check_string, push_error, read_userdata, write_userdata};
-use log::error;
+use crate::types::Int64ModeGuard;
+use crate::utils::{call_settings, check_string, inherit_settings, opt_boolean, read_settings, push_error, push_failure, read_userdata, write_userdata};
 use mongodb::{Client, Database};
 use rglua::lua::LuaState;
 use rglua::prelude::*;
@@ -24,6 +27,7 @@ pub extern "C" fn get_database(l: LuaState) -> i32 {
         write_userdata(l, db);
         luaL_getmetatable(l, cstr!("MongoDBDatabase"));
         lua_setmetatable(l, -2);
+        inherit_settings(l, 1);
 
         1
     }
@@ -37,13 +41,14 @@ pub extern "C" fn list_collections(l: LuaState) -> i32 {
             Err(e) => return push_error(l, e),
         };
 
+        let settings = match call_settings(l, 1, 2) {
+            Ok(settings) => settings,
+            Err(e) => return push_error(l, e),
+        };
+        let _timeout = CallTimeoutGuard::new(settings.timeout);
         let collections = match operations::list_collections(&database) {
             Ok(cols) => cols,
-            Err(e) => {
-                error!("Failed to list collections: {}", e);
-                lua_pushnil(l);
-                return 1;
-            }
+            Err(e) => return push_failure(l, &settings, "Failed to list collections", e),
         };
 
         lua_newtable(l);
@@ -71,15 +76,17 @@ pub extern "C" fn create_collection(l: LuaState) -> i32 {
             Err(e) => return push_error(l, e),
         };
 
+        let settings = match call_settings(l, 1, 3) {
+            Ok(settings) => settings,
+            Err(e) => return push_error(l, e),
+        };
+        let _timeout = CallTimeoutGuard::new(settings.timeout);
         match operations::create_collection(&database, &collection_name) {
             Ok(_) => {
                 log_info!("Created collection: {}", collection_name);
                 lua_pushboolean(l, 1);
             }
-            Err(e) => {
-                error!("Failed to create collection: {}", e);
-                lua_pushboolean(l, 0);
-            }
+            Err(e) => return push_failure(l, &settings, "Failed to create collection", e),
         }
 
         1
@@ -99,15 +106,17 @@ pub extern "C" fn drop_collection(l: LuaState) -> i32 {
             Err(e) => return push_error(l, e),
         };
 
+        let settings = match call_settings(l, 1, 3) {
+            Ok(settings) => settings,
+            Err(e) => return push_error(l, e),
+        };
+        let _timeout = CallTimeoutGuard::new(settings.timeout);
         match operations::drop_collection(&database, &collection_name) {
             Ok(_) => {
                 log_info!("Dropped collection: {}", collection_name);
                 lua_pushboolean(l, 1);
             }
-            Err(e) => {
-                error!("Failed to drop collection: {}", e);
-                lua_pushboolean(l, 0);
-            }
+            Err(e) => return push_failure(l, &settings, "Failed to drop collection", e),
         }
 
         1
@@ -127,14 +136,17 @@ pub extern "C" fn collection_stats(l: LuaState) -> i32 {
             Err(e) => return push_error(l, e),
         };
 
+        let settings = match call_settings(l, 1, 3) {
+            Ok(settings) => settings,
+            Err(e) => return push_error(l, e),
+        };
+        let _int64 = Int64ModeGuard::new(settings.int64);
+        let _timeout = CallTimeoutGuard::new(settings.timeout);
         match operations::collection_stats(&database, &collection_name) {
             Ok(stats) => {
                 crate::types::bson_to_lua_table(l, &stats);
             }
-            Err(e) => {
-                error!("Failed to get collection stats: {}", e);
-                lua_pushnil(l);
-            }
+            Err(e) => return push_failure(l, &settings, "Failed to get collection stats", e),
         }
 
         1
@@ -149,21 +161,194 @@ pub extern "C" fn drop_database(l: LuaState) -> i32 {
             Err(e) => return push_error(l, e),
         };
 
+        let settings = match call_settings(l, 1, 2) {
+            Ok(settings) => settings,
+            Err(e) => return push_error(l, e),
+        };
+        let _timeout = CallTimeoutGuard::new(settings.timeout);
         match operations::drop_database(&database) {
             Ok(_) => {
                 log_info!("Dropped database: {}", database.name());
                 lua_pushboolean(l, 1);
             }
-            Err(e) => {
-                error!("Failed to drop database: {}", e);
-                lua_pushboolean(l, 0);
+            Err(e) => return push_failure(l, &settings, "Failed to drop database", e),
+        }
+
+        1
+    }
+}
+
+#[lua_function]
+pub extern "C" fn rename_collection(l: LuaState) -> i32 {
+    unsafe {
+        let database: Database = match read_userdata(l, 1) {
+            Ok(db) => db,
+            Err(e) => return push_error(l, e),
+        };
+
+        let (old_name, new_name) = match (check_string(l, 2), check_string(l, 3)) {
+            (Ok(old_name), Ok(new_name)) => (old_name, new_name),
+            (Err(e), _) | (_, Err(e)) => return push_error(l, e),
+        };
+
+        let drop_target = opt_boolean(l, 4, false);
+
+        let settings = match call_settings(l, 1, 5) {
+            Ok(settings) => settings,
+            Err(e) => return push_error(l, e),
+        };
+        let _timeout = CallTimeoutGuard::new(settings.timeout);
+        match operations::rename_collection(&database, &old_name, &new_name, drop_target) {
+            Ok(_) => {
+                log_info!("Renamed collection: {} -> {}", old_name, new_name);
+                lua_pushboolean(l, 1);
             }
+            Err(e) => return push_failure(l, &settings, "Failed to rename collection", e),
+        }
+
+        1
+    }
+}
+
+#[lua_function]
+pub extern "C" fn collection_exists(l: LuaState) -> i32 {
+    unsafe {
+        let database: Database = match read_userdata(l, 1) {
+            Ok(db) => db,
+            Err(e) => return push_error(l, e),
+        };
+
+        let collection_name = match check_string(l, 2) {
+            Ok(s) => s,
+            Err(e) => return push_error(l, e),
+        };
+
+        let settings = match call_settings(l, 1, 3) {
+            Ok(settings) => settings,
+            Err(e) => return push_error(l, e),
+        };
+        let _timeout = CallTimeoutGuard::new(settings.timeout);
+        match operations::collection_exists(&database, &collection_name) {
+            Ok(exists) => lua_pushboolean(l, exists as i32),
+            Err(e) => return push_failure(l, &settings, "Failed to check collection", e),
         }
 
         1
     }
 }
 
+/// Async version of rename_collection with callback
+#[lua_function]
+pub unsafe fn rename_collection_async(l: LuaState) -> i32 {
+    let database: Database = match read_userdata(l, 1) {
+        Ok(db) => db,
+        Err(e) => return push_error(l, e),
+    };
+
+    let (old_name, new_name) = match (check_string(l, 2), check_string(l, 3)) {
+        (Ok(old_name), Ok(new_name)) => (old_name, new_name),
+        (Err(e), _) | (_, Err(e)) => return push_error(l, e),
+    };
+
+    // drop_target may be left out: RenameCollectionAsync(old, new, callback)
+    let callback_index = if lua_isfunction(l, 4) { 4 } else { 5 };
+    let drop_target = callback_index == 5 && opt_boolean(l, 4, false);
+
+    let operation = Operation::RenameCollection {
+        database,
+        old_name,
+        new_name,
+        drop_target,
+    };
+    submit_operation(l, operation, read_settings(l, 1), callback_index)
+}
+
+/// Async version of collection_exists with callback
+#[lua_function]
+pub unsafe fn collection_exists_async(l: LuaState) -> i32 {
+    let database: Database = match read_userdata(l, 1) {
+        Ok(db) => db,
+        Err(e) => return push_error(l, e),
+    };
+
+    let name = match check_string(l, 2) {
+        Ok(s) => s,
+        Err(e) => return push_error(l, e),
+    };
+
+    submit_operation(l, Operation::CollectionExists { database, name }, read_settings(l, 1), 3)
+}
+
+/// Async version of list_collections with callback
+#[lua_function]
+pub unsafe fn list_collections_async(l: LuaState) -> i32 {
+    let database: Database = match read_userdata(l, 1) {
+        Ok(db) => db,
+        Err(e) => return push_error(l, e),
+    };
+
+    submit_operation(l, Operation::ListCollections { database }, read_settings(l, 1), 2)
+}
+
+/// Async version of create_collection with callback
+#[lua_function]
+pub unsafe fn create_collection_async(l: LuaState) -> i32 {
+    let database: Database = match read_userdata(l, 1) {
+        Ok(db) => db,
+        Err(e) => return push_error(l, e),
+    };
+
+    let name = match check_string(l, 2) {
+        Ok(s) => s,
+        Err(e) => return push_error(l, e),
+    };
+
+    submit_operation(l, Operation::CreateCollection { database, name }, read_settings(l, 1), 3)
+}
+
+/// Async version of drop_collection with callback
+#[lua_function]
+pub unsafe fn drop_collection_async(l: LuaState) -> i32 {
+    let database: Database = match read_userdata(l, 1) {
+        Ok(db) => db,
+        Err(e) => return push_error(l, e),
+    };
+
+    let name = match check_string(l, 2) {
+        Ok(s) => s,
+        Err(e) => return push_error(l, e),
+    };
+
+    submit_operation(l, Operation::DropCollection { database, name }, read_settings(l, 1), 3)
+}
+
+/// Async version of collection_stats with callback
+#[lua_function]
+pub unsafe fn collection_stats_async(l: LuaState) -> i32 {
+    let database: Database = match read_userdata(l, 1) {
+        Ok(db) => db,
+        Err(e) => return push_error(l, e),
+    };
+
+    let name = match check_string(l, 2) {
+        Ok(s) => s,
+        Err(e) => return push_error(l, e),
+    };
+
+    submit_operation(l, Operation::CollectionStats { database, name }, read_settings(l, 1), 3)
+}
+
+/// Async version of drop_database with callback
+#[lua_function]
+pub unsafe fn drop_database_async(l: LuaState) -> i32 {
+    let database: Database = match read_userdata(l, 1) {
+        Ok(db) => db,
+        Err(e) => return push_error(l, e),
+    };
+
+    submit_operation(l, Operation::DropDatabase { database }, read_settings(l, 1), 2)
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
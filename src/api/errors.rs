@@ -0,0 +1,30 @@
+use crate::utils::push_bytes;
+use rglua::lua::LuaState;
+use rglua::prelude::*;
+
+/// `tostring(err)` of a `MongoDBError`: its message, so scripts that print
+/// errors keep working
+#[lua_function]
+pub unsafe fn error_tostring(l: LuaState) -> i32 {
+    lua_getfield(l, 1, cstr!("message"));
+    1
+}
+
+/// `..` with a `MongoDBError` on either side concatenates its message
+#[lua_function]
+pub unsafe fn error_concat(l: LuaState) -> i32 {
+    let mut joined = Vec::new();
+    for index in 1..=2 {
+        lua_getglobal(l, cstr!("tostring"));
+        lua_pushvalue(l, index);
+        lua_call(l, 1, 1);
+        let mut len = 0;
+        let ptr = lua_tolstring(l, -1, &mut len);
+        if !ptr.is_null() {
+            joined.extend_from_slice(std::slice::from_raw_parts(ptr as *const u8, len));
+        }
+        lua_pop(l, 1);
+    }
+    push_bytes(l, &joined);
+    1
+}
@@ -0,0 +1,312 @@
+use crate::api::collection_async::reject;
+use crate::api::job::submit_with_handle;
+use crate::core::runtime::CallTimeoutGuard;
+use crate::core::worker::{Operation, LUA_REGISTRYINDEX};
+use crate::error::LuaError;
+use crate::operations::gridfs::{self, GridFsTarget};
+use crate::types::{bson_to_lua_table, bson_value_to_lua, lua_table_to_bson, lua_value_to_bson, Int64ModeGuard};
+use crate::utils::{
+    call_settings, check_bytes, check_string, inherit_settings, opt_string, push_bytes, push_error, push_failure, push_invalid, read_settings, read_userdata, write_userdata,
+};
+use mongodb::bson::Document;
+use mongodb::gridfs::GridFsBucket;
+use mongodb::Database;
+use rglua::lua::LuaState;
+use rglua::prelude::*;
+
+unsafe fn opt_metadata(l: LuaState, index: i32) -> Result<Option<Document>, String> {
+    if !lua_istable(l, index) {
+        return Ok(None);
+    }
+    lua_table_to_bson(l, index)
+        .map(Some)
+        .map_err(|e| e.to_string())
+}
+
+unsafe fn opt_filter(l: LuaState, index: i32) -> Result<Document, String> {
+    if !lua_istable(l, index) {
+        return Ok(Document::new());
+    }
+    lua_table_to_bson(l, index).map_err(|e| e.to_string())
+}
+
+/// Reads a file id or filename. A plain string is a filename; `{ id = ... }`
+/// and `{ name = ... }` say which one is meant, e.g. for string ids.
+unsafe fn check_target(l: LuaState, index: i32) -> Result<GridFsTarget, String> {
+    if lua_isnoneornil(l, index) {
+        return Err("Expected file id or filename".to_string());
+    }
+
+    if lua_istable(l, index) {
+        lua_getfield(l, index, cstr!("id"));
+        if !lua_isnil(l, -1) {
+            let id = lua_value_to_bson(l, -1);
+            lua_pop(l, 1);
+            return id.map(GridFsTarget::Id).map_err(|e| e.to_string());
+        }
+        lua_pop(l, 1);
+
+        lua_getfield(l, index, cstr!("name"));
+        if !lua_isnil(l, -1) {
+            let name = (lua_isstring(l, -1) != 0).then(|| check_string(l, -1));
+            lua_pop(l, 1);
+            return match name {
+                Some(name) => name.map(GridFsTarget::Name).map_err(|e| e.to_string()),
+                None => Err("Expected string 'name'".to_string()),
+            };
+        }
+        lua_pop(l, 1);
+    }
+
+    lua_value_to_bson(l, index)
+        .map(GridFsTarget::from)
+        .map_err(|e| e.to_string())
+}
+
+unsafe fn opt_callback(l: LuaState, index: i32) -> Option<i32> {
+    if lua_isfunction(l, index) {
+        lua_pushvalue(l, index);
+        Some(luaL_ref(l, LUA_REGISTRYINDEX))
+    } else {
+        None
+    }
+}
+
+/// Queues `operation` with the callback at `callback_index` and the optional
+/// call options right after it
+unsafe fn submit(l: LuaState, operation: Operation, callback_index: i32) -> i32 {
+    let settings = match call_settings(l, 1, callback_index + 1) {
+        Ok(settings) => settings,
+        Err(e) => return push_error(l, e),
+    };
+    submit_with_handle(l, operation, settings, opt_callback(l, callback_index), None)
+}
+
+#[lua_function]
+pub unsafe fn get_gridfs_bucket(l: LuaState) -> i32 {
+    let database: Database = match read_userdata(l, 1) {
+        Ok(db) => db,
+        Err(e) => return push_error(l, e),
+    };
+
+    let name = match opt_string(l, 2) {
+        Ok(name) => name,
+        Err(e) => return push_error(l, e),
+    };
+
+    let chunk_size_bytes = if lua_isnumber(l, 3) != 0 {
+        let size = lua_tonumber(l, 3);
+        if !(1.0..=u32::MAX as f64).contains(&size) {
+            return push_error(l, LuaError::InvalidArgument {
+                position: 3,
+                message: "Chunk size must be a positive number of bytes".to_string(),
+            });
+        }
+        Some(size as u32)
+    } else {
+        None
+    };
+
+    let bucket = gridfs::gridfs_bucket(&database, name, chunk_size_bytes);
+
+    write_userdata(l, bucket);
+    luaL_getmetatable(l, cstr!("MongoDBGridFSBucket"));
+    lua_setmetatable(l, -2);
+    inherit_settings(l, 1);
+
+    1
+}
+
+#[lua_function]
+pub unsafe fn gridfs_upload(l: LuaState) -> i32 {
+    let bucket: GridFsBucket = match read_userdata(l, 1) {
+        Ok(b) => b,
+        Err(e) => return push_error(l, e),
+    };
+
+    let filename = match check_string(l, 2) {
+        Ok(s) => s,
+        Err(e) => return push_error(l, e),
+    };
+
+    let data = match check_bytes(l, 3) {
+        Ok(data) => data,
+        Err(e) => return push_error(l, e),
+    };
+
+    let settings = match call_settings(l, 1, 5) {
+        Ok(settings) => settings,
+        Err(e) => return push_error(l, e),
+    };
+
+    let metadata = match opt_metadata(l, 4) {
+        Ok(metadata) => metadata,
+        Err(e) => return push_invalid(l, &settings, "Failed to convert metadata", e),
+    };
+
+    let _int64 = Int64ModeGuard::new(settings.int64);
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    match gridfs::upload(&bucket, filename, data, metadata) {
+        Ok(id) => bson_value_to_lua(l, &id),
+        Err(e) => return push_failure(l, &settings, "Failed to upload file", e),
+    }
+
+    1
+}
+
+#[lua_function]
+pub unsafe fn gridfs_download(l: LuaState) -> i32 {
+    let bucket: GridFsBucket = match read_userdata(l, 1) {
+        Ok(b) => b,
+        Err(e) => return push_error(l, e),
+    };
+
+    let target = match check_target(l, 2) {
+        Ok(target) => target,
+        Err(e) => return push_error(l, e),
+    };
+
+    let settings = match call_settings(l, 1, 3) {
+        Ok(settings) => settings,
+        Err(e) => return push_error(l, e),
+    };
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    match gridfs::download(&bucket, target) {
+        Ok(data) => push_bytes(l, &data),
+        Err(e) => return push_failure(l, &settings, "Failed to download file", e),
+    }
+
+    1
+}
+
+#[lua_function]
+pub unsafe fn gridfs_delete(l: LuaState) -> i32 {
+    let bucket: GridFsBucket = match read_userdata(l, 1) {
+        Ok(b) => b,
+        Err(e) => return push_error(l, e),
+    };
+
+    let target = match check_target(l, 2) {
+        Ok(target) => target,
+        Err(e) => return push_error(l, e),
+    };
+
+    let settings = match call_settings(l, 1, 3) {
+        Ok(settings) => settings,
+        Err(e) => return push_error(l, e),
+    };
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    match gridfs::delete(&bucket, target) {
+        Ok(_) => lua_pushboolean(l, 1),
+        Err(e) => return push_failure(l, &settings, "Failed to delete file", e),
+    }
+
+    1
+}
+
+#[lua_function]
+pub unsafe fn gridfs_find(l: LuaState) -> i32 {
+    let bucket: GridFsBucket = match read_userdata(l, 1) {
+        Ok(b) => b,
+        Err(e) => return push_error(l, e),
+    };
+
+    let settings = match call_settings(l, 1, 3) {
+        Ok(settings) => settings,
+        Err(e) => return push_error(l, e),
+    };
+
+    let filter = match opt_filter(l, 2) {
+        Ok(filter) => filter,
+        Err(e) => return push_invalid(l, &settings, "Failed to convert filter", e),
+    };
+
+    let _int64 = Int64ModeGuard::new(settings.int64);
+    let _timeout = CallTimeoutGuard::new(settings.timeout);
+    match gridfs::find_files(&bucket, filter) {
+        Ok(files) => {
+            lua_newtable(l);
+            for (i, file) in files.iter().enumerate() {
+                bson_to_lua_table(l, file);
+                lua_rawseti(l, -2, (i + 1) as i32);
+            }
+        }
+        Err(e) => return push_failure(l, &settings, "Failed to find files", e),
+    }
+
+    1
+}
+
+/// Async version of gridfs_upload with callback
+#[lua_function]
+pub unsafe fn gridfs_upload_async(l: LuaState) -> i32 {
+    let bucket: GridFsBucket = match read_userdata(l, 1) {
+        Ok(b) => b,
+        Err(e) => return push_error(l, e),
+    };
+
+    let filename = match check_string(l, 2) {
+        Ok(s) => s,
+        Err(e) => return push_error(l, e),
+    };
+
+    let data = match check_bytes(l, 3) {
+        Ok(data) => data,
+        Err(e) => return push_error(l, e),
+    };
+
+    let metadata = match opt_metadata(l, 4) {
+        Ok(metadata) => metadata,
+        Err(e) => return reject(l, read_settings(l, 1), 5, "Failed to convert metadata", e),
+    };
+
+    submit(l, Operation::GridFsUpload { bucket, filename, data, metadata }, 5)
+}
+
+/// Async version of gridfs_download with callback
+#[lua_function]
+pub unsafe fn gridfs_download_async(l: LuaState) -> i32 {
+    let bucket: GridFsBucket = match read_userdata(l, 1) {
+        Ok(b) => b,
+        Err(e) => return push_error(l, e),
+    };
+
+    let target = match check_target(l, 2) {
+        Ok(target) => target,
+        Err(e) => return push_error(l, e),
+    };
+
+    submit(l, Operation::GridFsDownload { bucket, target }, 3)
+}
+
+/// Async version of gridfs_delete with callback
+#[lua_function]
+pub unsafe fn gridfs_delete_async(l: LuaState) -> i32 {
+    let bucket: GridFsBucket = match read_userdata(l, 1) {
+        Ok(b) => b,
+        Err(e) => return push_error(l, e),
+    };
+
+    let target = match check_target(l, 2) {
+        Ok(target) => target,
+        Err(e) => return push_error(l, e),
+    };
+
+    submit(l, Operation::GridFsDelete { bucket, target }, 3)
+}
+
+/// Async version of gridfs_find with callback
+#[lua_function]
+pub unsafe fn gridfs_find_async(l: LuaState) -> i32 {
+    let bucket: GridFsBucket = match read_userdata(l, 1) {
+        Ok(b) => b,
+        Err(e) => return push_error(l, e),
+    };
+
+    let filter = match opt_filter(l, 2) {
+        Ok(filter) => filter,
+        Err(e) => return reject(l, read_settings(l, 1), 3, "Failed to convert filter", e),
+    };
+
+    submit(l, Operation::GridFsFind { bucket, filter }, 3)
+}
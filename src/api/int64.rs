@@ -0,0 +1,154 @@
+use crate::error::LuaError;
+use crate::types::int64::{floor_div, floor_mod};
+use crate::types::{check_int64_operand, push_int64, to_int64};
+use crate::utils::push_error;
+use rglua::lua::LuaState;
+use rglua::prelude::*;
+
+unsafe fn check_operand(l: LuaState, index: i32) -> Result<i64, LuaError> {
+    check_int64_operand(l, index).ok_or_else(|| LuaError::InvalidArgument {
+        position: index as usize,
+        message: "Expected Int64, whole number or integer string".to_string(),
+    })
+}
+
+unsafe fn push_str(l: LuaState, value: &str) {
+    lua_pushlstring(l, value.as_ptr() as _, value.len());
+}
+
+/// Applies `op` to both operands and pushes the result as Int64
+unsafe fn arithmetic(l: LuaState, op: fn(i64, i64) -> Option<i64>, what: &str) -> i32 {
+    let (a, b) = match (check_operand(l, 1), check_operand(l, 2)) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(e), _) | (_, Err(e)) => return push_error(l, e),
+    };
+
+    match op(a, b) {
+        Some(value) => push_int64(l, value),
+        // Only division and modulo fail on a zero divisor
+        None if b == 0 => return push_error(l, LuaError::InvalidArgument {
+            position: 2,
+            message: format!("Int64 {} by zero", what),
+        }),
+        None => return push_error(l, LuaError::TypeConversion {
+            expected: format!("Int64 {} without overflow", what),
+            actual: format!("{} and {}", a, b),
+        }),
+    }
+    1
+}
+
+/// `MongoDB.Int64(value)`: from a whole number, a decimal string or another Int64
+#[lua_function]
+pub unsafe fn new_int64(l: LuaState) -> i32 {
+    match check_operand(l, 1) {
+        Ok(value) => push_int64(l, value),
+        Err(e) => return push_error(l, e),
+    }
+    1
+}
+
+/// Nearest Lua number; loses precision beyond 2^53
+#[lua_function]
+pub unsafe fn int64_to_number(l: LuaState) -> i32 {
+    match check_operand(l, 1) {
+        Ok(value) => lua_pushnumber(l, value as f64),
+        Err(e) => return push_error(l, e),
+    }
+    1
+}
+
+/// Exact decimal digits, also used for `__tostring`
+#[lua_function]
+pub unsafe fn int64_to_string(l: LuaState) -> i32 {
+    match check_operand(l, 1) {
+        Ok(value) => push_str(l, &value.to_string()),
+        Err(e) => return push_error(l, e),
+    }
+    1
+}
+
+#[lua_function]
+pub unsafe fn int64_add(l: LuaState) -> i32 {
+    arithmetic(l, i64::checked_add, "addition")
+}
+
+#[lua_function]
+pub unsafe fn int64_sub(l: LuaState) -> i32 {
+    arithmetic(l, i64::checked_sub, "subtraction")
+}
+
+#[lua_function]
+pub unsafe fn int64_mul(l: LuaState) -> i32 {
+    arithmetic(l, i64::checked_mul, "multiplication")
+}
+
+/// Integer division, rounding toward negative infinity
+#[lua_function]
+pub unsafe fn int64_div(l: LuaState) -> i32 {
+    arithmetic(l, floor_div, "division")
+}
+
+#[lua_function]
+pub unsafe fn int64_mod(l: LuaState) -> i32 {
+    arithmetic(l, floor_mod, "modulo")
+}
+
+#[lua_function]
+pub unsafe fn int64_unm(l: LuaState) -> i32 {
+    match check_operand(l, 1).map(i64::checked_neg) {
+        Ok(Some(value)) => push_int64(l, value),
+        Ok(None) => return push_error(l, LuaError::TypeConversion {
+            expected: "Int64 negation without overflow".to_string(),
+            actual: i64::MIN.to_string(),
+        }),
+        Err(e) => return push_error(l, e),
+    }
+    1
+}
+
+#[lua_function]
+pub unsafe fn int64_concat(l: LuaState) -> i32 {
+    let mut joined = String::new();
+    for index in 1..=2 {
+        match to_int64(l, index) {
+            Some(value) => joined.push_str(&value.to_string()),
+            None if lua_isstring(l, index) != 0 => {
+                let mut len = 0;
+                let ptr = lua_tolstring(l, index, &mut len);
+                joined.push_str(&String::from_utf8_lossy(std::slice::from_raw_parts(ptr as *const u8, len)));
+            }
+            None => return push_error(l, LuaError::InvalidArgument {
+                position: index as usize,
+                message: "Can only concatenate Int64 with strings and numbers".to_string(),
+            }),
+        }
+    }
+    push_str(l, &joined);
+    1
+}
+
+#[lua_function]
+pub unsafe fn int64_eq(l: LuaState) -> i32 {
+    let equal = matches!((to_int64(l, 1), to_int64(l, 2)), (Some(a), Some(b)) if a == b);
+    lua_pushboolean(l, equal as i32);
+    1
+}
+
+#[lua_function]
+pub unsafe fn int64_lt(l: LuaState) -> i32 {
+    match (check_operand(l, 1), check_operand(l, 2)) {
+        (Ok(a), Ok(b)) => lua_pushboolean(l, (a < b) as i32),
+        (Err(e), _) | (_, Err(e)) => return push_error(l, e),
+    }
+    1
+}
+
+#[lua_function]
+pub unsafe fn int64_le(l: LuaState) -> i32 {
+    match (check_operand(l, 1), check_operand(l, 2)) {
+        (Ok(a), Ok(b)) => lua_pushboolean(l, (a <= b) as i32),
+        (Err(e), _) | (_, Err(e)) => return push_error(l, e),
+    }
+    1
+}
@@ -0,0 +1,348 @@
+use crate::api::callbacks::{call_with_results, dispatch_budget, flush_callbacks, set_dispatch_budget};
+use crate::api::collection_async::maybe_register_hook;
+use crate::config::ClientSettings;
+use crate::core::job::{JobHandle, JobStatus};
+use crate::core::queue::{OverflowPolicy, DEFAULT_BLOCK_TIMEOUT};
+use crate::core::session::SessionHandle;
+use crate::core::worker::{decrease_callbacks_pending, get_callbacks_pending, queue_limits, queued_jobs, submit_job, Job, LuaReference, Operation, LUA_REGISTRYINDEX};
+use crate::error::{ErrorCategory, ErrorDetails, LuaError, LuaResult};
+use crate::utils::{check_string, is_udata, push_error, push_error_object, read_userdata, write_userdata};
+use rglua::lua::LuaState;
+use rglua::prelude::*;
+use std::time::Duration;
+
+/// Default bound for `Wait` when no timeout is given, in seconds
+const DEFAULT_WAIT_SECONDS: f64 = 5.0;
+
+unsafe fn check_job(l: LuaState, index: i32) -> LuaResult<JobHandle> {
+    if !is_udata(l, index, cstr!("MongoDBJob")) {
+        return Err(LuaError::InvalidUserdata("Expected MongoDBJob".to_string()));
+    }
+    read_userdata(l, index)
+}
+
+unsafe fn release_callbacks(l: LuaState, refs: Vec<LuaReference>) {
+    for callback in refs {
+        luaL_unref(l, LUA_REGISTRYINDEX, callback);
+    }
+}
+
+/// Pushes the error handed to callbacks and coroutines of a cancelled job
+unsafe fn push_cancelled(l: LuaState) {
+    let mut error = ErrorDetails::new(ErrorCategory::Client, "Job was cancelled");
+    error.kind = Some("cancelled");
+    push_error_object(l, &error);
+}
+
+/// Submits `operation` and pushes its `MongoDBJob` handle, or `false` and
+/// the reason when the job queue turned it away
+pub(crate) unsafe fn submit_with_handle(l: LuaState, operation: Operation, settings: ClientSettings, callback: Option<LuaReference>, session: Option<SessionHandle>) -> i32 {
+    maybe_register_hook(l);
+
+    let handle = JobHandle::new(callback);
+    let job = Job {
+        operation,
+        handle: handle.clone(),
+        session,
+        settings,
+        result: None,
+    };
+
+    match submit_job(job) {
+        Ok(_) => {
+            write_userdata(l, handle);
+            luaL_getmetatable(l, cstr!("MongoDBJob"));
+            lua_setmetatable(l, -2);
+            1
+        }
+        Err(e) => {
+            release_callbacks(l, callback.into_iter().collect());
+            lua_pushboolean(l, 0);
+            push_error_object(l, &ErrorDetails::from(e.to_string()));
+            2
+        }
+    }
+}
+
+/// `job:Status()`: "pending", "running", "done", "failed" or "cancelled"
+#[lua_function]
+pub unsafe fn job_status(l: LuaState) -> i32 {
+    let handle = match check_job(l, 1) {
+        Ok(handle) => handle,
+        Err(e) => return push_error(l, e),
+    };
+
+    let status = handle.status().as_str();
+    lua_pushlstring(l, status.as_ptr() as LuaString, status.len());
+    1
+}
+
+/// `job:Cancel()`: aborts the job and drops its callbacks; returns false
+/// if it had already finished
+#[lua_function]
+pub unsafe fn job_cancel(l: LuaState) -> i32 {
+    let handle = match check_job(l, 1) {
+        Ok(handle) => handle,
+        Err(e) => return push_error(l, e),
+    };
+
+    if !handle.cancel() {
+        lua_pushboolean(l, 0);
+        return 1;
+    }
+
+    // Callbacks are dropped, but coroutines in `Await` must not hang forever
+    let (threads, functions): (Vec<_>, Vec<_>) = std::mem::take(&mut handle.callbacks().refs)
+        .into_iter()
+        .partition(|&callback| {
+            lua_rawgeti(l, LUA_REGISTRYINDEX, callback);
+            let is_thread = lua_isthread(l, -1);
+            lua_pop(l, 1);
+            is_thread
+        });
+    release_callbacks(l, functions);
+    decrease_callbacks_pending(1);
+    if !threads.is_empty() {
+        push_cancelled(l);
+        lua_pushnil(l);
+        call_with_results(l, &threads, true);
+        lua_pop(l, 2);
+    }
+    lua_pushboolean(l, 1);
+    1
+}
+
+/// `job:Wait([timeout])`: blocks the game thread until the job finishes or
+/// `timeout` seconds pass, then runs the callbacks of finished jobs.
+/// Returns whether the job finished in time.
+#[lua_function]
+pub unsafe fn job_wait(l: LuaState) -> i32 {
+    let handle = match check_job(l, 1) {
+        Ok(handle) => handle,
+        Err(e) => return push_error(l, e),
+    };
+
+    let seconds = if lua_isnumber(l, 2) != 0 {
+        lua_tonumber(l, 2).max(0.0)
+    } else {
+        DEFAULT_WAIT_SECONDS
+    };
+
+    let settled = handle.wait(Duration::from_secs_f64(seconds));
+    if settled && handle.status() != JobStatus::Cancelled {
+        flush_callbacks(l);
+    }
+
+    lua_pushboolean(l, settled as i32);
+    1
+}
+
+/// `job:Then(fn)`: calls `fn(err, result)` once the job finishes, right away
+/// if it already has. Returns the job so calls can be chained.
+#[lua_function]
+pub unsafe fn job_then(l: LuaState) -> i32 {
+    let handle = match check_job(l, 1) {
+        Ok(handle) => handle,
+        Err(e) => return push_error(l, e),
+    };
+
+    if !lua_isfunction(l, 2) {
+        return push_error(l, LuaError::InvalidArgument {
+            position: 2,
+            message: "Expected function".to_string(),
+        });
+    }
+
+    let mut callbacks = handle.callbacks();
+    if !callbacks.delivered {
+        if handle.status() != JobStatus::Cancelled {
+            lua_pushvalue(l, 2);
+            callbacks.refs.push(luaL_ref(l, LUA_REGISTRYINDEX));
+        }
+    } else if let Some(results) = callbacks.results {
+        drop(callbacks);
+        lua_rawgeti(l, LUA_REGISTRYINDEX, results);
+        lua_rawgeti(l, -1, 1);
+        lua_rawgeti(l, -2, 2);
+        lua_pushvalue(l, 2);
+        let callback = luaL_ref(l, LUA_REGISTRYINDEX);
+        call_with_results(l, &[callback], true);
+        lua_pop(l, 3);
+    }
+
+    lua_pushvalue(l, 1);
+    1
+}
+
+/// `MongoDB.Await(job)`: suspends the running coroutine until the job
+/// finishes and returns its `err, result`. Errors outside a coroutine.
+#[lua_function]
+pub unsafe fn await_job(l: LuaState) -> i32 {
+    let handle = match check_job(l, 1) {
+        Ok(handle) => handle,
+        Err(e) => return push_error(l, e),
+    };
+
+    if lua_pushthread(l) == 1 {
+        lua_pop(l, 1);
+        return push_error(l, LuaError::InvalidArgument {
+            position: 1,
+            message: "MongoDB.Await must be called from inside a coroutine".to_string(),
+        });
+    }
+
+    let mut callbacks = handle.callbacks();
+    if callbacks.delivered {
+        let results = callbacks.results;
+        drop(callbacks);
+        lua_pop(l, 1);
+        return match results {
+            Some(results) => {
+                lua_rawgeti(l, LUA_REGISTRYINDEX, results);
+                lua_rawgeti(l, -1, 1);
+                lua_rawgeti(l, -2, 2);
+                2
+            }
+            None => {
+                lua_pushnil(l);
+                lua_pushnil(l);
+                2
+            }
+        };
+    }
+    if handle.status() == JobStatus::Cancelled {
+        drop(callbacks);
+        lua_pop(l, 1);
+        push_cancelled(l);
+        lua_pushnil(l);
+        return 2;
+    }
+
+    // Resumed from `poll_callbacks` with `err, result` as the return values
+    callbacks.refs.push(luaL_ref(l, LUA_REGISTRYINDEX));
+    drop(callbacks);
+    lua_yield(l, 0)
+}
+
+/// `MongoDB.SetQueueLimits(options)`: sets how many jobs may wait for the
+/// worker (`capacity`) and what happens to new jobs once that many do
+/// (`overflow`: "reject", "drop_oldest" or "block" for up to `block_timeout`
+/// seconds). Keys left out keep their current value.
+#[lua_function]
+pub unsafe fn set_queue_limits(l: LuaState) -> i32 {
+    if !lua_istable(l, 1) {
+        return push_error(l, LuaError::InvalidArgument {
+            position: 1,
+            message: "Expected table".to_string(),
+        });
+    }
+
+    let mut limits = queue_limits();
+
+    lua_getfield(l, 1, cstr!("capacity"));
+    if lua_isnumber(l, -1) != 0 {
+        limits.capacity = lua_tonumber(l, -1).max(1.0) as usize;
+    }
+    lua_pop(l, 1);
+
+    let mut timeout = match limits.overflow {
+        OverflowPolicy::Block(timeout) => timeout,
+        _ => DEFAULT_BLOCK_TIMEOUT,
+    };
+    lua_getfield(l, 1, cstr!("block_timeout"));
+    if lua_isnumber(l, -1) != 0 {
+        timeout = Duration::from_secs_f64(lua_tonumber(l, -1).max(0.0));
+        if let OverflowPolicy::Block(_) = limits.overflow {
+            limits.overflow = OverflowPolicy::Block(timeout);
+        }
+    }
+    lua_pop(l, 1);
+
+    lua_getfield(l, 1, cstr!("overflow"));
+    if lua_isstring(l, -1) != 0 {
+        let name = match check_string(l, -1) {
+            Ok(name) => name,
+            Err(e) => return push_error(l, e),
+        };
+        lua_pop(l, 1);
+        limits.overflow = match OverflowPolicy::from_name(&name, timeout) {
+            Some(overflow) => overflow,
+            None => {
+                return push_error(l, LuaError::InvalidArgument {
+                    position: 1,
+                    message: format!("Unknown overflow policy '{}', expected 'reject', 'drop_oldest' or 'block'", name),
+                })
+            }
+        };
+    } else {
+        lua_pop(l, 1);
+    }
+
+    crate::core::worker::set_queue_limits(limits);
+    0
+}
+
+/// Reads the number at `key` of the table at `index`; `Some(None)` when it
+/// is zero or negative, meaning no limit
+unsafe fn opt_limit(l: LuaState, index: i32, key: LuaString) -> Option<Option<f64>> {
+    lua_getfield(l, index, key);
+    let value = (lua_isnumber(l, -1) != 0).then(|| lua_tonumber(l, -1)).map(|n| (n > 0.0).then_some(n));
+    lua_pop(l, 1);
+    value
+}
+
+/// `MongoDB.SetCallbackBudget(options)`: limits the time (`milliseconds`)
+/// and number of callbacks (`callbacks`) one tick spends on delivery, and
+/// how many documents of a large result are converted per step
+/// (`chunk_size`). Zero or less removes a limit; keys left out keep their
+/// current value.
+#[lua_function]
+pub unsafe fn set_callback_budget(l: LuaState) -> i32 {
+    if !lua_istable(l, 1) {
+        return push_error(l, LuaError::InvalidArgument {
+            position: 1,
+            message: "Expected table".to_string(),
+        });
+    }
+
+    let mut budget = dispatch_budget();
+    if let Some(milliseconds) = opt_limit(l, 1, cstr!("milliseconds")) {
+        budget.time = milliseconds.map(|ms| Duration::from_secs_f64(ms / 1000.0));
+    }
+    if let Some(callbacks) = opt_limit(l, 1, cstr!("callbacks")) {
+        budget.callbacks = callbacks.map(|n| (n as usize).max(1));
+    }
+    if let Some(chunk_size) = opt_limit(l, 1, cstr!("chunk_size")) {
+        budget.chunk_size = chunk_size.map_or(usize::MAX, |n| (n as usize).max(1));
+    }
+
+    set_dispatch_budget(budget);
+    0
+}
+
+/// `MongoDB.QueueStats()`: `{ queued, pending, capacity }`, where `pending`
+/// also counts running jobs and results not yet delivered
+#[lua_function]
+pub unsafe fn queue_stats(l: LuaState) -> i32 {
+    lua_createtable(l, 0, 3);
+    lua_pushinteger(l, queued_jobs() as _);
+    lua_setfield(l, -2, cstr!("queued"));
+    lua_pushinteger(l, get_callbacks_pending() as _);
+    lua_setfield(l, -2, cstr!("pending"));
+    lua_pushinteger(l, queue_limits().capacity as _);
+    lua_setfield(l, -2, cstr!("capacity"));
+    1
+}
+
+/// `__gc` of `MongoDBJob`; the job itself keeps running
+pub unsafe extern "C" fn job_gc(l: LuaState) -> i32 {
+    if let Ok(handle) = check_job(l, 1) {
+        let results = {
+            let mut callbacks = handle.callbacks();
+            callbacks.released = true;
+            callbacks.results.take()
+        };
+        release_callbacks(l, results.into_iter().collect());
+    }
+    crate::utils::gc_userdata::<JobHandle>(l)
+}
@@ -3,9 +3,33 @@ pub mod database;
 pub mod collection;
 pub mod collection_async;
 pub mod callbacks;
+pub mod cursor;
+pub mod object_id;
+pub mod int64;
+pub mod job;
+pub mod errors;
+pub mod shape;
+#[cfg(feature = "transactions")]
+pub mod session;
+#[cfg(feature = "gridfs")]
+pub mod gridfs;
+#[cfg(feature = "change-streams")]
+pub mod change_streams;
 
 pub use callbacks::*;
 pub use client::*;
 pub use collection::*;
 pub use collection_async::*;
+pub use cursor::*;
+pub use object_id::*;
+pub use int64::*;
+pub use job::*;
+pub use errors::*;
+pub use shape::*;
 pub use database::*;
+#[cfg(feature = "transactions")]
+pub use session::*;
+#[cfg(feature = "gridfs")]
+pub use gridfs::*;
+#[cfg(feature = "change-streams")]
+pub use change_streams::*;
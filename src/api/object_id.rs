@@ -0,0 +1,103 @@
+use crate::error::LuaError;
+use crate::types::{object_id_from_time, push_object_id, to_object_id};
+use crate::utils::push_error;
+use mongodb::bson::oid::ObjectId;
+use rglua::lua::LuaState;
+use rglua::prelude::*;
+
+unsafe fn check_object_id(l: LuaState, index: i32) -> Result<ObjectId, LuaError> {
+    to_object_id(l, index).ok_or_else(|| LuaError::InvalidUserdata("Expected MongoDBObjectId".to_string()))
+}
+
+unsafe fn push_str(l: LuaState, value: &str) {
+    lua_pushlstring(l, value.as_ptr() as _, value.len());
+}
+
+/// `MongoDB.ObjectId([hex])`: parses `hex`, or generates a new id
+#[lua_function]
+pub unsafe fn new_object_id(l: LuaState) -> i32 {
+    if lua_isnoneornil(l, 1) {
+        push_object_id(l, &ObjectId::new());
+        return 1;
+    }
+
+    let hex = match lua_isstring(l, 1) != 0 {
+        true => std::ffi::CStr::from_ptr(lua_tostring(l, 1)).to_string_lossy().into_owned(),
+        false => {
+            return push_error(l, LuaError::InvalidArgument {
+                position: 1,
+                message: "Expected hex string".to_string(),
+            })
+        }
+    };
+
+    match ObjectId::parse_str(&hex) {
+        Ok(oid) => push_object_id(l, &oid),
+        Err(e) => {
+            return push_error(l, LuaError::InvalidArgument {
+                position: 1,
+                message: format!("Invalid ObjectId '{}': {}", hex, e),
+            })
+        }
+    }
+
+    1
+}
+
+/// `MongoDB.ObjectIdFromTime(seconds)`
+#[lua_function]
+pub unsafe fn new_object_id_from_time(l: LuaState) -> i32 {
+    if lua_isnumber(l, 1) == 0 {
+        return push_error(l, LuaError::InvalidArgument {
+            position: 1,
+            message: "Expected unix timestamp in seconds".to_string(),
+        });
+    }
+
+    push_object_id(l, &object_id_from_time(lua_tonumber(l, 1) as u32));
+    1
+}
+
+#[lua_function]
+pub unsafe fn object_id_hex(l: LuaState) -> i32 {
+    match check_object_id(l, 1) {
+        Ok(oid) => push_str(l, &oid.to_hex()),
+        Err(e) => return push_error(l, e),
+    }
+    1
+}
+
+/// Creation time in unix seconds
+#[lua_function]
+pub unsafe fn object_id_timestamp(l: LuaState) -> i32 {
+    match check_object_id(l, 1) {
+        Ok(oid) => lua_pushnumber(l, (oid.timestamp().timestamp_millis() / 1000) as f64),
+        Err(e) => return push_error(l, e),
+    }
+    1
+}
+
+#[lua_function]
+pub unsafe fn object_id_eq(l: LuaState) -> i32 {
+    let equal = matches!((to_object_id(l, 1), to_object_id(l, 2)), (Some(a), Some(b)) if a == b);
+    lua_pushboolean(l, equal as i32);
+    1
+}
+
+#[lua_function]
+pub unsafe fn object_id_lt(l: LuaState) -> i32 {
+    match (check_object_id(l, 1), check_object_id(l, 2)) {
+        (Ok(a), Ok(b)) => lua_pushboolean(l, (a < b) as i32),
+        (Err(e), _) | (_, Err(e)) => return push_error(l, e),
+    }
+    1
+}
+
+#[lua_function]
+pub unsafe fn object_id_le(l: LuaState) -> i32 {
+    match (check_object_id(l, 1), check_object_id(l, 2)) {
+        (Ok(a), Ok(b)) => lua_pushboolean(l, (a <= b) as i32),
+        (Err(e), _) | (_, Err(e)) => return push_error(l, e),
+    }
+    1
+}
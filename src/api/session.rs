@@ -0,0 +1,262 @@
+use crate::core::session::SessionHandle;
+use crate::error::{ErrorDetails, LuaError, LuaResult};
+use crate::log_info;
+use crate::operations;
+use crate::types::lua_table_to_bson;
+use crate::utils::{inherit_settings, is_udata, push_error, push_failure, push_invalid, read_settings, read_userdata, write_userdata};
+use mongodb::bson::{Bson, Document};
+use mongodb::error::{Error, TRANSIENT_TRANSACTION_ERROR};
+use mongodb::options::{Acknowledgment, ReadConcern, TransactionOptions, WriteConcern};
+use mongodb::Client;
+use rglua::lua::LuaState;
+use rglua::prelude::*;
+use std::time::{Duration, Instant};
+
+/// Upper bound for retrying a transaction in `WithTransaction`, same as the driver's helper
+const WITH_TRANSACTION_TIMEOUT: Duration = Duration::from_secs(120);
+
+unsafe fn check_session(l: LuaState, index: i32) -> LuaResult<SessionHandle> {
+    if !is_udata(l, index, cstr!("MongoDBSession")) {
+        return Err(LuaError::InvalidUserdata("Expected MongoDBSession".to_string()));
+    }
+    read_userdata(l, index)
+}
+
+/// Reads `read_concern`, `write_concern` and `max_commit_time_ms` from a Lua options table
+unsafe fn opt_transaction_options(l: LuaState, index: i32) -> LuaResult<Option<TransactionOptions>> {
+    if !lua_istable(l, index) {
+        return Ok(None);
+    }
+
+    let doc: Document = lua_table_to_bson(l, index)?;
+
+    let mut options = TransactionOptions::default();
+
+    if let Ok(level) = doc.get_str("read_concern") {
+        options.read_concern = Some(ReadConcern::custom(level));
+    }
+
+    let acknowledgment = match doc.get("write_concern") {
+        Some(Bson::String(w)) => Some(Acknowledgment::from(w.as_str())),
+        Some(Bson::Int32(w)) => Some(Acknowledgment::from(*w as u32)),
+        Some(Bson::Int64(w)) => Some(Acknowledgment::from(*w as u32)),
+        _ => None,
+    };
+    if let Some(w) = acknowledgment {
+        options.write_concern = Some(WriteConcern::builder().w(w).build());
+    }
+
+    match doc.get("max_commit_time_ms") {
+        Some(Bson::Int32(ms)) => options.max_commit_time = Some(Duration::from_millis(*ms as u64)),
+        Some(Bson::Int64(ms)) => options.max_commit_time = Some(Duration::from_millis(*ms as u64)),
+        _ => {}
+    }
+
+    Ok(Some(options))
+}
+
+#[lua_function]
+pub unsafe fn start_session(l: LuaState) -> i32 {
+    let client: Client = match read_userdata(l, 1) {
+        Ok(c) => c,
+        Err(e) => return push_error(l, e),
+    };
+
+    match operations::start_session(&client) {
+        Ok(session) => {
+            write_userdata(l, session);
+            luaL_getmetatable(l, cstr!("MongoDBSession"));
+            lua_setmetatable(l, -2);
+            inherit_settings(l, 1);
+            1
+        }
+        Err(e) => push_failure(l, &read_settings(l, 1), "Failed to start session", e),
+    }
+}
+
+#[lua_function]
+pub unsafe fn session_start_transaction(l: LuaState) -> i32 {
+    let session = match check_session(l, 1) {
+        Ok(s) => s,
+        Err(e) => return push_error(l, e),
+    };
+    let settings = read_settings(l, 1);
+    let options = match opt_transaction_options(l, 2) {
+        Ok(options) => options,
+        Err(e) => return push_invalid(l, &settings, "Invalid transaction options", e),
+    };
+
+    match operations::start_transaction(&session, options) {
+        Ok(_) => {
+            lua_pushboolean(l, 1);
+            1
+        }
+        Err(e) => push_failure(l, &settings, "Failed to start transaction", e),
+    }
+}
+
+#[lua_function]
+pub unsafe fn session_commit_transaction(l: LuaState) -> i32 {
+    let session = match check_session(l, 1) {
+        Ok(s) => s,
+        Err(e) => return push_error(l, e),
+    };
+
+    match operations::commit_transaction(&session) {
+        Ok(_) => {
+            lua_pushboolean(l, 1);
+            1
+        }
+        Err(e) => push_failure(l, &read_settings(l, 1), "Failed to commit transaction", e),
+    }
+}
+
+#[lua_function]
+pub unsafe fn session_abort_transaction(l: LuaState) -> i32 {
+    let session = match check_session(l, 1) {
+        Ok(s) => s,
+        Err(e) => return push_error(l, e),
+    };
+
+    match operations::abort_transaction(&session) {
+        Ok(_) => {
+            lua_pushboolean(l, 1);
+            1
+        }
+        Err(e) => push_failure(l, &read_settings(l, 1), "Failed to abort transaction", e),
+    }
+}
+
+/// Runs `fn(session)` inside a transaction and commits it.
+///
+/// The whole transaction is retried while the failure carries the
+/// `TransientTransactionError` label. Returns `true`, or `nil, err` once the
+/// transaction was aborted.
+#[lua_function]
+pub unsafe fn session_with_transaction(l: LuaState) -> i32 {
+    let session = match check_session(l, 1) {
+        Ok(s) => s,
+        Err(e) => return push_error(l, e),
+    };
+    if !lua_isfunction(l, 2) {
+        return push_error(l, LuaError::InvalidArgument {
+            position: 2,
+            message: "Expected function".to_string(),
+        });
+    }
+    let settings = read_settings(l, 1);
+    let options = match opt_transaction_options(l, 3) {
+        Ok(options) => options,
+        Err(e) => return push_invalid(l, &settings, "Invalid transaction options", e),
+    };
+
+    let started = Instant::now();
+    let mut attempt = 1;
+
+    loop {
+        session.take_error();
+
+        if let Err(e) = operations::start_transaction(&session, options.clone()) {
+            return push_failure(l, &settings, "Failed to start transaction", e);
+        }
+
+        lua_pushvalue(l, 2);
+        lua_pushvalue(l, 1);
+        let failure = if lua_pcall(l, 1, 0, 0) != 0 {
+            let message = if lua_isstring(l, -1) != 0 {
+                std::ffi::CStr::from_ptr(lua_tostring(l, -1)).to_string_lossy().into_owned()
+            } else {
+                "Transaction callback raised a non-string error".to_string()
+            };
+            lua_pop(l, 1);
+            Some((message, session.take_error()))
+        } else {
+            session.take_error().map(|e| (e.to_string(), Some(e)))
+        };
+
+        let failure = match failure {
+            Some(failure) => Some(failure),
+            None => match operations::commit_transaction(&session) {
+                Ok(_) => None,
+                Err(e) => Some((e.to_string(), session.take_error())),
+            },
+        };
+
+        let Some((message, driver_error)) = failure else {
+            lua_pushboolean(l, 1);
+            return 1;
+        };
+
+        let _ = operations::abort_transaction(&session);
+
+        if should_retry(driver_error.as_ref(), started.elapsed()) {
+            log_info!("Retrying transaction after transient error (attempt {}): {}", attempt, message);
+            attempt += 1;
+            continue;
+        }
+
+        return push_failure(l, &settings, "Transaction failed", failure_details(message, driver_error.as_ref()));
+    }
+}
+
+/// Whether `WithTransaction` runs the transaction again after a failed
+/// attempt: only transient errors are retried, and only within the timeout
+fn should_retry(error: Option<&Error>, elapsed: Duration) -> bool {
+    error.is_some_and(|e| e.contains_label(TRANSIENT_TRANSACTION_ERROR)) && elapsed < WITH_TRANSACTION_TIMEOUT
+}
+
+/// Error returned by `WithTransaction`: the message of whatever ended the
+/// transaction, with the code and labels of the driver error behind it
+fn failure_details(message: String, error: Option<&Error>) -> ErrorDetails {
+    let mut details = match error {
+        Some(e) => ErrorDetails::from(e),
+        None => ErrorDetails::from(message.clone()),
+    };
+    details.message = message;
+    details
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transient_error() -> Error {
+        let concern: mongodb::error::WriteConcernError = mongodb::bson::from_document(mongodb::bson::doc! {
+            "code": 112,
+            "codeName": "WriteConflict",
+            "errmsg": "write conflict",
+            "errorLabels": [TRANSIENT_TRANSACTION_ERROR],
+        })
+        .unwrap();
+        mongodb::error::ErrorKind::Write(mongodb::error::WriteFailure::WriteConcernError(concern)).into()
+    }
+
+    #[test]
+    fn test_retry_only_transient_errors() {
+        let transient = transient_error();
+        let command: mongodb::error::CommandError = mongodb::bson::from_document(mongodb::bson::doc! {
+            "code": 11000,
+            "codeName": "DuplicateKey",
+            "errmsg": "duplicate key",
+        })
+        .unwrap();
+        let other: Error = mongodb::error::ErrorKind::Command(command).into();
+
+        assert!(should_retry(Some(&transient), Duration::ZERO));
+        assert!(!should_retry(Some(&other), Duration::ZERO));
+        assert!(!should_retry(None, Duration::ZERO));
+        assert!(!should_retry(Some(&transient), WITH_TRANSACTION_TIMEOUT));
+    }
+
+    #[test]
+    fn test_failure_keeps_callback_message() {
+        let details = failure_details("callback exploded".to_string(), Some(&transient_error()));
+        assert_eq!(details.message, "callback exploded");
+        assert_eq!(details.code, Some(112));
+        assert_eq!(details.labels, vec![TRANSIENT_TRANSACTION_ERROR.to_string()]);
+
+        let details = failure_details("callback exploded".to_string(), None);
+        assert_eq!(details.message, "callback exploded");
+        assert!(details.labels.is_empty());
+    }
+}
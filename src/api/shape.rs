@@ -0,0 +1,34 @@
+use crate::error::LuaError;
+use crate::types::{set_table_shape, TableShape};
+use crate::utils::push_error;
+use rglua::lua::LuaState;
+use rglua::prelude::*;
+
+/// Marks the table argument, or a new empty table, with `shape` and returns it
+unsafe fn mark_table(l: LuaState, shape: TableShape) -> i32 {
+    if lua_isnoneornil(l, 1) {
+        lua_newtable(l);
+    } else if lua_istable(l, 1) {
+        lua_pushvalue(l, 1);
+    } else {
+        return push_error(l, LuaError::InvalidArgument {
+            position: 1,
+            message: "Expected table or nil".to_string(),
+        });
+    }
+
+    set_table_shape(l, shape);
+    1
+}
+
+/// `MongoDB.Array([t])`: `t` is always written as a BSON array, even when empty
+#[lua_function]
+pub unsafe fn new_array(l: LuaState) -> i32 {
+    mark_table(l, TableShape::Array)
+}
+
+/// `MongoDB.Document([t])`: `t` is always written as an embedded document
+#[lua_function]
+pub unsafe fn new_document(l: LuaState) -> i32 {
+    mark_table(l, TableShape::Document)
+}
@@ -1,6 +1,8 @@
 use std::time::Duration;
  use mongodb::options::{ClientOptions, ServerApi, ServerApiVersion};
+use crate::core::queue::Limiter;
 use crate::error::{ConfigError, ConfigResult};
+use crate::types::Int64Mode;
 
 #[derive(Debug, Clone)]
 pub struct ConnectionConfig {
@@ -10,7 +12,6 @@ pub struct ConnectionConfig {
     pub min_pool_size: Option<u32>,
     pub server_selection_timeout: Duration,
     pub connect_timeout: Duration,
-    pub socket_timeout: Option<Duration>,
     pub max_idle_time: Option<Duration>,
     pub retry_writes: bool,
     pub retry_reads: bool,
@@ -27,7 +28,6 @@ impl Default for ConnectionConfig {
             min_pool_size: Some(10),
             server_selection_timeout: Duration::from_secs(30),
             connect_timeout: Duration::from_secs(10),
-            socket_timeout: None,
             max_idle_time: Some(Duration::from_secs(600)),
             retry_writes: true,
             retry_reads: true,
@@ -117,6 +117,32 @@ impl ConnectionConfig {
     }
 }
 
+/// Behaviour of the Lua bindings chosen per client
+///
+/// Databases, collections and everything opened from them inherit the
+/// settings of the client they came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientSettings {
+    pub int64: Int64Mode,
+    /// The client's concurrency limit
+    pub limiter: Option<Limiter>,
+    /// Whether failed calls are also written to the server console
+    pub log_errors: bool,
+    /// How long a call may take before it fails with a timeout error
+    pub timeout: Option<Duration>,
+}
+
+impl Default for ClientSettings {
+    fn default() -> Self {
+        Self {
+            int64: Int64Mode::default(),
+            limiter: None,
+            log_errors: true,
+            timeout: None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -40,7 +40,7 @@ impl MongoConnection {
                 .database("admin")
                 .run_command(mongodb::bson::doc! {"ping": 1})
                 .await
-                .map_err(|e| MongoError::Connection(format!("Ping failed: {}", e)))?;
+                .map_err(|e| MongoError::classify("Ping failed", e, MongoError::Network))?;
             Ok(())
         })
     }
@@ -59,7 +59,7 @@ impl MongoConnection {
             client
                 .list_database_names()
                 .await
-                .map_err(|e| MongoError::Operation(e.to_string()))
+                .map_err(|e| MongoError::driver("List databases failed", e))
         })
     }
 
@@ -79,7 +79,7 @@ pub fn database_exists(client: &Client, db_name: &str) -> MongoResult<bool> {
         let names = client
             .list_database_names()
             .await
-            .map_err(|e| MongoError::Operation(e.to_string()))?;
+            .map_err(|e| MongoError::driver("List databases failed", e))?;
         Ok(names.contains(&db_name))
     })
 }
@@ -91,7 +91,7 @@ pub fn collection_exists(database: &Database, collection_name: &str) -> MongoRes
         let names = database
             .list_collection_names()
             .await
-            .map_err(|e| MongoError::Operation(e.to_string()))?;
+            .map_err(|e| MongoError::driver("List collections failed", e))?;
         Ok(names.contains(&collection_name))
     })
 }
@@ -0,0 +1,100 @@
+//! Server-side cursors kept open across Lua calls
+//!
+//! Wraps a driver `Cursor` so Lua can pull documents in batches instead of
+//! receiving the whole result set at once.
+
+use crate::core::runtime::MONGO_RUNTIME;
+use futures::TryStreamExt;
+use mongodb::bson::Document;
+use mongodb::Cursor;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+struct CursorState {
+    cursor: Option<Cursor<Document>>,
+    peeked: Option<Document>,
+}
+
+impl CursorState {
+    async fn next(&mut self) -> mongodb::error::Result<Option<Document>> {
+        if let Some(doc) = self.peeked.take() {
+            return Ok(Some(doc));
+        }
+
+        let Some(cursor) = self.cursor.as_mut() else {
+            return Ok(None);
+        };
+
+        let next = cursor.try_next().await;
+        if !matches!(next, Ok(Some(_))) {
+            self.close();
+        }
+        next
+    }
+
+    fn close(&mut self) {
+        if let Some(cursor) = self.cursor.take() {
+            // Dropping a live cursor schedules `killCursors` on the current runtime
+            let _guard = MONGO_RUNTIME.enter();
+            drop(cursor);
+        }
+    }
+}
+
+impl Drop for CursorState {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[derive(Clone)]
+pub struct CursorHandle {
+    inner: Arc<Mutex<CursorState>>,
+}
+
+impl std::fmt::Debug for CursorHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CursorHandle").finish_non_exhaustive()
+    }
+}
+
+impl CursorHandle {
+    pub fn new(cursor: Cursor<Document>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(CursorState {
+                cursor: Some(cursor),
+                peeked: None,
+            })),
+        }
+    }
+
+    /// Reads up to `count` documents; fewer are returned once the cursor is exhausted.
+    pub async fn next_batch(&self, count: usize) -> mongodb::error::Result<Vec<Document>> {
+        let mut state = self.inner.lock().await;
+        let mut documents = Vec::with_capacity(count.min(1024));
+
+        while documents.len() < count {
+            match state.next().await? {
+                Some(doc) => documents.push(doc),
+                None => break,
+            }
+        }
+
+        Ok(documents)
+    }
+
+    /// Fetches ahead if needed to tell whether another document is available.
+    pub async fn has_next(&self) -> mongodb::error::Result<bool> {
+        let mut state = self.inner.lock().await;
+        if state.peeked.is_none() {
+            state.peeked = state.next().await?;
+        }
+        Ok(state.peeked.is_some())
+    }
+
+    pub async fn close(&self) {
+        let mut state = self.inner.lock().await;
+        state.peeked = None;
+        state.close();
+    }
+}
@@ -0,0 +1,190 @@
+//! Handles for jobs submitted to the async worker
+//!
+//! The worker and the Lua handle returned by every `*Async` call share a
+//! `JobHandle`. Status changes happen under one lock, so a job ends up
+//! either cancelled or delivered, never both: whichever side moves it out
+//! of `Pending`/`Running` first owns its callbacks.
+
+use crate::core::worker::LuaReference;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::task::AbortHandle;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Running => "running",
+            Self::Done => "done",
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn is_finished(self) -> bool {
+        !matches!(self, Self::Pending | Self::Running)
+    }
+}
+
+#[derive(Debug)]
+struct JobState {
+    status: JobStatus,
+    /// The outcome is in the callback queue, or the job was cancelled
+    settled: bool,
+    abort: Option<AbortHandle>,
+}
+
+/// Lua side of a job, only touched from the game thread
+#[derive(Debug, Default)]
+pub struct Callbacks {
+    pub refs: Vec<LuaReference>,
+    /// `{ err, result }` kept for `Then` calls made after delivery
+    pub results: Option<LuaReference>,
+    pub delivered: bool,
+    /// The Lua handle was collected, so nobody can call `Then` any more
+    pub released: bool,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<JobState>,
+    settled: Condvar,
+    callbacks: Mutex<Callbacks>,
+}
+
+#[derive(Debug, Clone)]
+pub struct JobHandle(Arc<Shared>);
+
+impl JobHandle {
+    pub fn new(callback: Option<LuaReference>) -> Self {
+        Self(Arc::new(Shared {
+            state: Mutex::new(JobState {
+                status: JobStatus::Pending,
+                settled: false,
+                abort: None,
+            }),
+            settled: Condvar::new(),
+            callbacks: Mutex::new(Callbacks {
+                refs: callback.into_iter().collect(),
+                ..Default::default()
+            }),
+        }))
+    }
+
+    fn state(&self) -> MutexGuard<'_, JobState> {
+        self.0.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn status(&self) -> JobStatus {
+        self.state().status
+    }
+
+    /// Marks a queued job as running; false if it was cancelled before it started
+    pub fn start(&self) -> bool {
+        let mut state = self.state();
+        if state.status != JobStatus::Pending {
+            return false;
+        }
+        state.status = JobStatus::Running;
+        true
+    }
+
+    /// Remembers how to abort the task running the job, aborting right away
+    /// if the job was cancelled before the task was spawned
+    pub fn set_abort(&self, abort: AbortHandle) {
+        let mut state = self.state();
+        if state.status == JobStatus::Cancelled {
+            abort.abort();
+        } else if !state.status.is_finished() {
+            state.abort = Some(abort);
+        }
+    }
+
+    /// Records the outcome; false if the job was cancelled meanwhile and
+    /// its result must be dropped
+    pub fn finish(&self, failed: bool) -> bool {
+        let mut state = self.state();
+        if state.status.is_finished() {
+            return false;
+        }
+        state.status = if failed { JobStatus::Failed } else { JobStatus::Done };
+        state.abort = None;
+        true
+    }
+
+    /// Wakes `wait` once the outcome is queued for delivery
+    pub fn settle(&self) {
+        self.state().settled = true;
+        self.0.settled.notify_all();
+    }
+
+    /// Cancels the job unless it already finished, aborting its task.
+    /// Returns whether the job was cancelled by this call.
+    pub fn cancel(&self) -> bool {
+        let mut state = self.state();
+        if state.status.is_finished() {
+            return false;
+        }
+        state.status = JobStatus::Cancelled;
+        state.settled = true;
+        if let Some(abort) = state.abort.take() {
+            abort.abort();
+        }
+        drop(state);
+        self.0.settled.notify_all();
+        true
+    }
+
+    /// Blocks until the job settles or `timeout` passes; returns whether it settled
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let state = self.state();
+        let (state, _) = self
+            .0
+            .settled
+            .wait_timeout_while(state, timeout, |state| !state.settled)
+            .unwrap_or_else(|e| e.into_inner());
+        state.settled
+    }
+
+    pub fn callbacks(&self) -> MutexGuard<'_, Callbacks> {
+        self.0.callbacks.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_lifecycle() {
+        let handle = JobHandle::new(Some(1));
+        assert_eq!(handle.status(), JobStatus::Pending);
+        assert!(handle.start());
+        assert_eq!(handle.status(), JobStatus::Running);
+        assert!(handle.finish(true));
+        assert_eq!(handle.status(), JobStatus::Failed);
+        assert!(!handle.cancel());
+        assert!(!handle.wait(Duration::ZERO));
+        handle.settle();
+        assert!(handle.wait(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_cancel_before_start() {
+        let handle = JobHandle::new(None);
+        assert!(handle.cancel());
+        assert!(!handle.start());
+        assert!(!handle.finish(false));
+        assert_eq!(handle.status(), JobStatus::Cancelled);
+        assert!(handle.wait(Duration::ZERO));
+    }
+}
@@ -1,3 +1,7 @@
 pub mod runtime;
 pub mod connection;
 pub mod worker;
+pub mod job;
+pub mod queue;
+pub mod session;
+pub mod cursor;
@@ -0,0 +1,235 @@
+//! Bounded queue between the game thread and the async worker
+//!
+//! Jobs wait here until the worker has a free slot for their client. Once
+//! the queue is full, the overflow policy decides whether a new job is
+//! rejected, replaces the oldest queued job, or waits briefly for room.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+
+pub const DEFAULT_CAPACITY: usize = 10_000;
+pub const DEFAULT_BLOCK_TIMEOUT: Duration = Duration::from_millis(50);
+/// Jobs a client runs at once unless `max_concurrent_jobs` says otherwise
+pub const DEFAULT_MAX_CONCURRENT_JOBS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    Reject,
+    DropOldest,
+    /// Waits up to the given time for room, then rejects
+    Block(Duration),
+}
+
+impl OverflowPolicy {
+    pub fn from_name(name: &str, timeout: Duration) -> Option<Self> {
+        match name {
+            "reject" => Some(Self::Reject),
+            "drop_oldest" => Some(Self::DropOldest),
+            "block" => Some(Self::Block(timeout)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueLimits {
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for QueueLimits {
+    fn default() -> Self {
+        Self {
+            capacity: DEFAULT_CAPACITY,
+            overflow: OverflowPolicy::Reject,
+        }
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum SubmitError {
+    #[error("Job queue is full")]
+    Full,
+}
+
+#[derive(Debug)]
+struct State<T> {
+    items: VecDeque<T>,
+    limits: QueueLimits,
+}
+
+#[derive(Debug)]
+pub struct BoundedQueue<T> {
+    state: Mutex<State<T>>,
+    /// Signalled when an item is taken, for `Block` pushes
+    space: Condvar,
+    /// Signalled when an item is added, for the consumer
+    ready: Notify,
+}
+
+impl<T> BoundedQueue<T> {
+    pub fn new(limits: QueueLimits) -> Self {
+        Self {
+            state: Mutex::new(State {
+                items: VecDeque::new(),
+                limits,
+            }),
+            space: Condvar::new(),
+            ready: Notify::new(),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn limits(&self) -> QueueLimits {
+        self.state().limits
+    }
+
+    /// New limits apply to later pushes; queued items are kept
+    pub fn set_limits(&self, limits: QueueLimits) {
+        self.state().limits = limits;
+        self.space.notify_all();
+    }
+
+    pub fn len(&self) -> usize {
+        self.state().items.len()
+    }
+
+    /// Queues `item`, returning the item evicted by `DropOldest`, or the
+    /// rejected item itself when there is no room
+    pub fn push(&self, item: T) -> Result<Option<T>, T> {
+        let mut state = self.state();
+        let mut evicted = None;
+
+        if state.items.len() >= state.limits.capacity {
+            match state.limits.overflow {
+                OverflowPolicy::Reject => return Err(item),
+                OverflowPolicy::DropOldest => match state.items.pop_front() {
+                    Some(oldest) => evicted = Some(oldest),
+                    None => return Err(item),
+                },
+                OverflowPolicy::Block(timeout) => {
+                    let (waited, _) = self
+                        .space
+                        .wait_timeout_while(state, timeout, |state| state.items.len() >= state.limits.capacity)
+                        .unwrap_or_else(|e| e.into_inner());
+                    state = waited;
+                    if state.items.len() >= state.limits.capacity {
+                        return Err(item);
+                    }
+                }
+            }
+        }
+
+        state.items.push_back(item);
+        drop(state);
+        self.ready.notify_one();
+        Ok(evicted)
+    }
+
+    fn try_pop(&self) -> Option<T> {
+        let item = self.state().items.pop_front();
+        if item.is_some() {
+            self.space.notify_one();
+        }
+        item
+    }
+
+    /// Waits for the next item; meant for a single consumer
+    pub async fn pop(&self) -> T {
+        loop {
+            if let Some(item) = self.try_pop() {
+                return item;
+            }
+            self.ready.notified().await;
+        }
+    }
+}
+
+/// Concurrency limit shared by a client and everything opened from it.
+/// Queued jobs carry it in their settings, so it outlives the client.
+#[derive(Debug, Clone)]
+pub struct Limiter(Arc<Semaphore>);
+
+impl Limiter {
+    pub fn new(max_concurrent: usize) -> Self {
+        Self(Arc::new(Semaphore::new(max_concurrent.clamp(1, Semaphore::MAX_PERMITS))))
+    }
+
+    /// Waits for a free slot, held until the permit is dropped
+    pub async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        self.0.clone().acquire_owned().await.ok()
+    }
+}
+
+impl PartialEq for Limiter {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(overflow: OverflowPolicy) -> BoundedQueue<i32> {
+        BoundedQueue::new(QueueLimits { capacity: 2, overflow })
+    }
+
+    #[test]
+    fn test_reject_when_full() {
+        let queue = queue(OverflowPolicy::Reject);
+        assert_eq!(queue.push(1), Ok(None));
+        assert_eq!(queue.push(2), Ok(None));
+        assert_eq!(queue.push(3), Err(3));
+        assert_eq!(queue.try_pop(), Some(1));
+        assert_eq!(queue.push(3), Ok(None));
+        assert_eq!(queue.len(), 2);
+    }
+
+    #[test]
+    fn test_drop_oldest() {
+        let queue = queue(OverflowPolicy::DropOldest);
+        queue.push(1).unwrap();
+        queue.push(2).unwrap();
+        assert_eq!(queue.push(3), Ok(Some(1)));
+        assert_eq!(queue.try_pop(), Some(2));
+        assert_eq!(queue.try_pop(), Some(3));
+    }
+
+    #[test]
+    fn test_block_times_out() {
+        let queue = queue(OverflowPolicy::Block(Duration::from_millis(1)));
+        queue.push(1).unwrap();
+        queue.push(2).unwrap();
+        assert_eq!(queue.push(3), Err(3));
+    }
+
+    #[test]
+    fn test_overflow_policy_names() {
+        let timeout = Duration::from_millis(10);
+        assert_eq!(OverflowPolicy::from_name("drop_oldest", timeout), Some(OverflowPolicy::DropOldest));
+        assert_eq!(OverflowPolicy::from_name("block", timeout), Some(OverflowPolicy::Block(timeout)));
+        assert_eq!(OverflowPolicy::from_name("wait", timeout), None);
+    }
+
+    #[test]
+    fn test_limiter_shared_by_clones() {
+        let limiter = Limiter::new(2);
+        let inherited = limiter.clone();
+        assert_eq!(limiter, inherited);
+        assert_ne!(limiter, Limiter::new(2));
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let permit = runtime.block_on(inherited.acquire());
+        assert!(permit.is_some());
+        assert_eq!(limiter.0.available_permits(), 1);
+        drop(permit);
+        assert_eq!(limiter.0.available_permits(), 2);
+    }
+}
@@ -1,6 +1,9 @@
 use once_cell::sync::Lazy;
 use tokio::runtime::Runtime;
+use std::cell::Cell;
 use std::sync::Arc;
+use std::time::Duration;
+use crate::error::{MongoError, MongoResult};
 
 pub static MONGO_RUNTIME: Lazy<Arc<Runtime>> = Lazy::new(|| {
     Arc::new(
@@ -28,6 +31,45 @@ where
     MONGO_RUNTIME.block_on(future)
 }
 
+thread_local! {
+    static CALL_TIMEOUT: Cell<Option<Duration>> = const { Cell::new(None) };
+}
+
+/// Bounds the blocking calls made on this thread until dropped
+pub struct CallTimeoutGuard {
+    previous: Option<Duration>,
+}
+
+impl CallTimeoutGuard {
+    pub fn new(timeout: Option<Duration>) -> Self {
+        let previous = CALL_TIMEOUT.with(|current| current.replace(timeout));
+        Self { previous }
+    }
+}
+
+impl Drop for CallTimeoutGuard {
+    fn drop(&mut self) {
+        CALL_TIMEOUT.with(|current| current.set(self.previous));
+    }
+}
+
+/// `block_on` for driver calls, giving up once the timeout set by the
+/// current `CallTimeoutGuard` has passed
+pub fn block_on_timed<F, T>(future: F) -> MongoResult<T>
+where
+    F: std::future::Future<Output = MongoResult<T>> + Send + 'static,
+    T: Send + 'static,
+{
+    match CALL_TIMEOUT.with(|timeout| timeout.get()) {
+        Some(timeout) => block_on(async move {
+            tokio::time::timeout(timeout, future)
+                .await
+                .unwrap_or_else(|_| Err(MongoError::Expired(timeout.as_millis() as u64)))
+        }),
+        None => block_on(future),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -43,4 +85,17 @@ mod tests {
         let cpus = num_cpus();
         assert!(cpus >= 4 && cpus <= 16);
     }
+
+    #[test]
+    fn test_block_on_timed() {
+        let _timeout = CallTimeoutGuard::new(Some(Duration::from_millis(10)));
+        let result: MongoResult<()> = block_on_timed(async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(())
+        });
+        assert!(matches!(result, Err(MongoError::Expired(10))));
+
+        drop(_timeout);
+        assert_eq!(block_on_timed(async { Ok(42) }).unwrap(), 42);
+    }
 }
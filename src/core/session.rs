@@ -0,0 +1,65 @@
+//! Shared client sessions
+//!
+//! Wraps a driver `ClientSession` so it can live inside Lua userdata and be
+//! handed to both the blocking operations and the async worker.
+
+use mongodb::error::Error;
+use mongodb::ClientSession;
+use std::sync::Arc;
+use tokio::sync::{Mutex, MutexGuard};
+
+#[derive(Clone)]
+pub struct SessionHandle {
+    inner: Arc<Mutex<ClientSession>>,
+    last_error: Arc<std::sync::Mutex<Option<Error>>>,
+}
+
+impl std::fmt::Debug for SessionHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionHandle").finish_non_exhaustive()
+    }
+}
+
+impl SessionHandle {
+    pub fn new(session: ClientSession) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(session)),
+            last_error: Arc::new(std::sync::Mutex::new(None)),
+        }
+    }
+
+    pub async fn lock(&self) -> MutexGuard<'_, ClientSession> {
+        self.inner.lock().await
+    }
+
+    /// Remembers the last failure of an operation run on this session, so
+    /// `WithTransaction` can decide whether to abort, retry or give up even
+    /// when the Lua code swallowed the error.
+    pub fn observe<T>(&self, result: &Result<T, Error>) {
+        if let Err(e) = result {
+            if let Ok(mut last_error) = self.last_error.lock() {
+                *last_error = Some(e.clone());
+            }
+        }
+    }
+
+    pub fn take_error(&self) -> Option<Error> {
+        self.last_error.lock().ok().and_then(|mut e| e.take())
+    }
+}
+
+/// Runs a driver action, attaching the session when one is given.
+#[macro_export]
+macro_rules! with_session {
+    ($action:expr, $session:expr) => {
+        match $session {
+            Some(session) => {
+                let mut guard = session.lock().await;
+                let result = $action.session(&mut *guard).await;
+                session.observe(&result);
+                result
+            }
+            None => $action.await,
+        }
+    };
+}
@@ -1,7 +1,23 @@
+use crate::config::ClientSettings;
 use crate::core::runtime::MONGO_RUNTIME;
+use crate::core::cursor::CursorHandle;
+use crate::core::job::JobHandle;
+use crate::core::queue::{BoundedQueue, Limiter, QueueLimits, SubmitError};
+use crate::core::session::SessionHandle;
+use crate::error::{ErrorDetails, MongoError};
+use crate::operations::{
+    aggregate_documents, count_matching, create_index_models, find_documents, group_pipeline, has_collection, index_model, is_acknowledged, list_index_documents,
+    ordered_ids, rename_command, run_admin_command, run_bulk_write, text_index_keys, BulkWriteSummary, WriteResult,
+};
+#[cfg(feature = "gridfs")]
+use crate::operations::gridfs::{self, GridFsTarget};
+#[cfg(feature = "change-streams")]
+use crate::operations::change_streams::{self, WatchControl, WatchTarget};
+use crate::with_session;
 use once_cell::sync::Lazy;
 use std::sync::atomic::AtomicUsize;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 pub type LuaReference = i32;
 pub const LUA_REGISTRYINDEX: i32 = -10000;
@@ -9,10 +25,10 @@ pub const LUA_REGISTRYINDEX: i32 = -10000;
 static CALLBACKS_PENDING: AtomicUsize = AtomicUsize::new(0);
 static HOOK_REGISTERED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
 
-pub static JOB_QUEUE: Lazy<tokio::sync::mpsc::UnboundedSender<Job>> = Lazy::new(|| {
-    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
-    spawn_worker(rx);
-    tx
+pub static JOB_QUEUE: Lazy<Arc<BoundedQueue<Job>>> = Lazy::new(|| {
+    let queue = Arc::new(BoundedQueue::new(QueueLimits::default()));
+    spawn_worker(queue.clone());
+    queue
 });
 
 pub static CALLBACK_QUEUE: Lazy<Mutex<(std::sync::mpsc::Sender<Job>, std::sync::mpsc::Receiver<Job>)>> =
@@ -21,7 +37,9 @@ pub static CALLBACK_QUEUE: Lazy<Mutex<(std::sync::mpsc::Sender<Job>, std::sync::
 #[derive(Debug)]
 pub struct Job {
     pub operation: Operation,
-    pub callback: Option<LuaReference>,
+    pub handle: JobHandle,
+    pub session: Option<SessionHandle>,
+    pub settings: ClientSettings,
     pub result: Option<JobResult>,
 }
 
@@ -38,11 +56,29 @@ pub enum Operation {
     Find {
         collection: mongodb::Collection<mongodb::bson::Document>,
         filter: mongodb::bson::Document,
-        limit: Option<i64>,
+        options: Box<mongodb::options::FindOptions>,
     },
     FindOne {
         collection: mongodb::Collection<mongodb::bson::Document>,
         filter: mongodb::bson::Document,
+        options: Box<mongodb::options::FindOneOptions>,
+    },
+    FindOneAndUpdate {
+        collection: mongodb::Collection<mongodb::bson::Document>,
+        filter: mongodb::bson::Document,
+        update: mongodb::bson::Document,
+        options: Box<mongodb::options::FindOneAndUpdateOptions>,
+    },
+    FindOneAndReplace {
+        collection: mongodb::Collection<mongodb::bson::Document>,
+        filter: mongodb::bson::Document,
+        replacement: mongodb::bson::Document,
+        options: Box<mongodb::options::FindOneAndReplaceOptions>,
+    },
+    FindOneAndDelete {
+        collection: mongodb::Collection<mongodb::bson::Document>,
+        filter: mongodb::bson::Document,
+        options: Box<mongodb::options::FindOneAndDeleteOptions>,
     },
     UpdateOne {
         collection: mongodb::Collection<mongodb::bson::Document>,
@@ -56,6 +92,12 @@ pub enum Operation {
         update: mongodb::bson::Document,
         upsert: bool,
     },
+    ReplaceOne {
+        collection: mongodb::Collection<mongodb::bson::Document>,
+        filter: mongodb::bson::Document,
+        replacement: mongodb::bson::Document,
+        upsert: bool,
+    },
     DeleteOne {
         collection: mongodb::Collection<mongodb::bson::Document>,
         filter: mongodb::bson::Document,
@@ -68,84 +110,330 @@ pub enum Operation {
         collection: mongodb::Collection<mongodb::bson::Document>,
         filter: mongodb::bson::Document,
     },
+    EstimatedCount {
+        collection: mongodb::Collection<mongodb::bson::Document>,
+    },
+    Distinct {
+        collection: mongodb::Collection<mongodb::bson::Document>,
+        field: String,
+        filter: mongodb::bson::Document,
+    },
+    BulkWrite {
+        collection: mongodb::Collection<mongodb::bson::Document>,
+        models: Vec<mongodb::options::WriteModel>,
+        options: Box<mongodb::options::BulkWriteOptions>,
+    },
+    CountAggregate {
+        collection: mongodb::Collection<mongodb::bson::Document>,
+        filter: mongodb::bson::Document,
+    },
     Aggregate {
         collection: mongodb::Collection<mongodb::bson::Document>,
         pipeline: Vec<mongodb::bson::Document>,
     },
+    GroupBy {
+        collection: mongodb::Collection<mongodb::bson::Document>,
+        group_field: String,
+        accumulator_field: Option<String>,
+    },
+    CreateIndex {
+        collection: mongodb::Collection<mongodb::bson::Document>,
+        keys: mongodb::bson::Document,
+        unique: bool,
+        name: Option<String>,
+    },
+    CreateIndexes {
+        collection: mongodb::Collection<mongodb::bson::Document>,
+        indexes: Vec<(mongodb::bson::Document, bool, Option<String>)>,
+    },
+    ListIndexes {
+        collection: mongodb::Collection<mongodb::bson::Document>,
+    },
+    DropIndex {
+        collection: mongodb::Collection<mongodb::bson::Document>,
+        name: String,
+    },
+    CreateTextIndex {
+        collection: mongodb::Collection<mongodb::bson::Document>,
+        fields: Vec<String>,
+        name: Option<String>,
+    },
+    DropIndexes {
+        collection: mongodb::Collection<mongodb::bson::Document>,
+    },
+    ListDatabases {
+        client: mongodb::Client,
+    },
+    ListCollections {
+        database: mongodb::Database,
+    },
+    CreateCollection {
+        database: mongodb::Database,
+        name: String,
+    },
+    DropCollection {
+        database: mongodb::Database,
+        name: String,
+    },
+    CollectionStats {
+        database: mongodb::Database,
+        name: String,
+    },
+    DropDatabase {
+        database: mongodb::Database,
+    },
+    RenameCollection {
+        database: mongodb::Database,
+        old_name: String,
+        new_name: String,
+        drop_target: bool,
+    },
+    CollectionExists {
+        database: mongodb::Database,
+        name: String,
+    },
+    CursorNext {
+        cursor: CursorHandle,
+        count: usize,
+    },
+    #[cfg(feature = "gridfs")]
+    GridFsUpload {
+        bucket: mongodb::gridfs::GridFsBucket,
+        filename: String,
+        data: Vec<u8>,
+        metadata: Option<mongodb::bson::Document>,
+    },
+    #[cfg(feature = "gridfs")]
+    GridFsDownload {
+        bucket: mongodb::gridfs::GridFsBucket,
+        target: GridFsTarget,
+    },
+    #[cfg(feature = "gridfs")]
+    GridFsDelete {
+        bucket: mongodb::gridfs::GridFsBucket,
+        target: GridFsTarget,
+    },
+    #[cfg(feature = "gridfs")]
+    GridFsFind {
+        bucket: mongodb::gridfs::GridFsBucket,
+        filter: mongodb::bson::Document,
+    },
+    #[cfg(feature = "change-streams")]
+    Watch {
+        target: WatchTarget,
+        pipeline: Vec<mongodb::bson::Document>,
+        options: Box<mongodb::options::ChangeStreamOptions>,
+        control: WatchControl,
+    },
+}
+
+impl Operation {
+    /// Passes the job's timeout on as `maxTimeMS` for the commands whose
+    /// options travel with the job, so the server stops working on them too
+    fn apply_max_time(&mut self, timeout: Option<Duration>) {
+        let max_time = match self {
+            Operation::Find { options, .. } => &mut options.max_time,
+            Operation::FindOne { options, .. } => &mut options.max_time,
+            Operation::FindOneAndUpdate { options, .. } => &mut options.max_time,
+            Operation::FindOneAndReplace { options, .. } => &mut options.max_time,
+            Operation::FindOneAndDelete { options, .. } => &mut options.max_time,
+            _ => return,
+        };
+        if max_time.is_none() {
+            *max_time = timeout;
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum JobResult {
-    InsertOne(Result<String, String>),
-    InsertMany(Result<Vec<String>, String>),
-    Find(Result<Vec<mongodb::bson::Document>, String>),
-    FindOne(Result<Option<mongodb::bson::Document>, String>),
-    UpdateOne(Result<i64, String>),
-    UpdateMany(Result<i64, String>),
-    DeleteOne(Result<i64, String>),
-    DeleteMany(Result<i64, String>),
-    CountDocuments(Result<i64, String>),
-    Aggregate(Result<Vec<mongodb::bson::Document>, String>),
-}
-
-pub fn submit_job(job: Job) -> Result<(), String> {
-    if job.callback.is_some() {
-        CALLBACKS_PENDING.fetch_add(1, std::sync::atomic::Ordering::Release);
+    InsertOne(Result<mongodb::bson::Bson, ErrorDetails>),
+    InsertMany(Result<Vec<mongodb::bson::Bson>, ErrorDetails>),
+    Find(Result<Vec<mongodb::bson::Document>, ErrorDetails>),
+    FindOne(Result<Option<mongodb::bson::Document>, ErrorDetails>),
+    UpdateOne(Result<WriteResult, ErrorDetails>),
+    UpdateMany(Result<WriteResult, ErrorDetails>),
+    ReplaceOne(Result<WriteResult, ErrorDetails>),
+    DeleteOne(Result<WriteResult, ErrorDetails>),
+    DeleteMany(Result<WriteResult, ErrorDetails>),
+    CountDocuments(Result<i64, ErrorDetails>),
+    Distinct(Result<Vec<mongodb::bson::Bson>, ErrorDetails>),
+    BulkWrite(Result<BulkWriteSummary, ErrorDetails>),
+    Aggregate(Result<Vec<mongodb::bson::Document>, ErrorDetails>),
+    CursorNext(Result<Vec<mongodb::bson::Document>, ErrorDetails>),
+    CreateIndexes(Result<Vec<String>, ErrorDetails>),
+    CreateIndex(Result<String, ErrorDetails>),
+    ListIndexes(Result<Vec<mongodb::bson::Document>, ErrorDetails>),
+    DropIndex(Result<(), ErrorDetails>),
+    DropIndexes(Result<(), ErrorDetails>),
+    ListDatabases(Result<Vec<String>, ErrorDetails>),
+    ListCollections(Result<Vec<String>, ErrorDetails>),
+    CreateCollection(Result<(), ErrorDetails>),
+    DropCollection(Result<(), ErrorDetails>),
+    CollectionStats(Result<mongodb::bson::Document, ErrorDetails>),
+    DropDatabase(Result<(), ErrorDetails>),
+    RenameCollection(Result<(), ErrorDetails>),
+    CollectionExists(Result<bool, ErrorDetails>),
+    #[cfg(feature = "gridfs")]
+    GridFsUpload(Result<mongodb::bson::Bson, ErrorDetails>),
+    #[cfg(feature = "gridfs")]
+    GridFsDownload(Result<Vec<u8>, ErrorDetails>),
+    #[cfg(feature = "gridfs")]
+    GridFsDelete(Result<(), ErrorDetails>),
+    #[cfg(feature = "gridfs")]
+    GridFsFind(Result<Vec<mongodb::bson::Document>, ErrorDetails>),
+    #[cfg(feature = "change-streams")]
+    ChangeEvent(mongodb::bson::Document),
+    #[cfg(feature = "change-streams")]
+    ChangeStreamClosed(Result<(), ErrorDetails>),
+    /// Ended without a result: evicted from a full queue or out of time
+    Failed(ErrorDetails),
+}
+
+impl JobResult {
+    /// Whether the operation failed, which the job handle reports as `failed`
+    pub fn is_error(&self) -> bool {
+        match self {
+            JobResult::InsertOne(res) => res.is_err(),
+            JobResult::InsertMany(res) | JobResult::Distinct(res) => res.is_err(),
+            JobResult::Find(res) | JobResult::Aggregate(res) | JobResult::CursorNext(res) | JobResult::ListIndexes(res) => res.is_err(),
+            JobResult::FindOne(res) => res.is_err(),
+            JobResult::UpdateOne(res) | JobResult::UpdateMany(res) | JobResult::ReplaceOne(res) |
+            JobResult::DeleteOne(res) | JobResult::DeleteMany(res) => res.is_err(),
+            JobResult::CountDocuments(res) => res.is_err(),
+            JobResult::BulkWrite(res) => res.is_err(),
+            JobResult::CreateIndexes(res) | JobResult::ListDatabases(res) | JobResult::ListCollections(res) => res.is_err(),
+            JobResult::CreateIndex(res) => res.is_err(),
+            JobResult::DropIndex(res) | JobResult::DropIndexes(res) | JobResult::CreateCollection(res) |
+            JobResult::DropCollection(res) | JobResult::DropDatabase(res) | JobResult::RenameCollection(res) => res.is_err(),
+            JobResult::CollectionStats(res) => res.is_err(),
+            JobResult::CollectionExists(res) => res.is_err(),
+            #[cfg(feature = "gridfs")]
+            JobResult::GridFsUpload(res) => res.is_err(),
+            #[cfg(feature = "gridfs")]
+            JobResult::GridFsDownload(res) => res.is_err(),
+            #[cfg(feature = "gridfs")]
+            JobResult::GridFsDelete(res) => res.is_err(),
+            #[cfg(feature = "gridfs")]
+            JobResult::GridFsFind(res) => res.is_err(),
+            #[cfg(feature = "change-streams")]
+            JobResult::ChangeEvent(_) => false,
+            #[cfg(feature = "change-streams")]
+            JobResult::ChangeStreamClosed(res) => res.is_err(),
+            JobResult::Failed(_) => true,
+        }
     }
 
-    JOB_QUEUE.send(job)
-        .map_err(|e| format!("Failed to submit job: {:?}", e))
+    /// Whether more results will follow for the same callback
+    pub fn is_intermediate(&self) -> bool {
+        #[cfg(feature = "change-streams")]
+        if let JobResult::ChangeEvent(_) = self {
+            return true;
+        }
+        false
+    }
 }
 
-async fn process_job(mut job: Job) {
-    let result = match &job.operation {
-        Operation::InsertOne { collection, document } => {
-            let result = collection
-                .insert_one(document.clone())
-                .await
-                .map(|r| r.inserted_id.to_string())
-                .map_err(|e| e.to_string());
-            JobResult::InsertOne(result)
+/// Queues `job`; every job is delivered back through the callback queue,
+/// so it counts as pending until then. A rejected job is handed back to
+/// nobody: the caller still owns its callbacks.
+pub fn submit_job(job: Job) -> Result<(), SubmitError> {
+    CALLBACKS_PENDING.fetch_add(1, std::sync::atomic::Ordering::Release);
+
+    match JOB_QUEUE.push(job) {
+        Ok(evicted) => {
+            if let Some(oldest) = evicted {
+                complete_job(oldest, JobResult::Failed(ErrorDetails::from("Job dropped: queue full".to_string())));
+            }
+            Ok(())
         }
-        Operation::InsertMany { collection, documents } => {
-            let result = collection
-                .insert_many(documents.clone())
-                .await
-                .map(|r| r.inserted_ids.values().map(|id| id.to_string()).collect())
-                .map_err(|e| e.to_string());
-            JobResult::InsertMany(result)
+        Err(_) => {
+            CALLBACKS_PENDING.fetch_sub(1, std::sync::atomic::Ordering::Release);
+            Err(SubmitError::Full)
         }
-        Operation::Find { collection, filter, limit } => {
-            use futures::TryStreamExt;
+    }
+}
 
-            let result = async {
-                let mut cursor = collection.find(filter.clone()).await
-                    .map_err(|e| e.to_string())?;
+pub fn queue_limits() -> QueueLimits {
+    JOB_QUEUE.limits()
+}
 
-                let mut documents = Vec::new();
-                let mut count = 0i64;
+pub fn set_queue_limits(limits: QueueLimits) {
+    JOB_QUEUE.set_limits(limits);
+}
 
-                while let Some(doc) = cursor.try_next().await.map_err(|e| e.to_string())? {
-                    documents.push(doc);
-                    count += 1;
+/// Jobs waiting for the worker, not counting those already running
+pub fn queued_jobs() -> usize {
+    JOB_QUEUE.len()
+}
 
-                    if let Some(limit_val) = limit {
-                        if count >= *limit_val {
-                            break;
-                        }
-                    }
-                }
+async fn process_job(mut job: Job) {
+    if !job.handle.start() {
+        return;
+    }
 
-                Ok(documents)
-            }.await;
+    job.operation.apply_max_time(job.settings.timeout);
+    let result = match job_timeout(&job) {
+        Some(timeout) => tokio::time::timeout(timeout, run_operation(&job))
+            .await
+            .unwrap_or_else(|_| JobResult::Failed(MongoError::Expired(timeout.as_millis() as u64).into())),
+        None => run_operation(&job).await,
+    };
 
-            JobResult::Find(result)
+    complete_job(job, result);
+}
+
+/// Time the job may run for; change streams are meant to stay open
+fn job_timeout(job: &Job) -> Option<Duration> {
+    #[cfg(feature = "change-streams")]
+    if let Operation::Watch { .. } = job.operation {
+        return None;
+    }
+    job.settings.timeout
+}
+
+async fn run_operation(job: &Job) -> JobResult {
+    let session = job.session.as_ref();
+    // Counts, distinct and aggregations have no options in `Operation` and
+    // take `maxTimeMS` from here instead of `apply_max_time`
+    let max_time = job.settings.timeout;
+    match &job.operation {
+        Operation::InsertOne { collection, document } => {
+            let result = with_session!(collection.insert_one(document.clone()), session)
+                .map(|r| r.inserted_id)
+                .map_err(ErrorDetails::from);
+            JobResult::InsertOne(result)
+        }
+        Operation::InsertMany { collection, documents } => {
+            let result = with_session!(collection.insert_many(documents.clone()), session)
+                .map(|r| ordered_ids(r.inserted_ids))
+                .map_err(ErrorDetails::from);
+            JobResult::InsertMany(result)
         }
-        Operation::FindOne { collection, filter } => {
-            let result = collection
-                .find_one(filter.clone())
+        Operation::Find { collection, filter, options } => {
+            let result = find_documents(collection, filter.clone(), (**options).clone(), session)
                 .await
-                .map_err(|e| e.to_string());
+                .map_err(ErrorDetails::from);
+            JobResult::Find(result)
+        }
+        Operation::FindOne { collection, filter, options } => {
+            let result = with_session!(collection.find_one(filter.clone()).with_options((**options).clone()), session)
+                .map_err(ErrorDetails::from);
+            JobResult::FindOne(result)
+        }
+        Operation::FindOneAndUpdate { collection, filter, update, options } => {
+            let result = with_session!(collection.find_one_and_update(filter.clone(), update.clone()).with_options((**options).clone()), session)
+                .map_err(ErrorDetails::from);
+            JobResult::FindOne(result)
+        }
+        Operation::FindOneAndReplace { collection, filter, replacement, options } => {
+            let result = with_session!(collection.find_one_and_replace(filter.clone(), replacement.clone()).with_options((**options).clone()), session)
+                .map_err(ErrorDetails::from);
+            JobResult::FindOne(result)
+        }
+        Operation::FindOneAndDelete { collection, filter, options } => {
+            let result = with_session!(collection.find_one_and_delete(filter.clone()).with_options((**options).clone()), session)
+                .map_err(ErrorDetails::from);
             JobResult::FindOne(result)
         }
         Operation::UpdateOne { collection, filter, update, upsert } => {
@@ -153,12 +441,9 @@ async fn process_job(mut job: Job) {
                 .upsert(*upsert)
                 .build();
 
-            let result = collection
-                .update_one(filter.clone(), update.clone())
-                .with_options(options)
-                .await
-                .map(|r| r.modified_count as i64)
-                .map_err(|e| e.to_string());
+            let result = with_session!(collection.update_one(filter.clone(), update.clone()).with_options(options), session)
+                .map(|r| WriteResult::from_update(r, is_acknowledged(collection)))
+                .map_err(ErrorDetails::from);
             JobResult::UpdateOne(result)
         }
         Operation::UpdateMany { collection, filter, update, upsert } => {
@@ -166,71 +451,267 @@ async fn process_job(mut job: Job) {
                 .upsert(*upsert)
                 .build();
 
-            let result = collection
-                .update_many(filter.clone(), update.clone())
-                .with_options(options)
-                .await
-                .map(|r| r.modified_count as i64)
-                .map_err(|e| e.to_string());
+            let result = with_session!(collection.update_many(filter.clone(), update.clone()).with_options(options), session)
+                .map(|r| WriteResult::from_update(r, is_acknowledged(collection)))
+                .map_err(ErrorDetails::from);
             JobResult::UpdateMany(result)
         }
+        Operation::ReplaceOne { collection, filter, replacement, upsert } => {
+            let options = mongodb::options::ReplaceOptions::builder()
+                .upsert(*upsert)
+                .build();
+
+            let result = with_session!(collection.replace_one(filter.clone(), replacement.clone()).with_options(options), session)
+                .map(|r| WriteResult::from_update(r, is_acknowledged(collection)))
+                .map_err(ErrorDetails::from);
+            JobResult::ReplaceOne(result)
+        }
         Operation::DeleteOne { collection, filter } => {
-            let result = collection
-                .delete_one(filter.clone())
-                .await
-                .map(|r| r.deleted_count as i64)
-                .map_err(|e| e.to_string());
+            let result = with_session!(collection.delete_one(filter.clone()), session)
+                .map(|r| WriteResult::from_delete(r, is_acknowledged(collection)))
+                .map_err(ErrorDetails::from);
             JobResult::DeleteOne(result)
         }
         Operation::DeleteMany { collection, filter } => {
-            let result = collection
-                .delete_many(filter.clone())
-                .await
-                .map(|r| r.deleted_count as i64)
-                .map_err(|e| e.to_string());
+            let result = with_session!(collection.delete_many(filter.clone()), session)
+                .map(|r| WriteResult::from_delete(r, is_acknowledged(collection)))
+                .map_err(ErrorDetails::from);
             JobResult::DeleteMany(result)
         }
         Operation::CountDocuments { collection, filter } => {
-            let result = collection
-                .count_documents(filter.clone())
+            let result = with_session!(collection.count_documents(filter.clone()).with_options(mongodb::options::CountOptions::builder().max_time(max_time).build()), session)
+                .map(|c| c as i64)
+                .map_err(ErrorDetails::from);
+            JobResult::CountDocuments(result)
+        }
+        Operation::EstimatedCount { collection } => {
+            let result = collection.estimated_document_count()
+                .with_options(mongodb::options::EstimatedDocumentCountOptions::builder().max_time(max_time).build())
                 .await
                 .map(|c| c as i64)
-                .map_err(|e| e.to_string());
+                .map_err(ErrorDetails::from);
             JobResult::CountDocuments(result)
         }
+        Operation::Distinct { collection, field, filter } => {
+            let result = with_session!(collection.distinct(field.as_str(), filter.clone()).with_options(mongodb::options::DistinctOptions::builder().max_time(max_time).build()), session)
+                .map_err(ErrorDetails::from);
+            JobResult::Distinct(result)
+        }
+        Operation::BulkWrite { collection, models, options } => {
+            let result = run_bulk_write(collection.client(), models.clone(), (**options).clone(), session)
+                .await
+                .map_err(ErrorDetails::from);
+            JobResult::BulkWrite(result)
+        }
         Operation::Aggregate { collection, pipeline } => {
-            use futures::TryStreamExt;
-
-            let result = async {
-                let mut cursor = collection
-                    .aggregate(pipeline.clone())
-                    .await
-                    .map_err(|e| e.to_string())?;
-
-                let mut documents = Vec::new();
-                while let Some(doc) = cursor.try_next().await.map_err(|e| e.to_string())? {
-                    documents.push(doc);
+            let result = aggregate_documents(collection, pipeline.clone(), session, max_time)
+                .await
+                .map_err(ErrorDetails::from);
+            JobResult::Aggregate(result)
+        }
+        Operation::CountAggregate { collection, filter } => {
+            let result = count_matching(collection, filter.clone(), max_time)
+                .await
+                .map_err(ErrorDetails::from);
+            JobResult::CountDocuments(result)
+        }
+        Operation::GroupBy { collection, group_field, accumulator_field } => {
+            let result = aggregate_documents(collection, group_pipeline(group_field, accumulator_field.as_deref()), None, max_time)
+                .await
+                .map_err(ErrorDetails::from);
+            JobResult::Aggregate(result)
+        }
+        Operation::CreateIndex { collection, keys, unique, name } => {
+            let result = collection.create_index(index_model(keys.clone(), *unique, name.clone()))
+                .await
+                .map(|r| r.index_name)
+                .map_err(ErrorDetails::from);
+            JobResult::CreateIndex(result)
+        }
+        Operation::CreateIndexes { collection, indexes } => {
+            let result = create_index_models(collection, indexes.clone())
+                .await
+                .map_err(ErrorDetails::from);
+            JobResult::CreateIndexes(result)
+        }
+        Operation::CreateTextIndex { collection, fields, name } => {
+            let result = collection.create_index(index_model(text_index_keys(fields.clone()), false, name.clone()))
+                .await
+                .map(|r| r.index_name)
+                .map_err(ErrorDetails::from);
+            JobResult::CreateIndex(result)
+        }
+        Operation::ListIndexes { collection } => {
+            let result = list_index_documents(collection)
+                .await
+                .map_err(ErrorDetails::from);
+            JobResult::ListIndexes(result)
+        }
+        Operation::DropIndex { collection, name } => {
+            let result = collection.drop_index(name.as_str())
+                .await
+                .map_err(ErrorDetails::from);
+            JobResult::DropIndex(result)
+        }
+        Operation::DropIndexes { collection } => {
+            let result = collection.drop_indexes()
+                .await
+                .map_err(ErrorDetails::from);
+            JobResult::DropIndexes(result)
+        }
+        Operation::ListDatabases { client } => {
+            let result = client.list_database_names()
+                .await
+                .map_err(ErrorDetails::from);
+            JobResult::ListDatabases(result)
+        }
+        Operation::ListCollections { database } => {
+            let result = database.list_collection_names()
+                .await
+                .map_err(ErrorDetails::from);
+            JobResult::ListCollections(result)
+        }
+        Operation::CreateCollection { database, name } => {
+            let result = database.create_collection(name.as_str())
+                .await
+                .map_err(ErrorDetails::from);
+            JobResult::CreateCollection(result)
+        }
+        Operation::DropCollection { database, name } => {
+            let result = database.collection::<mongodb::bson::Document>(name).drop()
+                .await
+                .map_err(ErrorDetails::from);
+            JobResult::DropCollection(result)
+        }
+        Operation::CollectionStats { database, name } => {
+            let result = database.run_command(mongodb::bson::doc! { "collStats": name.as_str() })
+                .await
+                .map_err(ErrorDetails::from);
+            JobResult::CollectionStats(result)
+        }
+        Operation::DropDatabase { database } => {
+            let result = database.drop()
+                .await
+                .map_err(ErrorDetails::from);
+            JobResult::DropDatabase(result)
+        }
+        Operation::RenameCollection { database, old_name, new_name, drop_target } => {
+            let command = rename_command(database.name(), old_name, new_name, *drop_target);
+            let result = run_admin_command(database, command)
+                .await
+                .map_err(ErrorDetails::from);
+            JobResult::RenameCollection(result)
+        }
+        Operation::CollectionExists { database, name } => {
+            let result = has_collection(database, name)
+                .await
+                .map_err(ErrorDetails::from);
+            JobResult::CollectionExists(result)
+        }
+        Operation::CursorNext { cursor, count } => {
+            let result = cursor.next_batch(*count)
+                .await
+                .map_err(ErrorDetails::from);
+            JobResult::CursorNext(result)
+        }
+        #[cfg(feature = "gridfs")]
+        Operation::GridFsUpload { bucket, filename, data, metadata } => {
+            let result = gridfs::upload_file(bucket, filename, data, metadata.clone())
+                .await
+                .map_err(ErrorDetails::from);
+            JobResult::GridFsUpload(result)
+        }
+        #[cfg(feature = "gridfs")]
+        Operation::GridFsDownload { bucket, target } => {
+            let result = gridfs::download_file(bucket, target.clone())
+                .await
+                .map_err(ErrorDetails::from);
+            JobResult::GridFsDownload(result)
+        }
+        #[cfg(feature = "gridfs")]
+        Operation::GridFsDelete { bucket, target } => {
+            let result = gridfs::delete_file(bucket, target.clone())
+                .await
+                .map_err(ErrorDetails::from);
+            JobResult::GridFsDelete(result)
+        }
+        #[cfg(feature = "gridfs")]
+        Operation::GridFsFind { bucket, filter } => {
+            let result = gridfs::list_files(bucket, filter.clone())
+                .await
+                .map_err(ErrorDetails::from);
+            JobResult::GridFsFind(result)
+        }
+        #[cfg(feature = "change-streams")]
+        Operation::Watch { target, pipeline, options, control } => {
+            let handle = job.handle.clone();
+            let settings = job.settings.clone();
+            let result = change_streams::run_change_stream(target.clone(), pipeline.clone(), (**options).clone(), control.clone(), |event| {
+                let event_job = Job {
+                    operation: Operation::Watch {
+                        target: target.clone(),
+                        pipeline: Vec::new(),
+                        options: Default::default(),
+                        control: control.clone(),
+                    },
+                    handle: handle.clone(),
+                    session: None,
+                    settings: settings.clone(),
+                    result: Some(JobResult::ChangeEvent(event)),
+                };
+                if let Ok(guard) = CALLBACK_QUEUE.lock() {
+                    guard.0.send(event_job).ok();
                 }
+            })
+            .await
+            .map_err(ErrorDetails::from);
+            JobResult::ChangeStreamClosed(result)
+        }
+    }
+}
 
-                Ok(documents)
-            }.await;
+/// Hands the outcome to the game thread
+fn complete_job(mut job: Job, result: JobResult) {
+    // A cancelled job was already settled on the Lua side
+    if !job.handle.finish(result.is_error()) {
+        return;
+    }
 
-            JobResult::Aggregate(result)
-        }
-    };
+    let handle = job.handle.clone();
+    job.result = Some(result);
+    if let Ok(guard) = CALLBACK_QUEUE.lock() {
+        guard.0.send(job).ok();
+    }
+    handle.settle();
+}
 
-    if job.callback.is_some() {
-        job.result = Some(result);
-        if let Ok(guard) = CALLBACK_QUEUE.lock() {
-            guard.0.send(job).ok();
-        }
+/// Concurrency limit the job runs under; change streams stay open for
+/// their whole lifetime, so they do not take a slot
+fn job_limiter(job: &Job) -> Option<&Limiter> {
+    #[cfg(feature = "change-streams")]
+    if let Operation::Watch { .. } = job.operation {
+        return None;
     }
+    job.settings.limiter.as_ref()
 }
 
-fn spawn_worker(mut rx: tokio::sync::mpsc::UnboundedReceiver<Job>) {
+/// Takes jobs in order and starts each in its own task once its client has
+/// a free slot. Until then later jobs stay in the bounded queue, so the
+/// queue's capacity and overflow policy cover everything not yet running.
+fn spawn_worker(queue: Arc<BoundedQueue<Job>>) {
     MONGO_RUNTIME.spawn(async move {
-        while let Some(job) = rx.recv().await {
-            tokio::task::spawn(process_job(job));
+        loop {
+            let job = queue.pop().await;
+            let permit = match job_limiter(&job) {
+                Some(limiter) if !job.handle.status().is_finished() => limiter.acquire().await,
+                _ => None,
+            };
+            let handle = job.handle.clone();
+            let task = tokio::task::spawn(async move {
+                let _permit = permit;
+                process_job(job).await;
+            });
+            handle.set_abort(task.abort_handle());
         }
     });
 }
@@ -247,6 +728,10 @@ pub fn should_register_hook() -> bool {
     !HOOK_REGISTERED.swap(true, std::sync::atomic::Ordering::AcqRel)
 }
 
+pub fn is_hook_registered() -> bool {
+    HOOK_REGISTERED.load(std::sync::atomic::Ordering::Acquire)
+}
+
 pub fn mark_hook_unregistered() {
     HOOK_REGISTERED.store(false, std::sync::atomic::Ordering::Release);
 }
@@ -0,0 +1,248 @@
+//! Errors as Lua scripts see them
+//!
+//! Driver errors are flattened into a code, a category and the server's
+//! labels, so scripts can tell a duplicate key from a network timeout
+//! without matching on the message.
+
+use crate::error::MongoError;
+use mongodb::error::{Error, ErrorKind, WriteFailure};
+
+/// Server codes for operations that ran out of time
+const TIMEOUT_CODES: [i32; 4] = [50, 89, 202, 262];
+/// Server codes for missing or rejected credentials
+const AUTH_CODES: [i32; 2] = [13, 18];
+/// `DocumentValidationFailure`
+const VALIDATION_CODE: i32 = 121;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCategory {
+    Network,
+    Write,
+    Auth,
+    Timeout,
+    Validation,
+    /// Any other error reported by the server
+    Server,
+    /// Errors raised before anything reached the server
+    Client,
+}
+
+impl ErrorCategory {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Network => "network",
+            Self::Write => "write",
+            Self::Auth => "auth",
+            Self::Timeout => "timeout",
+            Self::Validation => "validation",
+            Self::Server => "server",
+            Self::Client => "client",
+        }
+    }
+}
+
+/// One failed write of an insert or bulk write; `index` counts from 0
+#[derive(Debug, Clone, PartialEq)]
+pub struct WriteErrorDetails {
+    pub index: usize,
+    pub code: i32,
+    pub code_name: Option<String>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorDetails {
+    pub code: Option<i32>,
+    pub code_name: Option<String>,
+    pub message: String,
+    pub labels: Vec<String>,
+    pub category: ErrorCategory,
+    /// `MongoError` variant the failure was classified as, if any
+    pub kind: Option<&'static str>,
+    pub write_errors: Vec<WriteErrorDetails>,
+}
+
+impl ErrorDetails {
+    pub fn new(category: ErrorCategory, message: impl Into<String>) -> Self {
+        Self {
+            code: None,
+            code_name: None,
+            message: message.into(),
+            labels: Vec::new(),
+            category,
+            kind: None,
+            write_errors: Vec::new(),
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorDetails {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Errors raised by this module rather than the driver
+impl From<String> for ErrorDetails {
+    fn from(message: String) -> Self {
+        Self::new(ErrorCategory::Client, message)
+    }
+}
+
+fn category_of_code(code: i32, fallback: ErrorCategory) -> ErrorCategory {
+    if TIMEOUT_CODES.contains(&code) {
+        ErrorCategory::Timeout
+    } else if AUTH_CODES.contains(&code) {
+        ErrorCategory::Auth
+    } else if code == VALIDATION_CODE {
+        ErrorCategory::Validation
+    } else {
+        fallback
+    }
+}
+
+impl From<&Error> for ErrorDetails {
+    fn from(error: &Error) -> Self {
+        let mut details = ErrorDetails::new(ErrorCategory::Client, error.to_string());
+        let mut labels: Vec<String> = error.labels().iter().cloned().collect();
+        labels.sort_unstable();
+        details.labels = labels;
+
+        match error.kind.as_ref() {
+            ErrorKind::Command(e) => {
+                details.code = Some(e.code);
+                details.code_name = Some(e.code_name.clone()).filter(|name| !name.is_empty());
+                details.category = category_of_code(e.code, ErrorCategory::Server);
+            }
+            ErrorKind::Write(WriteFailure::WriteError(e)) => {
+                details.code = Some(e.code);
+                details.code_name = e.code_name.clone();
+                details.write_errors.push(WriteErrorDetails {
+                    index: 0,
+                    code: e.code,
+                    code_name: e.code_name.clone(),
+                    message: e.message.clone(),
+                });
+                details.category = category_of_code(e.code, ErrorCategory::Write);
+            }
+            ErrorKind::Write(WriteFailure::WriteConcernError(e)) => {
+                details.code = Some(e.code);
+                details.code_name = Some(e.code_name.clone()).filter(|name| !name.is_empty());
+                details.category = category_of_code(e.code, ErrorCategory::Write);
+            }
+            ErrorKind::Write(_) => details.category = ErrorCategory::Write,
+            ErrorKind::InsertMany(e) => {
+                details.write_errors = e
+                    .write_errors
+                    .iter()
+                    .flatten()
+                    .map(|failure| WriteErrorDetails {
+                        index: failure.index,
+                        code: failure.code,
+                        code_name: failure.code_name.clone(),
+                        message: failure.message.clone(),
+                    })
+                    .collect();
+                details.category = ErrorCategory::Write;
+            }
+            ErrorKind::BulkWrite(e) => {
+                details.write_errors = e
+                    .write_errors
+                    .iter()
+                    .map(|(index, failure)| WriteErrorDetails {
+                        index: *index,
+                        code: failure.code,
+                        code_name: failure.code_name.clone(),
+                        message: failure.message.clone(),
+                    })
+                    .collect();
+                details.write_errors.sort_unstable_by_key(|failure| failure.index);
+                details.category = ErrorCategory::Write;
+            }
+            ErrorKind::Authentication { .. } => details.category = ErrorCategory::Auth,
+            ErrorKind::Io(e) if e.kind() == std::io::ErrorKind::TimedOut => details.category = ErrorCategory::Timeout,
+            ErrorKind::Io(_) | ErrorKind::DnsResolve { .. } | ErrorKind::ConnectionPoolCleared { .. } |
+            ErrorKind::ServerSelection { .. } => details.category = ErrorCategory::Network,
+            ErrorKind::InvalidArgument { .. } | ErrorKind::BsonDeserialization(_) | ErrorKind::BsonSerialization(_) => {
+                details.category = ErrorCategory::Validation
+            }
+            _ => {}
+        }
+
+        // Write errors share one code when there is only one of them
+        if details.code.is_none() {
+            if let [failure] = details.write_errors.as_slice() {
+                details.code = Some(failure.code);
+                details.code_name = failure.code_name.clone();
+                details.category = category_of_code(failure.code, ErrorCategory::Write);
+            }
+        }
+        if details.category == ErrorCategory::Client && error.contains_label("NetworkError") {
+            details.category = ErrorCategory::Network;
+        }
+
+        details
+    }
+}
+
+impl From<Error> for ErrorDetails {
+    fn from(error: Error) -> Self {
+        MongoError::from(error).into()
+    }
+}
+
+impl From<MongoError> for ErrorDetails {
+    fn from(error: MongoError) -> Self {
+        let kind = error.kind();
+        let mut details = match error.driver_failure() {
+            Some(failure) => {
+                let mut details = ErrorDetails::from(&failure.source);
+                details.message = failure.to_string();
+                details
+            }
+            None => {
+                let category = match error {
+                    MongoError::Connection(_) => ErrorCategory::Network,
+                    MongoError::InvalidQuery(_) => ErrorCategory::Validation,
+                    MongoError::Expired(_) => ErrorCategory::Timeout,
+                    _ => ErrorCategory::Server,
+                };
+                ErrorDetails::new(category, error.to_string())
+            }
+        };
+        details.kind = Some(kind);
+        details
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_io_error_categories() {
+        let timeout = Error::from(ErrorKind::Io(Arc::new(std::io::Error::new(std::io::ErrorKind::TimedOut, "timed out"))));
+        assert_eq!(ErrorDetails::from(timeout).category, ErrorCategory::Timeout);
+
+        let refused = Error::from(ErrorKind::Io(Arc::new(std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused"))));
+        let details = ErrorDetails::from(refused);
+        assert_eq!(details.category, ErrorCategory::Network);
+        assert_eq!(details.code, None);
+    }
+
+    #[test]
+    fn test_code_categories() {
+        assert_eq!(category_of_code(50, ErrorCategory::Server), ErrorCategory::Timeout);
+        assert_eq!(category_of_code(18, ErrorCategory::Server), ErrorCategory::Auth);
+        assert_eq!(category_of_code(121, ErrorCategory::Write), ErrorCategory::Validation);
+        assert_eq!(category_of_code(11000, ErrorCategory::Write), ErrorCategory::Write);
+    }
+
+    #[test]
+    fn test_module_errors() {
+        let details = ErrorDetails::from("Job queue is full".to_string());
+        assert_eq!(details.category, ErrorCategory::Client);
+        assert_eq!(details.to_string(), "Job queue is full");
+    }
+}
@@ -1,39 +1,172 @@
+mod details;
+
+pub use details::*;
 use thiserror::Error;
 
+/// Server codes for a unique index rejecting a write
+const DUPLICATE_KEY_CODES: [i32; 3] = [11000, 11001, 12582];
+/// `WriteConflict`
+const WRITE_CONFLICT_CODE: i32 = 112;
+/// Server codes for writes or reads sent to a node that is no longer primary
+const NOT_PRIMARY_CODES: [i32; 5] = [189, 10107, 11602, 13435, 13436];
+/// Server codes for operations killed before they finished
+const CANCELLED_CODES: [i32; 3] = [175, 237, 11601];
+/// `NamespaceNotFound`
+const NAMESPACE_NOT_FOUND_CODE: i32 = 26;
+
+/// A driver error together with what was being attempted
+#[derive(Error, Debug)]
+pub struct DriverFailure {
+    pub context: String,
+    #[source]
+    pub source: mongodb::error::Error,
+}
+
+impl std::fmt::Display for DriverFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.context.is_empty() {
+            write!(f, "{}", self.source)
+        } else {
+            write!(f, "{}: {}", self.context, self.source)
+        }
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum MongoError {
     #[error("Connection error: {0}")]
     Connection(String),
 
+    #[error("Network error: {0}")]
+    Network(DriverFailure),
+
     #[error("Authentication failed: {0}")]
-    Authentication(String),
+    Authentication(DriverFailure),
 
     #[error("Database operation failed: {0}")]
-    Operation(String),
+    Operation(DriverFailure),
 
     #[error("Invalid query: {0}")]
     InvalidQuery(String),
 
     #[error("Collection not found: {0}")]
-    CollectionNotFound(String),
+    CollectionNotFound(DriverFailure),
 
     #[error("Database not found: {0}")]
     DatabaseNotFound(String),
 
     #[error("Document validation failed: {0}")]
-    ValidationError(String),
+    ValidationError(DriverFailure),
+
+    #[error("Duplicate key: {0}")]
+    DuplicateKey(DriverFailure),
+
+    #[error("Write conflict: {0}")]
+    WriteConflict(DriverFailure),
+
+    #[error("Not primary: {0}")]
+    NotPrimary(DriverFailure),
+
+    #[error("Operation cancelled: {0}")]
+    Cancelled(DriverFailure),
 
     #[error("Index error: {0}")]
-    IndexError(String),
+    IndexError(DriverFailure),
 
     #[error("Transaction error: {0}")]
-    TransactionError(String),
+    TransactionError(DriverFailure),
+
+    #[error("Timeout: {0}")]
+    Timeout(DriverFailure),
 
-    #[error("Timeout: operation took longer than {0}s")]
-    Timeout(u64),
+    /// The call's own `timeout_ms` ran out before the driver answered
+    #[error("Timeout: operation took longer than {0}ms")]
+    Expired(u64),
 
     #[error("MongoDB driver error: {0}")]
-    DriverError(#[from] mongodb::error::Error),
+    DriverError(DriverFailure),
+}
+
+impl MongoError {
+    /// Picks the variant that describes a driver error, falling back to
+    /// `fallback` when nothing more specific applies
+    pub fn classify(context: impl Into<String>, source: mongodb::error::Error, fallback: fn(DriverFailure) -> MongoError) -> Self {
+        let details = ErrorDetails::from(&source);
+        let failure = DriverFailure {
+            context: context.into(),
+            source,
+        };
+
+        match details.code {
+            Some(code) if DUPLICATE_KEY_CODES.contains(&code) => return Self::DuplicateKey(failure),
+            Some(WRITE_CONFLICT_CODE) => return Self::WriteConflict(failure),
+            Some(code) if NOT_PRIMARY_CODES.contains(&code) => return Self::NotPrimary(failure),
+            Some(code) if CANCELLED_CODES.contains(&code) => return Self::Cancelled(failure),
+            Some(NAMESPACE_NOT_FOUND_CODE) => return Self::CollectionNotFound(failure),
+            _ => {}
+        }
+
+        match details.category {
+            ErrorCategory::Network => Self::Network(failure),
+            ErrorCategory::Auth => Self::Authentication(failure),
+            ErrorCategory::Timeout => Self::Timeout(failure),
+            ErrorCategory::Validation => Self::ValidationError(failure),
+            _ => fallback(failure),
+        }
+    }
+
+    /// `classify` for plain operations
+    pub fn driver(context: impl Into<String>, source: mongodb::error::Error) -> Self {
+        Self::classify(context, source, Self::Operation)
+    }
+
+    /// Variant name as Lua scripts see it in `err.kind`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Connection(_) => "connection",
+            Self::Network(_) => "network",
+            Self::Authentication(_) => "authentication",
+            Self::Operation(_) => "operation",
+            Self::InvalidQuery(_) => "invalid_query",
+            Self::CollectionNotFound(_) => "collection_not_found",
+            Self::DatabaseNotFound(_) => "database_not_found",
+            Self::ValidationError(_) => "validation",
+            Self::DuplicateKey(_) => "duplicate_key",
+            Self::WriteConflict(_) => "write_conflict",
+            Self::NotPrimary(_) => "not_primary",
+            Self::Cancelled(_) => "cancelled",
+            Self::IndexError(_) => "index",
+            Self::TransactionError(_) => "transaction",
+            Self::Timeout(_) | Self::Expired(_) => "timeout",
+            Self::DriverError(_) => "driver",
+        }
+    }
+
+    /// The driver error behind this one, if any
+    pub fn driver_failure(&self) -> Option<&DriverFailure> {
+        match self {
+            Self::Connection(_) | Self::InvalidQuery(_) | Self::DatabaseNotFound(_) | Self::Expired(_) => None,
+            Self::Network(failure) |
+            Self::Authentication(failure) |
+            Self::Operation(failure) |
+            Self::CollectionNotFound(failure) |
+            Self::ValidationError(failure) |
+            Self::DuplicateKey(failure) |
+            Self::WriteConflict(failure) |
+            Self::NotPrimary(failure) |
+            Self::Cancelled(failure) |
+            Self::IndexError(failure) |
+            Self::TransactionError(failure) |
+            Self::Timeout(failure) |
+            Self::DriverError(failure) => Some(failure),
+        }
+    }
+}
+
+impl From<mongodb::error::Error> for MongoError {
+    fn from(source: mongodb::error::Error) -> Self {
+        Self::classify(String::new(), source, Self::DriverError)
+    }
 }
 
 #[derive(Error, Debug)]
@@ -98,4 +231,58 @@ mod tests {
         };
         assert!(err.to_string().contains("position 1"));
     }
+
+    fn command_error(code: i32, code_name: &str) -> mongodb::error::Error {
+        let command: mongodb::error::CommandError = mongodb::bson::from_document(mongodb::bson::doc! {
+            "code": code,
+            "codeName": code_name,
+            "errmsg": "server said no",
+        })
+        .unwrap();
+        mongodb::error::ErrorKind::Command(command).into()
+    }
+
+    #[test]
+    fn test_classify_codes() {
+        let duplicate = MongoError::driver("Insert failed", command_error(11000, "DuplicateKey"));
+        assert!(matches!(duplicate, MongoError::DuplicateKey(_)));
+
+        let conflict = MongoError::driver("Update failed", command_error(112, "WriteConflict"));
+        assert!(matches!(conflict, MongoError::WriteConflict(_)));
+
+        let stepped_down = MongoError::driver("Find failed", command_error(10107, "NotWritablePrimary"));
+        assert!(matches!(stepped_down, MongoError::NotPrimary(_)));
+
+        let killed = MongoError::driver("Find failed", command_error(11601, "Interrupted"));
+        assert!(matches!(killed, MongoError::Cancelled(_)));
+
+        let expired = MongoError::driver("Find failed", command_error(50, "MaxTimeMSExpired"));
+        assert!(matches!(expired, MongoError::Timeout(_)));
+    }
+
+    #[test]
+    fn test_classify_fallback() {
+        let error = MongoError::classify("Create index failed", command_error(85, "IndexOptionsConflict"), MongoError::IndexError);
+        assert!(matches!(error, MongoError::IndexError(_)));
+        assert_eq!(error.kind(), "index");
+    }
+
+    #[test]
+    fn test_details_keep_code_and_labels() {
+        let concern: mongodb::error::WriteConcernError = mongodb::bson::from_document(mongodb::bson::doc! {
+            "code": 64,
+            "codeName": "WriteConcernFailed",
+            "errmsg": "waiting for replication timed out",
+            "errorLabels": ["RetryableWriteError"],
+        })
+        .unwrap();
+        let source = mongodb::error::ErrorKind::Write(mongodb::error::WriteFailure::WriteConcernError(concern)).into();
+        let details = ErrorDetails::from(MongoError::driver("Update failed", source));
+        assert_eq!(details.code, Some(64));
+        assert_eq!(details.code_name.as_deref(), Some("WriteConcernFailed"));
+        assert_eq!(details.labels, vec!["RetryableWriteError".to_string()]);
+        assert_eq!(details.category, ErrorCategory::Write);
+        assert_eq!(details.kind, Some("operation"));
+        assert!(details.message.starts_with("Update failed: "));
+    }
 }
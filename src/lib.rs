@@ -66,8 +66,39 @@ unsafe fn open(l: LuaState) -> i32 {
     lua_setfield(l, -2, cstr!("Database"));
     lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::list_databases) });
     lua_setfield(l, -2, cstr!("ListDatabases"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::list_databases_async) });
+    lua_setfield(l, -2, cstr!("ListDatabasesAsync"));
+    #[cfg(feature = "transactions")]
+    {
+        lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::start_session) });
+        lua_setfield(l, -2, cstr!("StartSession"));
+    }
+    #[cfg(feature = "change-streams")]
+    {
+        lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::client_watch) });
+        lua_setfield(l, -2, cstr!("Watch"));
+    }
     lua_pop(l, 1);
 
+    // Register MongoDBSession metatable
+    #[cfg(feature = "transactions")]
+    {
+        luaL_newmetatable(l, cstr!("MongoDBSession"));
+        lua_pushvalue(l, -1);
+        lua_setfield(l, -2, cstr!("__index"));
+        lua_pushcfunction(l, utils::gc_userdata::<core::session::SessionHandle>);
+        lua_setfield(l, -2, cstr!("__gc"));
+        lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::session_start_transaction) });
+        lua_setfield(l, -2, cstr!("StartTransaction"));
+        lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::session_commit_transaction) });
+        lua_setfield(l, -2, cstr!("CommitTransaction"));
+        lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::session_abort_transaction) });
+        lua_setfield(l, -2, cstr!("AbortTransaction"));
+        lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::session_with_transaction) });
+        lua_setfield(l, -2, cstr!("WithTransaction"));
+        lua_pop(l, 1);
+    }
+
     // Register MongoDBDatabase metatable
     luaL_newmetatable(l, cstr!("MongoDBDatabase"));
     lua_pushvalue(l, -1);
@@ -82,8 +113,120 @@ unsafe fn open(l: LuaState) -> i32 {
     lua_setfield(l, -2, cstr!("DropCollection"));
     lua_pushcfunction(l, api::collection_stats as LuaCFunction);
     lua_setfield(l, -2, cstr!("Stats"));
+    lua_pushcfunction(l, api::rename_collection as LuaCFunction);
+    lua_setfield(l, -2, cstr!("RenameCollection"));
+    lua_pushcfunction(l, api::collection_exists as LuaCFunction);
+    lua_setfield(l, -2, cstr!("CollectionExists"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::rename_collection_async) });
+    lua_setfield(l, -2, cstr!("RenameCollectionAsync"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::collection_exists_async) });
+    lua_setfield(l, -2, cstr!("CollectionExistsAsync"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::list_collections_async) });
+    lua_setfield(l, -2, cstr!("ListCollectionsAsync"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::create_collection_async) });
+    lua_setfield(l, -2, cstr!("CreateCollectionAsync"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::drop_collection_async) });
+    lua_setfield(l, -2, cstr!("DropCollectionAsync"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::collection_stats_async) });
+    lua_setfield(l, -2, cstr!("StatsAsync"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::drop_database_async) });
+    lua_setfield(l, -2, cstr!("DropAsync"));
     lua_pushcfunction(l, api::drop_database as LuaCFunction);
     lua_setfield(l, -2, cstr!("Drop"));
+    #[cfg(feature = "gridfs")]
+    {
+        lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::get_gridfs_bucket) });
+        lua_setfield(l, -2, cstr!("GridFSBucket"));
+    }
+    #[cfg(feature = "change-streams")]
+    {
+        lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::database_watch) });
+        lua_setfield(l, -2, cstr!("Watch"));
+    }
+    lua_pop(l, 1);
+
+    // Register MongoDBGridFSBucket metatable
+    #[cfg(feature = "gridfs")]
+    {
+        luaL_newmetatable(l, cstr!("MongoDBGridFSBucket"));
+        lua_pushvalue(l, -1);
+        lua_setfield(l, -2, cstr!("__index"));
+        lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::gridfs_upload) });
+        lua_setfield(l, -2, cstr!("Upload"));
+        lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::gridfs_download) });
+        lua_setfield(l, -2, cstr!("Download"));
+        lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::gridfs_delete) });
+        lua_setfield(l, -2, cstr!("Delete"));
+        lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::gridfs_find) });
+        lua_setfield(l, -2, cstr!("Find"));
+        lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::gridfs_upload_async) });
+        lua_setfield(l, -2, cstr!("UploadAsync"));
+        lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::gridfs_download_async) });
+        lua_setfield(l, -2, cstr!("DownloadAsync"));
+        lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::gridfs_delete_async) });
+        lua_setfield(l, -2, cstr!("DeleteAsync"));
+        lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::gridfs_find_async) });
+        lua_setfield(l, -2, cstr!("FindAsync"));
+        lua_pop(l, 1);
+    }
+
+    // Register MongoDBChangeStream metatable
+    #[cfg(feature = "change-streams")]
+    {
+        luaL_newmetatable(l, cstr!("MongoDBChangeStream"));
+        lua_pushvalue(l, -1);
+        lua_setfield(l, -2, cstr!("__index"));
+        lua_pushcfunction(l, api::gc_change_stream);
+        lua_setfield(l, -2, cstr!("__gc"));
+        lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::change_stream_stop) });
+        lua_setfield(l, -2, cstr!("Stop"));
+        lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::change_stream_is_active) });
+        lua_setfield(l, -2, cstr!("IsActive"));
+        lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::change_stream_resume_token) });
+        lua_setfield(l, -2, cstr!("ResumeToken"));
+        lua_pop(l, 1);
+    }
+
+    // Register MongoDBLimiter metatable, the token behind a client's concurrency limit
+    luaL_newmetatable(l, cstr!("MongoDBLimiter"));
+    lua_pushcfunction(l, utils::gc_userdata::<core::queue::Limiter>);
+    lua_setfield(l, -2, cstr!("__gc"));
+    lua_pop(l, 1);
+
+    // Register MongoDBJob metatable
+    luaL_newmetatable(l, cstr!("MongoDBJob"));
+    lua_pushvalue(l, -1);
+    lua_setfield(l, -2, cstr!("__index"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::job_gc) });
+    lua_setfield(l, -2, cstr!("__gc"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::job_status) });
+    lua_setfield(l, -2, cstr!("Status"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::job_cancel) });
+    lua_setfield(l, -2, cstr!("Cancel"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::job_wait) });
+    lua_setfield(l, -2, cstr!("Wait"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::job_then) });
+    lua_setfield(l, -2, cstr!("Then"));
+    lua_pop(l, 1);
+
+    // Register MongoDBCursor metatable
+    luaL_newmetatable(l, cstr!("MongoDBCursor"));
+    lua_pushvalue(l, -1);
+    lua_setfield(l, -2, cstr!("__index"));
+    lua_pushcfunction(l, utils::gc_userdata::<core::cursor::CursorHandle>);
+    lua_setfield(l, -2, cstr!("__gc"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::cursor_next) });
+    lua_setfield(l, -2, cstr!("Next"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::cursor_next_batch) });
+    lua_setfield(l, -2, cstr!("NextBatch"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::cursor_has_next) });
+    lua_setfield(l, -2, cstr!("HasNext"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::cursor_close) });
+    lua_setfield(l, -2, cstr!("Close"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::cursor_iterate) });
+    lua_setfield(l, -2, cstr!("Iterate"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::cursor_next_batch_async) });
+    lua_setfield(l, -2, cstr!("NextBatchAsync"));
     lua_pop(l, 1);
 
     // Register MongoDBCollection metatable
@@ -100,16 +243,30 @@ unsafe fn open(l: LuaState) -> i32 {
     lua_setfield(l, -2, cstr!("Find"));
     lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::find_one) });
     lua_setfield(l, -2, cstr!("FindOne"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::find_one_and_update) });
+    lua_setfield(l, -2, cstr!("FindOneAndUpdate"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::find_one_and_replace) });
+    lua_setfield(l, -2, cstr!("FindOneAndReplace"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::find_one_and_delete) });
+    lua_setfield(l, -2, cstr!("FindOneAndDelete"));
     lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::update_one) });
     lua_setfield(l, -2, cstr!("UpdateOne"));
     lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::update_many) });
     lua_setfield(l, -2, cstr!("UpdateMany"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::replace_one) });
+    lua_setfield(l, -2, cstr!("ReplaceOne"));
     lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::delete_one) });
     lua_setfield(l, -2, cstr!("DeleteOne"));
     lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::delete_many) });
     lua_setfield(l, -2, cstr!("DeleteMany"));
     lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::count_documents) });
     lua_setfield(l, -2, cstr!("Count"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::estimated_count) });
+    lua_setfield(l, -2, cstr!("EstimatedCount"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::distinct) });
+    lua_setfield(l, -2, cstr!("Distinct"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::bulk_write) });
+    lua_setfield(l, -2, cstr!("BulkWrite"));
 
     lua_pushcfunction(l, api::insert_one_async as LuaCFunction);
     lua_setfield(l, -2, cstr!("InsertOneAsync"));
@@ -119,22 +276,53 @@ unsafe fn open(l: LuaState) -> i32 {
     lua_setfield(l, -2, cstr!("FindAsync"));
     lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::find_one_async) });
     lua_setfield(l, -2, cstr!("FindOneAsync"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::find_one_and_update_async) });
+    lua_setfield(l, -2, cstr!("FindOneAndUpdateAsync"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::find_one_and_replace_async) });
+    lua_setfield(l, -2, cstr!("FindOneAndReplaceAsync"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::find_one_and_delete_async) });
+    lua_setfield(l, -2, cstr!("FindOneAndDeleteAsync"));
     lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::update_one_async) });
     lua_setfield(l, -2, cstr!("UpdateOneAsync"));
     lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::update_many_async) });
     lua_setfield(l, -2, cstr!("UpdateManyAsync"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::replace_one_async) });
+    lua_setfield(l, -2, cstr!("ReplaceOneAsync"));
     lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::delete_one_async) });
     lua_setfield(l, -2, cstr!("DeleteOneAsync"));
     lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::delete_many_async) });
     lua_setfield(l, -2, cstr!("DeleteManyAsync"));
     lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::count_documents_async) });
     lua_setfield(l, -2, cstr!("CountAsync"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::estimated_count_async) });
+    lua_setfield(l, -2, cstr!("EstimatedCountAsync"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::distinct_async) });
+    lua_setfield(l, -2, cstr!("DistinctAsync"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::bulk_write_async) });
+    lua_setfield(l, -2, cstr!("BulkWriteAsync"));
 
     // Aggregation
     lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::aggregate) });
     lua_setfield(l, -2, cstr!("Aggregate"));
     lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::aggregate_async) });
     lua_setfield(l, -2, cstr!("AggregateAsync"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::count_aggregate) });
+    lua_setfield(l, -2, cstr!("CountAggregate"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::count_aggregate_async) });
+    lua_setfield(l, -2, cstr!("CountAggregateAsync"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::group_by) });
+    lua_setfield(l, -2, cstr!("GroupBy"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::group_by_async) });
+    lua_setfield(l, -2, cstr!("GroupByAsync"));
+
+    // Cursors
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::find_cursor) });
+    lua_setfield(l, -2, cstr!("FindCursor"));
+    #[cfg(feature = "change-streams")]
+    {
+        lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::collection_watch) });
+        lua_setfield(l, -2, cstr!("Watch"));
+    }
 
     // Index management
     lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::create_index) });
@@ -143,7 +331,89 @@ unsafe fn open(l: LuaState) -> i32 {
     lua_setfield(l, -2, cstr!("ListIndexes"));
     lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::drop_index) });
     lua_setfield(l, -2, cstr!("DropIndex"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::create_indexes) });
+    lua_setfield(l, -2, cstr!("CreateIndexes"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::create_text_index) });
+    lua_setfield(l, -2, cstr!("CreateTextIndex"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::drop_indexes) });
+    lua_setfield(l, -2, cstr!("DropIndexes"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::create_indexes_async) });
+    lua_setfield(l, -2, cstr!("CreateIndexesAsync"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::create_text_index_async) });
+    lua_setfield(l, -2, cstr!("CreateTextIndexAsync"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::drop_indexes_async) });
+    lua_setfield(l, -2, cstr!("DropIndexesAsync"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::create_index_async) });
+    lua_setfield(l, -2, cstr!("CreateIndexAsync"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::list_indexes_async) });
+    lua_setfield(l, -2, cstr!("ListIndexesAsync"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::drop_index_async) });
+    lua_setfield(l, -2, cstr!("DropIndexAsync"));
+
+    lua_pop(l, 1);
+
+    // Register MongoDBObjectId metatable
+    luaL_newmetatable(l, cstr!("MongoDBObjectId"));
+    lua_pushvalue(l, -1);
+    lua_setfield(l, -2, cstr!("__index"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::object_id_hex) });
+    lua_setfield(l, -2, cstr!("Hex"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::object_id_timestamp) });
+    lua_setfield(l, -2, cstr!("Timestamp"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::object_id_hex) });
+    lua_setfield(l, -2, cstr!("__tostring"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::object_id_eq) });
+    lua_setfield(l, -2, cstr!("__eq"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::object_id_lt) });
+    lua_setfield(l, -2, cstr!("__lt"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::object_id_le) });
+    lua_setfield(l, -2, cstr!("__le"));
+    lua_pop(l, 1);
+
+    // Register MongoDBInt64 metatable
+    luaL_newmetatable(l, cstr!("MongoDBInt64"));
+    lua_pushvalue(l, -1);
+    lua_setfield(l, -2, cstr!("__index"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::int64_to_number) });
+    lua_setfield(l, -2, cstr!("ToNumber"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::int64_to_string) });
+    lua_setfield(l, -2, cstr!("ToString"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::int64_to_string) });
+    lua_setfield(l, -2, cstr!("__tostring"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::int64_concat) });
+    lua_setfield(l, -2, cstr!("__concat"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::int64_add) });
+    lua_setfield(l, -2, cstr!("__add"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::int64_sub) });
+    lua_setfield(l, -2, cstr!("__sub"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::int64_mul) });
+    lua_setfield(l, -2, cstr!("__mul"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::int64_div) });
+    lua_setfield(l, -2, cstr!("__div"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::int64_mod) });
+    lua_setfield(l, -2, cstr!("__mod"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::int64_unm) });
+    lua_setfield(l, -2, cstr!("__unm"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::int64_eq) });
+    lua_setfield(l, -2, cstr!("__eq"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::int64_lt) });
+    lua_setfield(l, -2, cstr!("__lt"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::int64_le) });
+    lua_setfield(l, -2, cstr!("__le"));
+    lua_pop(l, 1);
+
+    // Register the array and document marker metatables
+    luaL_newmetatable(l, cstr!("MongoDBArray"));
+    lua_pop(l, 1);
+    luaL_newmetatable(l, cstr!("MongoDBDocument"));
+    lua_pop(l, 1);
 
+    // Register the error metatable
+    luaL_newmetatable(l, cstr!("MongoDBError"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::error_tostring) });
+    lua_setfield(l, -2, cstr!("__tostring"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::error_concat) });
+    lua_setfield(l, -2, cstr!("__concat"));
     lua_pop(l, 1);
 
     // Create global MongoDB table
@@ -155,9 +425,33 @@ unsafe fn open(l: LuaState) -> i32 {
     lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::new_client_with_options) });
     lua_setfield(l, -2, cstr!("ClientWithOptions"));
 
+    // BSON types
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::new_object_id) });
+    lua_setfield(l, -2, cstr!("ObjectId"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::new_object_id_from_time) });
+    lua_setfield(l, -2, cstr!("ObjectIdFromTime"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::new_int64) });
+    lua_setfield(l, -2, cstr!("Int64"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::new_array) });
+    lua_setfield(l, -2, cstr!("Array"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::new_document) });
+    lua_setfield(l, -2, cstr!("Document"));
+
+    // Async jobs
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::await_job) });
+    lua_setfield(l, -2, cstr!("Await"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::set_queue_limits) });
+    lua_setfield(l, -2, cstr!("SetQueueLimits"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::queue_stats) });
+    lua_setfield(l, -2, cstr!("QueueStats"));
+    lua_pushcfunction(l, unsafe { std::mem::transmute::<unsafe extern "C" fn(LuaState) -> i32, LuaCFunction>(api::set_callback_budget) });
+    lua_setfield(l, -2, cstr!("SetCallbackBudget"));
+
     // Utility functions
     lua_pushcfunction(l, suppress_messages);
     lua_setfield(l, -2, cstr!("SuppressMessages"));
+    lua_pushcfunction(l, set_max_depth);
+    lua_setfield(l, -2, cstr!("SetMaxDepth"));
     lua_pushcfunction(l, get_version);
     lua_setfield(l, -2, cstr!("Version"));
 
@@ -173,6 +467,13 @@ extern "C" fn suppress_messages(l: LuaState) -> i32 {
     0
 }
 
+extern "C" fn set_max_depth(l: LuaState) -> i32 {
+    let depth = luaL_optinteger(l, 1, types::conversion::DEFAULT_MAX_DEPTH as _).max(1) as usize;
+    types::conversion::set_max_depth(depth);
+    info!("Maximum document nesting depth: {}", types::conversion::max_depth());
+    0
+}
+
 extern "C" fn get_version(l: LuaState) -> i32 {
     unsafe {
         let version = env!("CARGO_PKG_VERSION");
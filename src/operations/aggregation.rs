@@ -1,73 +1,82 @@
-use crate::core::runtime::block_on;
+use crate::core::runtime::block_on_timed;
+use crate::core::session::SessionHandle;
+use crate::operations::crud::collect_documents;
 use crate::error::{MongoError, MongoResult};
-use mongodb::{bson::Document, Collection};
+use mongodb::options::AggregateOptions;
+use mongodb::{bson::{Bson, Document}, Collection};
+use std::time::Duration;
 
-pub fn aggregate(collection: &Collection<Document>, pipeline: Vec<Document>) -> MongoResult<Vec<Document>> {
+pub fn aggregate(collection: &Collection<Document>, pipeline: Vec<Document>, session: Option<SessionHandle>, max_time: Option<Duration>) -> MongoResult<Vec<Document>> {
     let collection = collection.clone();
-    block_on(async move {
-        let mut cursor = collection
-            .aggregate(pipeline)
+    block_on_timed(async move {
+        aggregate_documents(&collection, pipeline, session.as_ref(), max_time)
             .await
-            .map_err(|e| MongoError::Operation(format!("Aggregation failed: {}", e)))?;
-
-        let mut results = Vec::new();
+            .map_err(|e| MongoError::driver("Aggregation failed", e))
+    })
+}
 
-        use futures::TryStreamExt;
-        while let Some(doc) = cursor.try_next().await
-            .map_err(|e| MongoError::Operation(format!("Cursor error: {}", e)))? {
-            results.push(doc);
+/// Runs `pipeline`; `max_time` is sent as `maxTimeMS` so the server gives up too
+pub(crate) async fn aggregate_documents(collection: &Collection<Document>, pipeline: Vec<Document>, session: Option<&SessionHandle>, max_time: Option<Duration>) -> mongodb::error::Result<Vec<Document>> {
+    let options = AggregateOptions::builder().max_time(max_time).build();
+    match session {
+        Some(session) => {
+            let mut guard = session.lock().await;
+            let result = collection.aggregate(pipeline).with_options(options).session(&mut *guard).await;
+            session.observe(&result);
+            let mut cursor = result?;
+            collect_documents(cursor.stream(&mut guard)).await
         }
+        None => {
+            let cursor = collection.aggregate(pipeline).with_options(options).await?;
+            collect_documents(cursor).await
+        }
+    }
+}
 
-        Ok(results)
+pub fn count_aggregate(collection: &Collection<Document>, filter: Document, max_time: Option<Duration>) -> MongoResult<i64> {
+    let collection = collection.clone();
+    block_on_timed(async move {
+        count_matching(&collection, filter, max_time)
+            .await
+            .map_err(|e| MongoError::driver("Count aggregation failed", e))
     })
 }
 
-pub fn count_aggregate(collection: &Collection<Document>, filter: Document) -> MongoResult<i64> {
-    let collection = collection.clone();
+/// Counts the documents matching `filter` with a `$match`/`$count` pipeline
+pub(crate) async fn count_matching(collection: &Collection<Document>, filter: Document, max_time: Option<Duration>) -> mongodb::error::Result<i64> {
     let pipeline = vec![
         mongodb::bson::doc! { "$match": filter },
         mongodb::bson::doc! { "$count": "total" },
     ];
 
-    block_on(async move {
-        let mut cursor = collection
-            .aggregate(pipeline)
-            .await
-            .map_err(|e| MongoError::Operation(format!("Count aggregation failed: {}", e)))?;
-
-        use futures::TryStreamExt;
-        if let Some(doc) = cursor.try_next().await
-            .map_err(|e| MongoError::Operation(format!("Cursor error: {}", e)))? {
-            if let Ok(count) = doc.get_i64("total") {
-                return Ok(count);
-            }
-        }
-
-        Ok(0)
+    let documents = aggregate_documents(collection, pipeline, None, max_time).await?;
+    // `$count` reports an int32 unless the total no longer fits
+    Ok(match documents.first().and_then(|doc| doc.get("total")) {
+        Some(Bson::Int32(count)) => *count as i64,
+        Some(Bson::Int64(count)) => *count,
+        _ => 0,
     })
 }
 
-pub fn group_by(collection: &Collection<Document>, group_field: &str, accumulator_field: Option<&str>) -> MongoResult<Vec<Document>> {
-    let accumulator = if let Some(field) = accumulator_field {
-        mongodb::bson::doc! { "sum": { "$sum": format!("${}", field) } }
-    } else {
-        mongodb::bson::doc! { "count": { "$sum": 1 } }
-    };
+pub fn group_by(collection: &Collection<Document>, group_field: &str, accumulator_field: Option<&str>, max_time: Option<Duration>) -> MongoResult<Vec<Document>> {
+    aggregate(collection, group_pipeline(group_field, accumulator_field), None, max_time)
+}
 
-    let pipeline = vec![
-        mongodb::bson::doc! {
-            "$group": {
-                "_id": format!("${}", group_field),
-                "values": accumulator
-            }
-        }
-    ];
+/// Groups by `group_field`, summing `accumulator_field` into `sum` or
+/// counting the documents into `count` when no field is given
+pub(crate) fn group_pipeline(group_field: &str, accumulator_field: Option<&str>) -> Vec<Document> {
+    let mut group = mongodb::bson::doc! { "_id": format!("${}", group_field) };
+    match accumulator_field {
+        Some(field) => group.insert("sum", mongodb::bson::doc! { "$sum": format!("${}", field) }),
+        None => group.insert("count", mongodb::bson::doc! { "$sum": 1 }),
+    };
 
-    aggregate(collection, pipeline)
+    vec![mongodb::bson::doc! { "$group": group }]
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
     use mongodb::bson::doc;
 
     #[test]
@@ -78,4 +87,10 @@ mod tests {
         ];
         assert_eq!(pipeline.len(), 2);
     }
+
+    #[test]
+    fn test_group_pipeline() {
+        assert_eq!(group_pipeline("job", None), vec![doc! { "$group": { "_id": "$job", "count": { "$sum": 1 } } }]);
+        assert_eq!(group_pipeline("job", Some("money")), vec![doc! { "$group": { "_id": "$job", "sum": { "$sum": "$money" } } }]);
+    }
 }
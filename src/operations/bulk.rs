@@ -0,0 +1,220 @@
+//! Mixed writes sent in a single `bulkWrite` round trip
+//!
+//! Each Lua operation arrives as a document with a single key naming it,
+//! e.g. `{ updateOne = { filter = ..., update = ... } }`. The command runs
+//! through `Client::bulk_write`, which needs MongoDB 8.0 or newer.
+
+use crate::core::runtime::block_on_timed;
+use crate::core::session::SessionHandle;
+use crate::error::{MongoError, MongoResult};
+use crate::operations::options::{get_bool, get_document};
+use crate::with_session;
+use mongodb::bson::{Bson, Document};
+use mongodb::error::ErrorKind;
+use mongodb::options::{
+    BulkWriteOptions, DeleteManyModel, DeleteOneModel, InsertOneModel, ReplaceOneModel, UpdateManyModel,
+    UpdateModifications, UpdateOneModel, WriteModel,
+};
+use mongodb::results::SummaryBulkWriteResult;
+use mongodb::error::PartialBulkWriteResult;
+use mongodb::{Client, Collection, Namespace};
+
+/// An operation the server rejected; `index` counts from 0 like the driver
+#[derive(Debug, Clone, PartialEq)]
+pub struct BulkWriteFailure {
+    pub index: usize,
+    pub code: i32,
+    pub message: String,
+}
+
+/// Aggregated outcome of a bulk write, including the operations that failed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BulkWriteSummary {
+    pub inserted: i64,
+    pub matched: i64,
+    pub modified: i64,
+    pub upserted: i64,
+    pub deleted: i64,
+    pub failures: Vec<BulkWriteFailure>,
+}
+
+impl From<SummaryBulkWriteResult> for BulkWriteSummary {
+    fn from(result: SummaryBulkWriteResult) -> Self {
+        Self {
+            inserted: result.inserted_count,
+            matched: result.matched_count,
+            modified: result.modified_count,
+            upserted: result.upserted_count,
+            deleted: result.deleted_count,
+            failures: Vec::new(),
+        }
+    }
+}
+
+fn required_document(doc: &Document, key: &str) -> Result<Document, String> {
+    get_document(doc, key)?.ok_or_else(|| format!("Missing '{}'", key))
+}
+
+/// Reads `update`, which is either an operator document or a pipeline
+fn update_modifications(doc: &Document) -> Result<UpdateModifications, String> {
+    match doc.get("update") {
+        Some(Bson::Document(update)) => Ok(UpdateModifications::Document(update.clone())),
+        Some(Bson::Array(stages)) => stages
+            .iter()
+            .map(|stage| match stage {
+                Bson::Document(stage) => Ok(stage.clone()),
+                other => Err(format!("Update pipeline stages must be tables, got {:?}", other.element_type())),
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(UpdateModifications::Pipeline),
+        None | Some(Bson::Null) => Err("Missing 'update'".to_string()),
+        Some(other) => Err(format!("'update' must be a table, got {:?}", other.element_type())),
+    }
+}
+
+fn hint(doc: &Document) -> Option<Bson> {
+    doc.get("hint").filter(|hint| !matches!(hint, Bson::Null)).cloned()
+}
+
+/// Builds the driver model for one `{ <operation> = { ... } }` entry
+pub fn write_model(namespace: &Namespace, operation: &Document) -> Result<WriteModel, String> {
+    let (name, spec) = match operation.iter().next() {
+        Some((name, Bson::Document(spec))) if operation.len() == 1 => (name.as_str(), spec),
+        _ => return Err("Expected a table with a single operation key".to_string()),
+    };
+    let namespace = namespace.clone();
+
+    let model = match name {
+        "insertOne" => WriteModel::InsertOne(
+            InsertOneModel::builder()
+                .namespace(namespace)
+                .document(required_document(spec, "document")?)
+                .build(),
+        ),
+        "updateOne" => WriteModel::UpdateOne(
+            UpdateOneModel::builder()
+                .namespace(namespace)
+                .filter(required_document(spec, "filter")?)
+                .update(update_modifications(spec)?)
+                .collation(get_document(spec, "collation")?)
+                .hint(hint(spec))
+                .upsert(get_bool(spec, "upsert")?)
+                .build(),
+        ),
+        "updateMany" => WriteModel::UpdateMany(
+            UpdateManyModel::builder()
+                .namespace(namespace)
+                .filter(required_document(spec, "filter")?)
+                .update(update_modifications(spec)?)
+                .collation(get_document(spec, "collation")?)
+                .hint(hint(spec))
+                .upsert(get_bool(spec, "upsert")?)
+                .build(),
+        ),
+        "replaceOne" => WriteModel::ReplaceOne(
+            ReplaceOneModel::builder()
+                .namespace(namespace)
+                .filter(required_document(spec, "filter")?)
+                .replacement(required_document(spec, "replacement")?)
+                .collation(get_document(spec, "collation")?)
+                .hint(hint(spec))
+                .upsert(get_bool(spec, "upsert")?)
+                .build(),
+        ),
+        "deleteOne" => WriteModel::DeleteOne(
+            DeleteOneModel::builder()
+                .namespace(namespace)
+                .filter(required_document(spec, "filter")?)
+                .collation(get_document(spec, "collation")?)
+                .hint(hint(spec))
+                .build(),
+        ),
+        "deleteMany" => WriteModel::DeleteMany(
+            DeleteManyModel::builder()
+                .namespace(namespace)
+                .filter(required_document(spec, "filter")?)
+                .collation(get_document(spec, "collation")?)
+                .hint(hint(spec))
+                .build(),
+        ),
+        other => return Err(format!("Unknown bulk operation '{}'", other)),
+    };
+
+    Ok(model)
+}
+
+/// Options accepted by `BulkWrite` and `BulkWriteAsync`
+pub fn bulk_write_options(doc: &Document) -> Result<BulkWriteOptions, String> {
+    let mut options = BulkWriteOptions::default();
+    options.ordered = get_bool(doc, "ordered")?;
+    options.bypass_document_validation = get_bool(doc, "bypass_document_validation")?;
+    Ok(options)
+}
+
+pub fn bulk_write(collection: Collection<Document>, models: Vec<WriteModel>, options: BulkWriteOptions, session: Option<SessionHandle>) -> MongoResult<BulkWriteSummary> {
+    block_on_timed(async move {
+        run_bulk_write(collection.client(), models, options, session.as_ref())
+            .await
+            .map_err(|e| MongoError::driver("Bulk write failed", e))
+    })
+}
+
+/// Runs the bulk write; per-operation failures are reported in the summary
+/// rather than as an error, so the caller still sees what succeeded
+pub(crate) async fn run_bulk_write(client: &Client, models: Vec<WriteModel>, options: BulkWriteOptions, session: Option<&SessionHandle>) -> mongodb::error::Result<BulkWriteSummary> {
+    let result = with_session!(client.bulk_write(models).with_options(options), session);
+
+    match result {
+        Ok(result) => Ok(result.into()),
+        Err(e) => match *e.kind {
+            ErrorKind::BulkWrite(ref error) if !error.write_errors.is_empty() => {
+                let mut summary = match &error.partial_result {
+                    Some(PartialBulkWriteResult::Summary(result)) => BulkWriteSummary::from(result.clone()),
+                    Some(PartialBulkWriteResult::Verbose(result)) => BulkWriteSummary::from(result.summary.clone()),
+                    None => BulkWriteSummary::default(),
+                };
+                summary.failures = error
+                    .write_errors
+                    .iter()
+                    .map(|(index, failure)| BulkWriteFailure {
+                        index: *index,
+                        code: failure.code,
+                        message: failure.message.clone(),
+                    })
+                    .collect();
+                summary.failures.sort_unstable_by_key(|failure| failure.index);
+                Ok(summary)
+            }
+            _ => Err(e),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::doc;
+
+    fn namespace() -> Namespace {
+        Namespace { db: "game".to_string(), coll: "players".to_string() }
+    }
+
+    #[test]
+    fn test_write_model() {
+        let model = write_model(&namespace(), &doc! {
+            "updateOne": { "filter": { "steamid": "1" }, "update": { "$inc": { "kills": 1_i64 } }, "upsert": true }
+        })
+        .unwrap();
+        assert!(matches!(model, WriteModel::UpdateOne(ref m) if m.upsert == Some(true)));
+
+        let model = write_model(&namespace(), &doc! { "deleteMany": { "filter": {} } }).unwrap();
+        assert!(matches!(model, WriteModel::DeleteMany(_)));
+    }
+
+    #[test]
+    fn test_write_model_errors() {
+        assert!(write_model(&namespace(), &doc! { "upsertOne": { "filter": {} } }).is_err());
+        assert!(write_model(&namespace(), &doc! { "insertOne": {} }).is_err());
+        assert!(write_model(&namespace(), &doc! { "deleteOne": {}, "deleteMany": {} }).is_err());
+    }
+}
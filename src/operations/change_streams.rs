@@ -0,0 +1,228 @@
+use futures::StreamExt;
+use mongodb::bson::{Bson, Document};
+use mongodb::change_stream::event::{ChangeStreamEvent, ResumeToken};
+use mongodb::change_stream::ChangeStream;
+use mongodb::error::{ErrorKind, RESUMABLE_CHANGE_STREAM_ERROR};
+use mongodb::options::{ChangeStreamOptions, FullDocumentBeforeChangeType, FullDocumentType};
+use mongodb::{Client, Collection, Database};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// How often a broken stream is reopened before the error is handed to Lua
+const MAX_RESUME_ATTEMPTS: u32 = 5;
+
+/// Delay before the first reopen attempt, doubled on every further attempt
+const RESUME_BACKOFF: Duration = Duration::from_millis(500);
+
+/// What a change stream observes
+#[derive(Debug, Clone)]
+pub enum WatchTarget {
+    Client(Client),
+    Database(Database),
+    Collection(Collection<Document>),
+}
+
+/// Shared between a running change stream and its Lua handle
+#[derive(Debug, Clone)]
+pub struct WatchControl {
+    stop: Arc<Notify>,
+    active: Arc<AtomicBool>,
+    resume_token: Arc<Mutex<Option<ResumeToken>>>,
+}
+
+impl Default for WatchControl {
+    fn default() -> Self {
+        Self {
+            stop: Arc::new(Notify::new()),
+            active: Arc::new(AtomicBool::new(true)),
+            resume_token: Arc::new(Mutex::new(None)),
+        }
+    }
+}
+
+impl WatchControl {
+    pub fn stop(&self) {
+        self.active.store(false, Ordering::Release);
+        self.stop.notify_one();
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Acquire)
+    }
+
+    /// The token of the last delivered event, usable as `resume_after` for a later watch
+    pub fn resume_token(&self) -> Option<ResumeToken> {
+        self.resume_token.lock().ok().and_then(|t| t.clone())
+    }
+
+    fn remember(&self, token: Option<ResumeToken>) {
+        if let (Some(token), Ok(mut current)) = (token, self.resume_token.lock()) {
+            *current = Some(token);
+        }
+    }
+}
+
+/// Builds `ChangeStreamOptions` from the Lua options table
+pub fn change_stream_options(doc: &Document) -> Result<ChangeStreamOptions, String> {
+    let mut options = ChangeStreamOptions::default();
+
+    if let Some(value) = doc.get("full_document") {
+        options.full_document = Some(
+            mongodb::bson::from_bson::<FullDocumentType>(value.clone())
+                .map_err(|e| format!("Invalid full_document: {}", e))?,
+        );
+    }
+
+    if let Some(value) = doc.get("full_document_before_change") {
+        options.full_document_before_change = Some(
+            mongodb::bson::from_bson::<FullDocumentBeforeChangeType>(value.clone())
+                .map_err(|e| format!("Invalid full_document_before_change: {}", e))?,
+        );
+    }
+
+    if let Some(value) = doc.get("resume_after") {
+        options.resume_after = Some(
+            mongodb::bson::from_bson::<ResumeToken>(value.clone())
+                .map_err(|e| format!("Invalid resume_after: {}", e))?,
+        );
+    }
+
+    match doc.get("batch_size") {
+        Some(Bson::Int32(n)) => options.batch_size = Some(*n as u32),
+        Some(Bson::Int64(n)) => options.batch_size = Some(*n as u32),
+        _ => {}
+    }
+
+    match doc.get("max_await_time_ms") {
+        Some(Bson::Int32(ms)) => options.max_await_time = Some(Duration::from_millis(*ms as u64)),
+        Some(Bson::Int64(ms)) => options.max_await_time = Some(Duration::from_millis(*ms as u64)),
+        _ => {}
+    }
+
+    Ok(options)
+}
+
+async fn open_stream(target: &WatchTarget, pipeline: Vec<Document>, options: ChangeStreamOptions) -> mongodb::error::Result<ChangeStream<ChangeStreamEvent<Document>>> {
+    match target {
+        WatchTarget::Client(client) => client.watch().pipeline(pipeline).with_options(options).await,
+        WatchTarget::Database(database) => database.watch().pipeline(pipeline).with_options(options).await,
+        WatchTarget::Collection(collection) => collection.watch().pipeline(pipeline).with_options(options).await,
+    }
+}
+
+fn is_resumable(error: &mongodb::error::Error) -> bool {
+    error.contains_label(RESUMABLE_CHANGE_STREAM_ERROR)
+        || matches!(
+            *error.kind,
+            ErrorKind::Io(_) | ErrorKind::ConnectionPoolCleared { .. } | ErrorKind::ServerSelection { .. }
+        )
+}
+
+/// Runs a change stream until it is stopped or fails for good, handing every event to `on_event`.
+///
+/// The driver already resumes once on its own; when that is not enough (e.g. the whole
+/// replica set was unreachable for a while) the stream is reopened from the last seen
+/// resume token with a growing delay.
+pub(crate) async fn run_change_stream<F>(target: WatchTarget, pipeline: Vec<Document>, options: ChangeStreamOptions, control: WatchControl, mut on_event: F) -> mongodb::error::Result<()>
+where
+    F: FnMut(Document),
+{
+    let mut options = options;
+    let mut attempts = 0;
+
+    let result = loop {
+        if !control.is_active() {
+            break Ok(());
+        }
+
+        let opened = tokio::select! {
+            _ = control.stop.notified() => break Ok(()),
+            opened = open_stream(&target, pipeline.clone(), options.clone()) => opened,
+        };
+
+        let error = match opened {
+            Ok(mut stream) => loop {
+                let next = tokio::select! {
+                    _ = control.stop.notified() => return finish(&control, Ok(())),
+                    next = stream.next() => next,
+                };
+
+                match next {
+                    Some(Ok(event)) => {
+                        attempts = 0;
+                        control.remember(stream.resume_token());
+                        match mongodb::bson::to_document(&event) {
+                            Ok(doc) => on_event(doc),
+                            Err(e) => log::error!("Failed to convert change event: {}", e),
+                        }
+                    }
+                    Some(Err(e)) => {
+                        control.remember(stream.resume_token());
+                        break e;
+                    }
+                    None => return finish(&control, Ok(())),
+                }
+            },
+            Err(e) => e,
+        };
+
+        if !is_resumable(&error) || attempts >= MAX_RESUME_ATTEMPTS {
+            break Err(error);
+        }
+
+        if let Some(token) = control.resume_token() {
+            options.resume_after = Some(token);
+            options.start_after = None;
+            options.start_at_operation_time = None;
+        }
+
+        let delay = RESUME_BACKOFF * 2u32.pow(attempts);
+        attempts += 1;
+        log::warn!("Change stream interrupted, resuming in {:?}: {}", delay, error);
+
+        tokio::select! {
+            _ = control.stop.notified() => break Ok(()),
+            _ = tokio::time::sleep(delay) => {}
+        }
+    };
+
+    finish(&control, result)
+}
+
+fn finish(control: &WatchControl, result: mongodb::error::Result<()>) -> mongodb::error::Result<()> {
+    control.active.store(false, Ordering::Release);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::doc;
+
+    #[test]
+    fn test_change_stream_options() {
+        let options = change_stream_options(&doc! {
+            "full_document": "updateLookup",
+            "batch_size": 50,
+        })
+        .unwrap();
+
+        assert!(matches!(options.full_document, Some(FullDocumentType::UpdateLookup)));
+        assert_eq!(options.batch_size, Some(50));
+    }
+
+    #[test]
+    fn test_invalid_change_stream_options() {
+        assert!(change_stream_options(&doc! { "full_document": 5 }).is_err());
+    }
+
+    #[test]
+    fn test_watch_control_stop() {
+        let control = WatchControl::default();
+        assert!(control.is_active());
+        control.stop();
+        assert!(!control.is_active());
+    }
+}
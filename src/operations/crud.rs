@@ -1,144 +1,228 @@
-use mongodb::{Collection, bson::Document};
-use crate::core::runtime::block_on;
+use mongodb::{Collection, bson::{Bson, Document}};
+use mongodb::options::{
+    Acknowledgment, CountOptions, DistinctOptions, EstimatedDocumentCountOptions, FindOneAndDeleteOptions, FindOneAndReplaceOptions,
+    FindOneAndUpdateOptions, FindOneOptions, FindOptions,
+};
+use mongodb::results::{DeleteResult, UpdateResult};
+use crate::core::runtime::block_on_timed;
+use crate::core::session::SessionHandle;
 use crate::error::{MongoError, MongoResult};
+use crate::with_session;
+use std::collections::HashMap;
+use std::time::Duration;
 
-pub fn insert_one(collection: Collection<Document>, document: Document) -> MongoResult<String> {
-    block_on(async move {
-        let result = collection
-            .insert_one(document)
-            .await
-            .map_err(|e| MongoError::Operation(format!("Insert failed: {}", e)))?;
+pub fn insert_one(collection: Collection<Document>, document: Document, session: Option<SessionHandle>) -> MongoResult<Bson> {
+    block_on_timed(async move {
+        let result = with_session!(collection.insert_one(document), session.as_ref())
+            .map_err(|e| MongoError::driver("Insert failed", e))?;
 
-        Ok(result.inserted_id.to_string())
+        Ok(result.inserted_id)
     })
 }
 
-pub fn insert_many(collection: Collection<Document>, documents: Vec<Document>) -> MongoResult<Vec<String>> {
-    block_on(async move {
-        let result = collection
-            .insert_many(documents)
-            .await
-            .map_err(|e| MongoError::Operation(format!("Insert many failed: {}", e)))?;
+pub fn insert_many(collection: Collection<Document>, documents: Vec<Document>, session: Option<SessionHandle>) -> MongoResult<Vec<Bson>> {
+    block_on_timed(async move {
+        let result = with_session!(collection.insert_many(documents), session.as_ref())
+            .map_err(|e| MongoError::driver("Insert many failed", e))?;
 
-        Ok(result.inserted_ids
-            .values()
-            .map(|id| id.to_string())
-            .collect())
+        Ok(ordered_ids(result.inserted_ids))
     })
 }
 
-pub fn find(collection: Collection<Document>, filter: Document, limit: Option<i64>) -> MongoResult<Vec<Document>> {
-    block_on(async move {
-        let mut cursor = collection
-            .find(filter)
+/// The driver reports inserted ids keyed by input position; Lua wants them as a sequence
+pub(crate) fn ordered_ids(inserted_ids: HashMap<usize, Bson>) -> Vec<Bson> {
+    let mut ids: Vec<(usize, Bson)> = inserted_ids.into_iter().collect();
+    ids.sort_unstable_by_key(|(index, _)| *index);
+    ids.into_iter().map(|(_, id)| id).collect()
+}
+
+/// Outcome of an update, replace or delete, as handed to Lua
+#[derive(Debug, Clone, PartialEq)]
+pub struct WriteResult {
+    pub matched: u64,
+    pub modified: u64,
+    pub deleted: u64,
+    pub upserted_id: Option<Bson>,
+    pub acknowledged: bool,
+}
+
+impl WriteResult {
+    pub fn from_update(result: UpdateResult, acknowledged: bool) -> Self {
+        Self {
+            matched: result.matched_count,
+            modified: result.modified_count,
+            deleted: 0,
+            upserted_id: result.upserted_id,
+            acknowledged,
+        }
+    }
+
+    pub fn from_delete(result: DeleteResult, acknowledged: bool) -> Self {
+        Self {
+            matched: 0,
+            modified: 0,
+            deleted: result.deleted_count,
+            upserted_id: None,
+            acknowledged,
+        }
+    }
+}
+
+/// Whether writes through `collection` wait for the server; the driver reports
+/// zero counts for unacknowledged writes instead of saying so
+pub(crate) fn is_acknowledged(collection: &Collection<Document>) -> bool {
+    collection
+        .write_concern()
+        .is_none_or(|concern| concern.w != Some(Acknowledgment::Nodes(0)) || concern.journal == Some(true))
+}
+
+pub fn find(collection: Collection<Document>, filter: Document, options: FindOptions, session: Option<SessionHandle>) -> MongoResult<Vec<Document>> {
+    block_on_timed(async move {
+        find_documents(&collection, filter, options, session.as_ref())
             .await
-            .map_err(|e| MongoError::Operation(format!("Find failed: {}", e)))?;
-
-        let mut documents = Vec::new();
-        let mut count = 0i64;
-
-        use futures::TryStreamExt;
-        while let Some(doc) = cursor.try_next().await
-            .map_err(|e| MongoError::Operation(format!("Cursor error: {}", e)))? {
-            documents.push(doc);
-            count += 1;
-
-            if let Some(limit_val) = limit {
-                if count >= limit_val {
-                    break;
-                }
-            }
+            .map_err(|e| MongoError::driver("Find failed", e))
+    })
+}
+
+/// Runs a find and drains the cursor.
+pub(crate) async fn find_documents(collection: &Collection<Document>, filter: Document, options: FindOptions, session: Option<&SessionHandle>) -> mongodb::error::Result<Vec<Document>> {
+    match session {
+        Some(session) => {
+            let mut guard = session.lock().await;
+            let result = collection.find(filter).with_options(options).session(&mut *guard).await;
+            session.observe(&result);
+            let mut cursor = result?;
+            collect_documents(cursor.stream(&mut guard)).await
         }
+        None => {
+            let cursor = collection.find(filter).with_options(options).await?;
+            collect_documents(cursor).await
+        }
+    }
+}
 
-        Ok(documents)
+pub(crate) async fn collect_documents<S>(cursor: S) -> mongodb::error::Result<Vec<Document>>
+where
+    S: futures::Stream<Item = mongodb::error::Result<Document>> + Unpin,
+{
+    use futures::TryStreamExt;
+
+    cursor.try_collect().await
+}
+
+pub fn find_one(collection: Collection<Document>, filter: Document, options: FindOneOptions, session: Option<SessionHandle>) -> MongoResult<Option<Document>> {
+    block_on_timed(async move {
+        with_session!(collection.find_one(filter).with_options(options), session.as_ref())
+            .map_err(|e| MongoError::driver("Find one failed", e))
     })
 }
 
-pub fn find_one(collection: Collection<Document>, filter: Document) -> MongoResult<Option<Document>> {
-    block_on(async move {
-        collection
-            .find_one(filter)
-            .await
-            .map_err(|e| MongoError::Operation(format!("Find one failed: {}", e)))
+pub fn find_one_and_update(collection: Collection<Document>, filter: Document, update: Document, options: FindOneAndUpdateOptions, session: Option<SessionHandle>) -> MongoResult<Option<Document>> {
+    block_on_timed(async move {
+        with_session!(collection.find_one_and_update(filter, update).with_options(options), session.as_ref())
+            .map_err(|e| MongoError::driver("Find one and update failed", e))
+    })
+}
+
+pub fn find_one_and_replace(collection: Collection<Document>, filter: Document, replacement: Document, options: FindOneAndReplaceOptions, session: Option<SessionHandle>) -> MongoResult<Option<Document>> {
+    block_on_timed(async move {
+        with_session!(collection.find_one_and_replace(filter, replacement).with_options(options), session.as_ref())
+            .map_err(|e| MongoError::driver("Find one and replace failed", e))
     })
 }
 
-pub fn update_one(collection: Collection<Document>, filter: Document, update: Document, upsert: bool) -> MongoResult<i64> {
-    block_on(async move {
+pub fn find_one_and_delete(collection: Collection<Document>, filter: Document, options: FindOneAndDeleteOptions, session: Option<SessionHandle>) -> MongoResult<Option<Document>> {
+    block_on_timed(async move {
+        with_session!(collection.find_one_and_delete(filter).with_options(options), session.as_ref())
+            .map_err(|e| MongoError::driver("Find one and delete failed", e))
+    })
+}
+
+pub fn update_one(collection: Collection<Document>, filter: Document, update: Document, upsert: bool, session: Option<SessionHandle>) -> MongoResult<WriteResult> {
+    block_on_timed(async move {
         let options = mongodb::options::UpdateOptions::builder()
             .upsert(upsert)
             .build();
 
-        let result = collection
-            .update_one(filter, update)
-            .with_options(options)
-            .await
-            .map_err(|e| MongoError::Operation(format!("Update failed: {}", e)))?;
+        let result = with_session!(collection.update_one(filter, update).with_options(options), session.as_ref())
+            .map_err(|e| MongoError::driver("Update failed", e))?;
 
-        Ok(result.modified_count as i64)
+        Ok(WriteResult::from_update(result, is_acknowledged(&collection)))
     })
 }
 
-pub fn update_many(collection: Collection<Document>, filter: Document, update: Document, upsert: bool) -> MongoResult<i64> {
-    block_on(async move {
+pub fn update_many(collection: Collection<Document>, filter: Document, update: Document, upsert: bool, session: Option<SessionHandle>) -> MongoResult<WriteResult> {
+    block_on_timed(async move {
         let options = mongodb::options::UpdateOptions::builder()
             .upsert(upsert)
             .build();
 
-        let result = collection
-            .update_many(filter, update)
-            .with_options(options)
-            .await
-            .map_err(|e| MongoError::Operation(format!("Update many failed: {}", e)))?;
+        let result = with_session!(collection.update_many(filter, update).with_options(options), session.as_ref())
+            .map_err(|e| MongoError::driver("Update many failed", e))?;
 
-        Ok(result.modified_count as i64)
+        Ok(WriteResult::from_update(result, is_acknowledged(&collection)))
     })
 }
 
-pub fn replace_one(collection: Collection<Document>, filter: Document, replacement: Document, upsert: bool) -> MongoResult<i64> {
-    block_on(async move {
+pub fn replace_one(collection: Collection<Document>, filter: Document, replacement: Document, upsert: bool, session: Option<SessionHandle>) -> MongoResult<WriteResult> {
+    block_on_timed(async move {
         let options = mongodb::options::ReplaceOptions::builder()
             .upsert(upsert)
             .build();
 
-        let result = collection
-            .replace_one(filter, replacement)
-            .with_options(options)
-            .await
-            .map_err(|e| MongoError::Operation(format!("Replace failed: {}", e)))?;
+        let result = with_session!(collection.replace_one(filter, replacement).with_options(options), session.as_ref())
+            .map_err(|e| MongoError::driver("Replace failed", e))?;
 
-        Ok(result.modified_count as i64)
+        Ok(WriteResult::from_update(result, is_acknowledged(&collection)))
     })
 }
 
-pub fn delete_one(collection: Collection<Document>, filter: Document) -> MongoResult<i64> {
-    block_on(async move {
-        let result = collection
-            .delete_one(filter)
-            .await
-            .map_err(|e| MongoError::Operation(format!("Delete failed: {}", e)))?;
+pub fn delete_one(collection: Collection<Document>, filter: Document, session: Option<SessionHandle>) -> MongoResult<WriteResult> {
+    block_on_timed(async move {
+        let result = with_session!(collection.delete_one(filter), session.as_ref())
+            .map_err(|e| MongoError::driver("Delete failed", e))?;
 
-        Ok(result.deleted_count as i64)
+        Ok(WriteResult::from_delete(result, is_acknowledged(&collection)))
     })
 }
 
-pub fn delete_many(collection: Collection<Document>, filter: Document) -> MongoResult<i64> {
-    block_on(async move {
-        let result = collection
-            .delete_many(filter)
-            .await
-            .map_err(|e| MongoError::Operation(format!("Delete many failed: {}", e)))?;
+pub fn delete_many(collection: Collection<Document>, filter: Document, session: Option<SessionHandle>) -> MongoResult<WriteResult> {
+    block_on_timed(async move {
+        let result = with_session!(collection.delete_many(filter), session.as_ref())
+            .map_err(|e| MongoError::driver("Delete many failed", e))?;
 
-        Ok(result.deleted_count as i64)
+        Ok(WriteResult::from_delete(result, is_acknowledged(&collection)))
     })
 }
 
-pub fn count_documents(collection: Collection<Document>, filter: Document) -> MongoResult<i64> {
-    block_on(async move {
+pub fn count_documents(collection: Collection<Document>, filter: Document, session: Option<SessionHandle>, max_time: Option<Duration>) -> MongoResult<i64> {
+    let options = CountOptions::builder().max_time(max_time).build();
+    block_on_timed(async move {
+        let count = with_session!(collection.count_documents(filter).with_options(options), session.as_ref())
+            .map_err(|e| MongoError::driver("Count failed", e))?;
+
+        Ok(count as i64)
+    })
+}
+
+pub fn distinct(collection: Collection<Document>, field: String, filter: Document, session: Option<SessionHandle>, max_time: Option<Duration>) -> MongoResult<Vec<Bson>> {
+    let options = DistinctOptions::builder().max_time(max_time).build();
+    block_on_timed(async move {
+        with_session!(collection.distinct(field, filter).with_options(options), session.as_ref())
+            .map_err(|e| MongoError::driver("Distinct failed", e))
+    })
+}
+
+/// Reads the count from collection metadata instead of scanning documents,
+/// so it ignores filters and may be off after an unclean shutdown
+pub fn estimated_document_count(collection: Collection<Document>, max_time: Option<Duration>) -> MongoResult<i64> {
+    let options = EstimatedDocumentCountOptions::builder().max_time(max_time).build();
+    block_on_timed(async move {
         let count = collection
-            .count_documents(filter)
+            .estimated_document_count()
+            .with_options(options)
             .await
-            .map_err(|e| MongoError::Operation(format!("Count failed: {}", e)))?;
+            .map_err(|e| MongoError::driver("Estimated count failed", e))?;
 
         Ok(count as i64)
     })
@@ -157,4 +241,26 @@ mod tests {
         };
         assert_eq!(doc.len(), 2);
     }
+
+    #[test]
+    fn test_ordered_ids() {
+        let ids = HashMap::from([
+            (2, Bson::String("c".to_string())),
+            (0, Bson::Int64(1)),
+            (1, Bson::String("b".to_string())),
+        ]);
+        assert_eq!(ordered_ids(ids), vec![Bson::Int64(1), Bson::String("b".to_string()), Bson::String("c".to_string())]);
+    }
+
+    #[test]
+    fn test_write_result_from_upsert() {
+        let mut update = UpdateResult::default();
+        update.upserted_id = Some(Bson::Int64(7));
+
+        let result = WriteResult::from_update(update, true);
+        assert_eq!(result.matched, 0);
+        assert_eq!(result.modified, 0);
+        assert_eq!(result.upserted_id, Some(Bson::Int64(7)));
+        assert!(result.acknowledged);
+    }
 }
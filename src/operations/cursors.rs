@@ -0,0 +1,46 @@
+use crate::core::cursor::CursorHandle;
+use crate::core::runtime::{block_on, block_on_timed};
+use crate::error::{MongoError, MongoResult};
+use mongodb::bson::Document;
+use mongodb::options::FindOptions;
+use mongodb::Collection;
+
+/// Batch size used by `Iterate()` when the Lua side did not ask for one
+pub const DEFAULT_CURSOR_BATCH: usize = 100;
+
+pub fn open_cursor(collection: Collection<Document>, filter: Document, options: FindOptions) -> MongoResult<CursorHandle> {
+    block_on_timed(async move {
+        collection
+            .find(filter)
+            .with_options(options)
+            .await
+            .map(CursorHandle::new)
+            .map_err(|e| MongoError::driver("Find cursor failed", e))
+    })
+}
+
+pub fn cursor_next_batch(cursor: &CursorHandle, count: usize) -> MongoResult<Vec<Document>> {
+    let cursor = cursor.clone();
+    block_on_timed(async move {
+        cursor
+            .next_batch(count)
+            .await
+            .map_err(|e| MongoError::driver("Cursor read failed", e))
+    })
+}
+
+pub fn cursor_has_next(cursor: &CursorHandle) -> MongoResult<bool> {
+    let cursor = cursor.clone();
+    block_on_timed(async move {
+        cursor
+            .has_next()
+            .await
+            .map_err(|e| MongoError::driver("Cursor read failed", e))
+    })
+}
+
+pub fn cursor_close(cursor: &CursorHandle) {
+    let cursor = cursor.clone();
+    block_on(async move { cursor.close().await })
+}
+
@@ -0,0 +1,132 @@
+use crate::core::runtime::block_on_timed;
+use crate::error::{MongoError, MongoResult};
+use futures::io::{AsyncReadExt, AsyncWriteExt};
+use mongodb::bson::{Bson, Document};
+use mongodb::gridfs::GridFsBucket;
+use mongodb::options::{GridFsBucketOptions, GridFsUploadOptions};
+use mongodb::Database;
+
+/// A stored file, addressed either by its `_id` or by its filename
+#[derive(Debug, Clone)]
+pub enum GridFsTarget {
+    Id(Bson),
+    Name(String),
+}
+
+impl From<Bson> for GridFsTarget {
+    fn from(value: Bson) -> Self {
+        match value {
+            Bson::String(name) => GridFsTarget::Name(name),
+            id => GridFsTarget::Id(id),
+        }
+    }
+}
+
+pub fn gridfs_bucket(database: &Database, name: Option<String>, chunk_size_bytes: Option<u32>) -> GridFsBucket {
+    let options = GridFsBucketOptions::builder()
+        .bucket_name(name)
+        .chunk_size_bytes(chunk_size_bytes)
+        .build();
+
+    database.gridfs_bucket(options)
+}
+
+pub fn upload(bucket: &GridFsBucket, filename: String, data: Vec<u8>, metadata: Option<Document>) -> MongoResult<Bson> {
+    let bucket = bucket.clone();
+    block_on_timed(async move {
+        upload_file(&bucket, &filename, &data, metadata)
+            .await
+            .map_err(|e| MongoError::driver("GridFS upload failed", e))
+    })
+}
+
+pub fn download(bucket: &GridFsBucket, target: GridFsTarget) -> MongoResult<Vec<u8>> {
+    let bucket = bucket.clone();
+    block_on_timed(async move {
+        download_file(&bucket, target)
+            .await
+            .map_err(|e| MongoError::driver("GridFS download failed", e))
+    })
+}
+
+pub fn delete(bucket: &GridFsBucket, target: GridFsTarget) -> MongoResult<()> {
+    let bucket = bucket.clone();
+    block_on_timed(async move {
+        delete_file(&bucket, target)
+            .await
+            .map_err(|e| MongoError::driver("GridFS delete failed", e))
+    })
+}
+
+pub fn find_files(bucket: &GridFsBucket, filter: Document) -> MongoResult<Vec<Document>> {
+    let bucket = bucket.clone();
+    block_on_timed(async move {
+        list_files(&bucket, filter)
+            .await
+            .map_err(|e| MongoError::driver("GridFS find failed", e))
+    })
+}
+
+pub(crate) async fn upload_file(bucket: &GridFsBucket, filename: &str, data: &[u8], metadata: Option<Document>) -> mongodb::error::Result<Bson> {
+    let options = GridFsUploadOptions::builder()
+        .metadata(metadata)
+        .build();
+
+    let mut stream = bucket
+        .open_upload_stream(filename)
+        .with_options(options)
+        .await?;
+
+    if let Err(e) = stream.write_all(data).await {
+        stream.abort().await.ok();
+        return Err(e.into());
+    }
+    stream.close().await?;
+
+    Ok(stream.id().clone())
+}
+
+pub(crate) async fn download_file(bucket: &GridFsBucket, target: GridFsTarget) -> mongodb::error::Result<Vec<u8>> {
+    let mut stream = match target {
+        GridFsTarget::Id(id) => bucket.open_download_stream(id).await?,
+        GridFsTarget::Name(name) => bucket.open_download_stream_by_name(name).await?,
+    };
+
+    let mut data = Vec::new();
+    stream.read_to_end(&mut data).await?;
+    Ok(data)
+}
+
+pub(crate) async fn delete_file(bucket: &GridFsBucket, target: GridFsTarget) -> mongodb::error::Result<()> {
+    match target {
+        GridFsTarget::Id(id) => bucket.delete(id).await,
+        GridFsTarget::Name(name) => bucket.delete_by_name(name).await,
+    }
+}
+
+pub(crate) async fn list_files(bucket: &GridFsBucket, filter: Document) -> mongodb::error::Result<Vec<Document>> {
+    use futures::TryStreamExt;
+
+    let mut cursor = bucket.find(filter).await?;
+    let mut files = Vec::new();
+
+    while let Some(file) = cursor.try_next().await? {
+        if let Ok(doc) = mongodb::bson::to_document(&file) {
+            files.push(doc);
+        }
+    }
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::oid::ObjectId;
+
+    #[test]
+    fn test_target_from_bson() {
+        assert!(matches!(GridFsTarget::from(Bson::String("map.png".to_string())), GridFsTarget::Name(_)));
+        assert!(matches!(GridFsTarget::from(Bson::ObjectId(ObjectId::new())), GridFsTarget::Id(_)));
+    }
+}
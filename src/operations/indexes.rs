@@ -1,23 +1,27 @@
-use crate::core::runtime::block_on;
+use crate::core::runtime::block_on_timed;
 use crate::error::{MongoError, MongoResult};
-use mongodb::{bson::Document, Collection, IndexModel};
+use crate::operations::options::{get_bool, get_document};
+use mongodb::{bson::{Bson, Document}, Collection, IndexModel};
+
+/// Builds the index model shared by the single and batch index commands
+pub(crate) fn index_model(keys: Document, unique: bool, name: Option<String>) -> IndexModel {
+    let mut options = mongodb::options::IndexOptions::default();
+    options.unique = Some(unique);
+    options.name = name;
+
+    IndexModel::builder()
+        .keys(keys)
+        .options(options)
+        .build()
+}
 
 pub fn create_index(collection: &Collection<Document>, keys: Document, unique: bool, name: Option<String>) -> MongoResult<String> {
     let collection = collection.clone();
-    block_on(async move {
-        let mut options = mongodb::options::IndexOptions::default();
-        options.unique = Some(unique);
-        options.name = name;
-
-        let index = IndexModel::builder()
-            .keys(keys)
-            .options(options)
-            .build();
-
+    block_on_timed(async move {
         let result = collection
-            .create_index(index)
+            .create_index(index_model(keys, unique, name))
             .await
-            .map_err(|e| MongoError::IndexError(format!("Create index failed: {}", e)))?;
+            .map_err(|e| MongoError::classify("Create index failed", e, MongoError::IndexError))?;
 
         Ok(result.index_name)
     })
@@ -25,85 +29,96 @@ pub fn create_index(collection: &Collection<Document>, keys: Document, unique: b
 
 pub fn create_indexes(collection: &Collection<Document>, indexes: Vec<(Document, bool, Option<String>)>) -> MongoResult<Vec<String>> {
     let collection = collection.clone();
-    block_on(async move {
-        let index_models: Vec<IndexModel> = indexes.into_iter()
-            .map(|(keys, unique, name)| {
-                let mut options = mongodb::options::IndexOptions::default();
-                options.unique = Some(unique);
-                options.name = name;
-
-                IndexModel::builder()
-                    .keys(keys)
-                    .options(options)
-                    .build()
-            })
-            .collect();
-
-        let result = collection
-            .create_indexes(index_models)
+    block_on_timed(async move {
+        create_index_models(&collection, indexes)
             .await
-            .map_err(|e| MongoError::IndexError(format!("Create indexes failed: {}", e)))?;
-
-        Ok(result.index_names)
+            .map_err(|e| MongoError::classify("Create indexes failed", e, MongoError::IndexError))
     })
 }
 
+/// Reads one `{ keys = ..., unique = ..., name = ... }` entry of `CreateIndexes`
+pub fn index_spec(doc: &Document) -> Result<(Document, bool, Option<String>), String> {
+    let keys = get_document(doc, "keys")?.ok_or_else(|| "Missing 'keys'".to_string())?;
+    let unique = get_bool(doc, "unique")?.unwrap_or(false);
+    let name = match doc.get("name") {
+        None | Some(Bson::Null) => None,
+        Some(Bson::String(name)) => Some(name.clone()),
+        Some(other) => return Err(format!("'name' must be a string, got {:?}", other.element_type())),
+    };
+    Ok((keys, unique, name))
+}
+
+pub(crate) async fn create_index_models(collection: &Collection<Document>, indexes: Vec<(Document, bool, Option<String>)>) -> mongodb::error::Result<Vec<String>> {
+    let index_models: Vec<IndexModel> = indexes.into_iter()
+        .map(|(keys, unique, name)| index_model(keys, unique, name))
+        .collect();
+
+    let result = collection.create_indexes(index_models).await?;
+    Ok(result.index_names)
+}
+
 pub fn list_indexes(collection: &Collection<Document>) -> MongoResult<Vec<Document>> {
     let collection = collection.clone();
-    block_on(async move {
-        let mut cursor = collection
-            .list_indexes()
+    block_on_timed(async move {
+        list_index_documents(&collection)
             .await
-            .map_err(|e| MongoError::IndexError(format!("List indexes failed: {}", e)))?;
+            .map_err(|e| MongoError::classify("List indexes failed", e, MongoError::IndexError))
+    })
+}
 
-        let mut indexes = Vec::new();
+pub(crate) async fn list_index_documents(collection: &Collection<Document>) -> mongodb::error::Result<Vec<Document>> {
+    let mut cursor = collection.list_indexes().await?;
+    let mut indexes = Vec::new();
 
-        use futures::TryStreamExt;
-        while let Some(index) = cursor.try_next().await
-            .map_err(|e| MongoError::IndexError(format!("Cursor error: {}", e)))? {
-            if let Ok(doc) = mongodb::bson::to_document(&index) {
-                indexes.push(doc);
-            }
+    use futures::TryStreamExt;
+    while let Some(index) = cursor.try_next().await? {
+        if let Ok(doc) = mongodb::bson::to_document(&index) {
+            indexes.push(doc);
         }
+    }
 
-        Ok(indexes)
-    })
+    Ok(indexes)
 }
 
 pub fn drop_index(collection: &Collection<Document>, index_name: &str) -> MongoResult<()> {
     let collection = collection.clone();
     let index_name = index_name.to_string();
-    block_on(async move {
+    block_on_timed(async move {
         collection
             .drop_index(index_name)
             .await
-            .map_err(|e| MongoError::IndexError(format!("Drop index failed: {}", e)))?;
+            .map_err(|e| MongoError::classify("Drop index failed", e, MongoError::IndexError))?;
         Ok(())
     })
 }
 
 pub fn drop_all_indexes(collection: &Collection<Document>) -> MongoResult<()> {
     let collection = collection.clone();
-    block_on(async move {
+    block_on_timed(async move {
         collection
             .drop_indexes()
             .await
-            .map_err(|e| MongoError::IndexError(format!("Drop all indexes failed: {}", e)))?;
+            .map_err(|e| MongoError::classify("Drop all indexes failed", e, MongoError::IndexError))?;
         Ok(())
     })
 }
 
 pub fn create_text_index(collection: &Collection<Document>, fields: Vec<String>, name: Option<String>) -> MongoResult<String> {
+    create_index(collection, text_index_keys(fields), false, name)
+}
+
+/// Index keys covering `fields` with a single text index
+pub(crate) fn text_index_keys(fields: Vec<String>) -> Document {
     let mut keys_doc = Document::new();
     for field in fields {
         keys_doc.insert(field, "text");
     }
-
-    create_index(collection, keys_doc, false, name)
+    keys_doc
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
     use mongodb::bson::doc;
 
     #[test]
@@ -111,4 +126,21 @@ mod tests {
         let keys = doc! { "username": 1 };
         assert!(keys.contains_key("username"));
     }
+
+    #[test]
+    fn test_text_index_keys() {
+        let keys = text_index_keys(vec!["title".to_string(), "body".to_string()]);
+        assert_eq!(keys, doc! { "title": "text", "body": "text" });
+    }
+
+    #[test]
+    fn test_index_spec() {
+        let (keys, unique, name) = index_spec(&doc! { "keys": { "steamid": 1 }, "unique": true }).unwrap();
+        assert_eq!(keys, doc! { "steamid": 1 });
+        assert!(unique);
+        assert_eq!(name, None);
+
+        assert!(index_spec(&doc! { "unique": true }).is_err());
+        assert!(index_spec(&doc! { "keys": { "a": 1 }, "name": 5 }).is_err());
+    }
 }
@@ -1,15 +1,15 @@
-use crate::core::runtime::block_on;
+use crate::core::runtime::block_on_timed;
 use crate::error::{MongoError, MongoResult};
 use mongodb::{bson::Document, Database};
 
 pub fn create_collection(database: &Database, name: &str) -> MongoResult<()> {
     let database = database.clone();
     let name = name.to_string();
-    block_on(async move {
+    block_on_timed(async move {
         database
             .create_collection(name)
             .await
-            .map_err(|e| MongoError::Operation(format!("Create collection failed: {}", e)))?;
+            .map_err(|e| MongoError::driver("Create collection failed", e))?;
         Ok(())
     })
 }
@@ -17,60 +17,78 @@ pub fn create_collection(database: &Database, name: &str) -> MongoResult<()> {
 pub fn drop_collection(database: &Database, name: &str) -> MongoResult<()> {
     let database = database.clone();
     let name = name.to_string();
-    block_on(async move {
+    block_on_timed(async move {
         let collection: mongodb::Collection<Document> = database.collection(&name);
         collection
             .drop()
             .await
-            .map_err(|e| MongoError::Operation(format!("Drop collection failed: {}", e)))?;
+            .map_err(|e| MongoError::driver("Drop collection failed", e))?;
         Ok(())
     })
 }
 
 pub fn list_collections(database: &Database) -> MongoResult<Vec<String>> {
     let database = database.clone();
-    block_on(async move {
+    block_on_timed(async move {
         database
             .list_collection_names()
             .await
-            .map_err(|e| MongoError::Operation(format!("List collections failed: {}", e)))
+            .map_err(|e| MongoError::driver("List collections failed", e))
     })
 }
 
 pub fn collection_exists(database: &Database, name: &str) -> MongoResult<bool> {
-    let collections = list_collections(database)?;
-    Ok(collections.contains(&name.to_string()))
+    let database = database.clone();
+    let name = name.to_string();
+    block_on_timed(async move {
+        has_collection(&database, &name)
+            .await
+            .map_err(|e| MongoError::driver("List collections failed", e))
+    })
+}
+
+pub(crate) async fn has_collection(database: &Database, name: &str) -> mongodb::error::Result<bool> {
+    let names = database
+        .list_collection_names()
+        .filter(mongodb::bson::doc! { "name": name })
+        .await?;
+    Ok(!names.is_empty())
 }
 
 pub fn rename_collection(database: &Database, old_name: &str, new_name: &str, drop_target: bool) -> MongoResult<()> {
     let database = database.clone();
-    let old_name = old_name.to_string();
-    let new_name = new_name.to_string();
-    block_on(async move {
-        let namespace = mongodb::Namespace::new(database.name(), &new_name);
+    let command = rename_command(database.name(), old_name, new_name, drop_target);
+    block_on_timed(async move {
+        run_admin_command(&database, command)
+            .await
+            .map_err(|e| MongoError::driver("Rename collection failed", e))
+    })
+}
 
-        let mut cmd = mongodb::bson::doc! {
-            "renameCollection": format!("{}.{}", database.name(), old_name),
-            "to": format!("{}.{}", namespace.db, namespace.coll),
-        };
+/// `renameCollection` for a collection staying in the same database
+pub(crate) fn rename_command(database_name: &str, old_name: &str, new_name: &str, drop_target: bool) -> Document {
+    let mut command = mongodb::bson::doc! {
+        "renameCollection": format!("{}.{}", database_name, old_name),
+        "to": format!("{}.{}", database_name, new_name),
+    };
 
-        if drop_target {
-            cmd.insert("dropTarget", true);
-        }
+    if drop_target {
+        command.insert("dropTarget", true);
+    }
 
-        database
-            .run_command(cmd)
-            .await
-            .map_err(|e| MongoError::Operation(format!("Rename collection failed: {}", e)))?;
+    command
+}
 
-        Ok(())
-    })
+/// Runs `command` against `admin`, where commands like `renameCollection` must be sent
+pub(crate) async fn run_admin_command(database: &Database, command: Document) -> mongodb::error::Result<()> {
+    database.client().database("admin").run_command(command).await?;
+    Ok(())
 }
 
 pub fn collection_stats(database: &Database, collection_name: &str) -> MongoResult<Document> {
     let database = database.clone();
     let collection_name = collection_name.to_string();
-    block_on(async move {
+    block_on_timed(async move {
         let cmd = mongodb::bson::doc! {
             "collStats": collection_name,
         };
@@ -78,17 +96,17 @@ pub fn collection_stats(database: &Database, collection_name: &str) -> MongoResu
         database
             .run_command(cmd)
             .await
-            .map_err(|e| MongoError::Operation(format!("Get collection stats failed: {}", e)))
+            .map_err(|e| MongoError::driver("Get collection stats failed", e))
     })
 }
 
 pub fn drop_database(database: &Database) -> MongoResult<()> {
     let database = database.clone();
-    block_on(async move {
+    block_on_timed(async move {
         database
             .drop()
             .await
-            .map_err(|e| MongoError::Operation(format!("Drop database failed: {}", e)))?;
+            .map_err(|e| MongoError::driver("Drop database failed", e))?;
         Ok(())
     })
 }
@@ -101,4 +119,12 @@ mod tests {
         assert!(!name.is_empty());
         assert!(!name.contains('$'));
     }
+
+    #[test]
+    fn test_rename_command() {
+        let command = super::rename_command("game", "players", "players_old", true);
+        assert_eq!(command.get_str("renameCollection").unwrap(), "game.players");
+        assert_eq!(command.get_str("to").unwrap(), "game.players_old");
+        assert!(command.get_bool("dropTarget").unwrap());
+    }
 }
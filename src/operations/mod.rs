@@ -1,9 +1,25 @@
 pub mod crud;
+pub mod bulk;
 pub mod aggregation;
 pub mod indexes;
 pub mod management;
+pub mod cursors;
+pub mod options;
+#[cfg(feature = "transactions")]
+pub mod transactions;
+#[cfg(feature = "gridfs")]
+pub mod gridfs;
+#[cfg(feature = "change-streams")]
+pub mod change_streams;
 
 pub use crud::*;
+pub use bulk::*;
 pub use aggregation::*;
 pub use indexes::*;
 pub use management::*;
+pub use cursors::*;
+pub use options::{
+    find_one_and_delete_options, find_one_and_replace_options, find_one_and_update_options, find_one_options, find_options,
+};
+#[cfg(feature = "transactions")]
+pub use transactions::*;
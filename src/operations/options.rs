@@ -0,0 +1,220 @@
+//! Driver options built from Lua option tables
+//!
+//! Lua tables arrive here already converted to BSON documents. Unknown keys
+//! are ignored, known keys with the wrong type are reported.
+
+use mongodb::bson::{Bson, Document};
+use mongodb::options::{
+    Collation, FindOneAndDeleteOptions, FindOneAndReplaceOptions, FindOneAndUpdateOptions, FindOneOptions, FindOptions, Hint,
+    ReturnDocument,
+};
+use std::time::Duration;
+
+/// Reads a whole number; Lua hands every number over as Int64 or Double
+pub(crate) fn get_integer(doc: &Document, key: &str) -> Result<Option<i64>, String> {
+    match doc.get(key) {
+        None | Some(Bson::Null) => Ok(None),
+        Some(Bson::Int32(n)) => Ok(Some(*n as i64)),
+        Some(Bson::Int64(n)) => Ok(Some(*n)),
+        Some(Bson::Double(n)) if n.fract() == 0.0 => Ok(Some(*n as i64)),
+        Some(other) => Err(format!("Option '{}' must be an integer, got {:?}", key, other.element_type())),
+    }
+}
+
+pub(crate) fn get_document(doc: &Document, key: &str) -> Result<Option<Document>, String> {
+    match doc.get(key) {
+        None | Some(Bson::Null) => Ok(None),
+        Some(Bson::Document(d)) => Ok(Some(d.clone())),
+        Some(other) => Err(format!("Option '{}' must be a table, got {:?}", key, other.element_type())),
+    }
+}
+
+pub(crate) fn get_bool(doc: &Document, key: &str) -> Result<Option<bool>, String> {
+    match doc.get(key) {
+        None | Some(Bson::Null) => Ok(None),
+        Some(Bson::Boolean(b)) => Ok(Some(*b)),
+        Some(other) => Err(format!("Option '{}' must be a boolean, got {:?}", key, other.element_type())),
+    }
+}
+
+pub(crate) fn get_duration_ms(doc: &Document, key: &str) -> Result<Option<Duration>, String> {
+    Ok(get_integer(doc, key)?.map(|ms| Duration::from_millis(ms.max(0) as u64)))
+}
+
+/// Reads a sort specification.
+///
+/// Lua tables do not keep key order, so besides `{ score = -1 }` an array of
+/// single-key tables (`{ { score = -1 }, { name = 1 } }`) is accepted to sort
+/// by several fields in a fixed order.
+pub(crate) fn get_sort(doc: &Document, key: &str) -> Result<Option<Document>, String> {
+    match doc.get(key) {
+        None | Some(Bson::Null) => Ok(None),
+        Some(Bson::Document(d)) => Ok(Some(d.clone())),
+        Some(Bson::Array(fields)) => {
+            let mut sort = Document::new();
+            for field in fields {
+                match field {
+                    Bson::Document(d) => sort.extend(d.clone()),
+                    other => return Err(format!("Option '{}' must contain tables, got {:?}", key, other.element_type())),
+                }
+            }
+            Ok(Some(sort))
+        }
+        Some(other) => Err(format!("Option '{}' must be a table, got {:?}", key, other.element_type())),
+    }
+}
+
+pub(crate) fn get_hint(doc: &Document, key: &str) -> Result<Option<Hint>, String> {
+    match doc.get(key) {
+        None | Some(Bson::Null) => Ok(None),
+        Some(Bson::String(name)) => Ok(Some(Hint::Name(name.clone()))),
+        Some(Bson::Document(keys)) => Ok(Some(Hint::Keys(keys.clone()))),
+        Some(other) => Err(format!("Option '{}' must be an index name or table, got {:?}", key, other.element_type())),
+    }
+}
+
+pub(crate) fn get_collation(doc: &Document, key: &str) -> Result<Option<Collation>, String> {
+    match get_document(doc, key)? {
+        Some(collation) => mongodb::bson::from_document(collation)
+            .map(Some)
+            .map_err(|e| format!("Invalid collation: {}", e)),
+        None => Ok(None),
+    }
+}
+
+pub(crate) fn get_document_array(doc: &Document, key: &str) -> Result<Option<Vec<Document>>, String> {
+    match doc.get(key) {
+        None | Some(Bson::Null) => Ok(None),
+        Some(Bson::Array(items)) => items
+            .iter()
+            .map(|item| match item {
+                Bson::Document(d) => Ok(d.clone()),
+                other => Err(format!("Option '{}' must contain tables, got {:?}", key, other.element_type())),
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Some),
+        Some(other) => Err(format!("Option '{}' must be an array of tables, got {:?}", key, other.element_type())),
+    }
+}
+
+/// Reads `"before"` or `"after"`
+pub(crate) fn get_return_document(doc: &Document, key: &str) -> Result<Option<ReturnDocument>, String> {
+    match doc.get(key) {
+        None | Some(Bson::Null) => Ok(None),
+        Some(Bson::String(s)) if s == "before" => Ok(Some(ReturnDocument::Before)),
+        Some(Bson::String(s)) if s == "after" => Ok(Some(ReturnDocument::After)),
+        Some(other) => Err(format!("Option '{}' must be \"before\" or \"after\", got {}", key, other)),
+    }
+}
+
+/// Options accepted by `Find`, `FindAsync` and `FindCursor`
+pub fn find_options(doc: &Document) -> Result<FindOptions, String> {
+    Ok(FindOptions::builder()
+        .sort(get_sort(doc, "sort")?)
+        .projection(get_document(doc, "projection")?)
+        .skip(get_integer(doc, "skip")?.map(|n| n.max(0) as u64))
+        .limit(get_integer(doc, "limit")?)
+        .hint(get_hint(doc, "hint")?)
+        .collation(get_collation(doc, "collation")?)
+        .max_time(get_duration_ms(doc, "max_time_ms")?)
+        .batch_size(get_integer(doc, "batch_size")?.map(|n| n.max(0) as u32))
+        .allow_disk_use(get_bool(doc, "allow_disk_use")?)
+        .build())
+}
+
+/// Options accepted by `FindOne` and `FindOneAsync`
+pub fn find_one_options(doc: &Document) -> Result<FindOneOptions, String> {
+    Ok(FindOneOptions::builder()
+        .sort(get_sort(doc, "sort")?)
+        .projection(get_document(doc, "projection")?)
+        .skip(get_integer(doc, "skip")?.map(|n| n.max(0) as u64))
+        .hint(get_hint(doc, "hint")?)
+        .collation(get_collation(doc, "collation")?)
+        .max_time(get_duration_ms(doc, "max_time_ms")?)
+        .build())
+}
+
+/// Options accepted by `FindOneAndUpdate` and `FindOneAndUpdateAsync`
+pub fn find_one_and_update_options(doc: &Document) -> Result<FindOneAndUpdateOptions, String> {
+    Ok(FindOneAndUpdateOptions::builder()
+        .return_document(get_return_document(doc, "return_document")?)
+        .upsert(get_bool(doc, "upsert")?)
+        .sort(get_sort(doc, "sort")?)
+        .projection(get_document(doc, "projection")?)
+        .array_filters(get_document_array(doc, "array_filters")?)
+        .hint(get_hint(doc, "hint")?)
+        .collation(get_collation(doc, "collation")?)
+        .max_time(get_duration_ms(doc, "max_time_ms")?)
+        .build())
+}
+
+/// Options accepted by `FindOneAndReplace` and `FindOneAndReplaceAsync`
+pub fn find_one_and_replace_options(doc: &Document) -> Result<FindOneAndReplaceOptions, String> {
+    Ok(FindOneAndReplaceOptions::builder()
+        .return_document(get_return_document(doc, "return_document")?)
+        .upsert(get_bool(doc, "upsert")?)
+        .sort(get_sort(doc, "sort")?)
+        .projection(get_document(doc, "projection")?)
+        .hint(get_hint(doc, "hint")?)
+        .collation(get_collation(doc, "collation")?)
+        .max_time(get_duration_ms(doc, "max_time_ms")?)
+        .build())
+}
+
+/// Options accepted by `FindOneAndDelete` and `FindOneAndDeleteAsync`
+pub fn find_one_and_delete_options(doc: &Document) -> Result<FindOneAndDeleteOptions, String> {
+    Ok(FindOneAndDeleteOptions::builder()
+        .sort(get_sort(doc, "sort")?)
+        .projection(get_document(doc, "projection")?)
+        .hint(get_hint(doc, "hint")?)
+        .collation(get_collation(doc, "collation")?)
+        .max_time(get_duration_ms(doc, "max_time_ms")?)
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::doc;
+
+    #[test]
+    fn test_find_options() {
+        let options = find_options(&doc! {
+            "sort": [{ "score": -1_i64 }, { "name": 1_i64 }],
+            "projection": { "_id": 0_i64 },
+            "limit": 10_i64,
+            "skip": 5.0,
+            "hint": "score_1",
+        })
+        .unwrap();
+
+        let sort = options.sort.unwrap();
+        assert_eq!(sort.keys().collect::<Vec<_>>(), vec!["score", "name"]);
+        assert_eq!(options.limit, Some(10));
+        assert_eq!(options.skip, Some(5));
+        assert!(matches!(options.hint, Some(Hint::Name(_))));
+    }
+
+    #[test]
+    fn test_find_options_rejects_wrong_type() {
+        assert!(find_options(&doc! { "limit": "ten" }).is_err());
+        assert!(find_one_options(&doc! { "projection": 1_i64 }).is_err());
+    }
+
+    #[test]
+    fn test_find_one_and_update_options() {
+        let options = find_one_and_update_options(&doc! {
+            "return_document": "after",
+            "upsert": true,
+            "array_filters": [{ "item.id": 3_i64 }],
+        })
+        .unwrap();
+
+        assert!(matches!(options.return_document, Some(ReturnDocument::After)));
+        assert_eq!(options.upsert, Some(true));
+        assert_eq!(options.array_filters.unwrap().len(), 1);
+
+        assert!(find_one_and_update_options(&doc! { "return_document": "later" }).is_err());
+        assert!(find_one_and_replace_options(&doc! { "array_filters": 1_i64 }).is_ok());
+    }
+}
@@ -0,0 +1,80 @@
+use crate::core::runtime::block_on;
+use crate::core::session::SessionHandle;
+use crate::error::{MongoError, MongoResult};
+use mongodb::error::UNKNOWN_TRANSACTION_COMMIT_RESULT;
+use mongodb::options::TransactionOptions;
+use mongodb::Client;
+
+/// How many times a commit is retried while its outcome is unknown
+const MAX_COMMIT_RETRIES: usize = 3;
+
+pub fn start_session(client: &Client) -> MongoResult<SessionHandle> {
+    let client = client.clone();
+    block_on(async move {
+        let session = client
+            .start_session()
+            .await
+            .map_err(|e| MongoError::classify("Start session failed", e, MongoError::TransactionError))?;
+
+        Ok(SessionHandle::new(session))
+    })
+}
+
+pub fn start_transaction(session: &SessionHandle, options: Option<TransactionOptions>) -> MongoResult<()> {
+    let session = session.clone();
+    block_on(async move {
+        let mut guard = session.lock().await;
+        guard
+            .start_transaction()
+            .with_options(options)
+            .await
+            .map_err(|e| MongoError::classify("Start transaction failed", e, MongoError::TransactionError))
+    })
+}
+
+pub fn commit_transaction(session: &SessionHandle) -> MongoResult<()> {
+    let session = session.clone();
+    block_on(async move {
+        let mut guard = session.lock().await;
+        let mut attempt = 0;
+
+        loop {
+            let result = guard.commit_transaction().await;
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e) if e.contains_label(UNKNOWN_TRANSACTION_COMMIT_RESULT) && attempt < MAX_COMMIT_RETRIES => {
+                    attempt += 1;
+                }
+                Err(e) => {
+                    session.observe::<()>(&Err(e.clone()));
+                    return Err(MongoError::classify("Commit transaction failed", e, MongoError::TransactionError));
+                }
+            }
+        }
+    })
+}
+
+pub fn abort_transaction(session: &SessionHandle) -> MongoResult<()> {
+    let session = session.clone();
+    block_on(async move {
+        let mut guard = session.lock().await;
+        guard
+            .abort_transaction()
+            .await
+            .map_err(|e| MongoError::classify("Abort transaction failed", e, MongoError::TransactionError))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::options::TransactionOptions;
+    use std::time::Duration;
+
+    #[test]
+    fn test_transaction_options_creation() {
+        let options = TransactionOptions::builder()
+            .max_commit_time(Duration::from_secs(5))
+            .build();
+        assert_eq!(options.max_commit_time, Some(Duration::from_secs(5)));
+    }
+}
@@ -1,56 +1,139 @@
 use crate::error::{LuaError, LuaResult};
-use mongodb::bson::{oid::ObjectId, Bson, DateTime, Document};
+use crate::types::int64::{int64_mode, push_int64, to_int64, Int64Mode};
+use crate::types::object_id::{push_object_id, to_object_id};
+use crate::types::shape::{set_table_shape, table_shape, TableShape};
+use mongodb::bson::spec::BinarySubtype;
+use mongodb::bson::{Binary, Bson, DateTime, Decimal128, Document, JavaScriptCodeWithScope, Regex, Timestamp};
 use rglua::lua::LuaState;
 use rglua::prelude::*;
-use std::ffi::{CStr, CString};
+use std::ffi::{c_void, CStr, CString};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 
 // Lua type constants
 const LUA_TNIL: i32 = 0;
 const LUA_TBOOLEAN: i32 = 1;
 const LUA_TLIGHTUSERDATA: i32 = 2;
-const LUA_TNUMBER: i32 = 3;
-const LUA_TSTRING: i32 = 4;
+pub(super) const LUA_TNUMBER: i32 = 3;
+pub(super) const LUA_TSTRING: i32 = 4;
 const LUA_TTABLE: i32 = 5;
 const LUA_TFUNCTION: i32 = 6;
 const LUA_TUSERDATA: i32 = 7;
 const LUA_TTHREAD: i32 = 8;
 
-pub fn lua_table_to_bson(l: LuaState, index: i32) -> LuaResult<Document> {
-    unsafe {
-        if lua_type(l, index) != LUA_TTABLE {
-            return Err(LuaError::TypeConversion {
-                expected: "table".to_string(),
-                actual: lua_typename(l, lua_type(l, index))
-                    .map(|s| s.to_string())
-                    .unwrap_or_else(|| "unknown".to_string()),
-            });
-        }
+/// Deepest nesting MongoDB accepts in a document, and so the most
+/// `SetMaxDepth` allows
+pub const DEFAULT_MAX_DEPTH: usize = 100;
 
-        let mut doc = Document::new();
+static MAX_DEPTH: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_DEPTH);
 
-        let abs_index = if index < 0 {
-            lua_gettop(l) + index + 1
-        } else {
-            index
-        };
+pub fn max_depth() -> usize {
+    MAX_DEPTH.load(Ordering::Relaxed)
+}
 
-        lua_pushnil(l);
-        while lua_next(l, abs_index) != 0 {
-            let key = get_table_key(l, -2)?;
+/// Values outside `1..=DEFAULT_MAX_DEPTH` are clamped; a deeper limit would
+/// let the recursive encoder overflow the native stack
+pub fn set_max_depth(depth: usize) {
+    MAX_DEPTH.store(depth.clamp(1, DEFAULT_MAX_DEPTH), Ordering::Relaxed);
+}
+
+/// State of one Lua to BSON conversion: the tables currently being encoded
+/// and the key path leading to the current value, used to reject cycles and
+/// excessive nesting before they exhaust the native stack
+struct Encoder {
+    path: Vec<String>,
+    open_tables: Vec<*const c_void>,
+    max_depth: usize,
+}
 
-            let value = lua_value_to_bson(l, -1)?;
+impl Encoder {
+    fn new() -> Self {
+        Self {
+            path: Vec::new(),
+            open_tables: Vec::new(),
+            max_depth: max_depth(),
+        }
+    }
 
-            doc.insert(key, value);
+    fn key_path(&self) -> String {
+        if self.path.is_empty() {
+            "<root>".to_string()
+        } else {
+            self.path.join(".")
+        }
+    }
 
-            lua_pop(l, 1);
+    /// Records that the table at `index` is being encoded
+    unsafe fn enter(&mut self, l: LuaState, index: i32) -> LuaResult<()> {
+        let table = lua_topointer(l, index) as *const c_void;
+        if self.open_tables.contains(&table) {
+            return Err(LuaError::TableConversion(format!(
+                "Table contains a reference to itself at '{}'",
+                self.key_path()
+            )));
         }
+        if self.open_tables.len() >= self.max_depth {
+            return Err(LuaError::TableConversion(format!(
+                "Table nesting exceeds {} levels at '{}'",
+                self.max_depth,
+                self.key_path()
+            )));
+        }
+        self.open_tables.push(table);
+        Ok(())
+    }
+
+    fn leave(&mut self) {
+        self.open_tables.pop();
+    }
+}
+
+pub fn lua_table_to_bson(l: LuaState, index: i32) -> LuaResult<Document> {
+    unsafe { encode_document(l, index, &mut Encoder::new()) }
+}
+
+pub unsafe fn lua_value_to_bson(l: LuaState, index: i32) -> LuaResult<Bson> {
+    encode_value(l, index, &mut Encoder::new())
+}
+
+unsafe fn encode_document(l: LuaState, index: i32, encoder: &mut Encoder) -> LuaResult<Document> {
+    if lua_type(l, index) != LUA_TTABLE {
+        return Err(LuaError::TypeConversion {
+            expected: "table".to_string(),
+            actual: lua_typename(l, lua_type(l, index))
+                .map(|s| s.to_string())
+                .unwrap_or_else(|| "unknown".to_string()),
+        });
+    }
+
+    let mut doc = Document::new();
+
+    let abs_index = if index < 0 {
+        lua_gettop(l) + index + 1
+    } else {
+        index
+    };
+
+    encoder.enter(l, abs_index)?;
+
+    lua_pushnil(l);
+    while lua_next(l, abs_index) != 0 {
+        let key = get_table_key(l, -2)?;
+
+        encoder.path.push(key);
+        let value = encode_value(l, -1, encoder)?;
+        let key = encoder.path.pop().unwrap_or_default();
+
+        doc.insert(key, value);
 
-        Ok(doc)
+        lua_pop(l, 1);
     }
+
+    encoder.leave();
+    Ok(doc)
 }
 
-unsafe fn lua_value_to_bson(l: LuaState, index: i32) -> LuaResult<Bson> {
+unsafe fn encode_value(l: LuaState, index: i32, encoder: &mut Encoder) -> LuaResult<Bson> {
     let value_type = lua_type(l, index);
 
     match value_type {
@@ -86,44 +169,32 @@ unsafe fn lua_value_to_bson(l: LuaState, index: i32) -> LuaResult<Bson> {
                 })?
                 .to_string();
 
-            if value.starts_with("ObjectId(") && value.ends_with(")") {
-                let oid_str = &value[9..value.len()-1];
-                if let Ok(oid) = ObjectId::parse_str(oid_str) {
-                    return Ok(Bson::ObjectId(oid));
-                }
-            }
-
             Ok(Bson::String(value))
         }
 
         LUA_TTABLE => {
-            lua_pushstring(l, cstr!("__bson_type"));
-            lua_gettable(l, index);
-
-            if lua_type(l, -1) == LUA_TSTRING {
-                let type_ptr = lua_tostring(l, -1);
-                if !type_ptr.is_null() {
-                    let bson_type = CStr::from_ptr(type_ptr).to_str().ok();
-                    lua_pop(l, 1);
-
-                    if bson_type == Some("date") {
-                        return parse_date_table(l, index);
-                    }
-                }
-            }
-            lua_pop(l, 1);
-
             let abs_index = if index < 0 {
                 lua_gettop(l) + index + 1
             } else {
                 index
             };
 
-            lua_table_to_bson_value(l, index)
+            match get_string_field(l, abs_index, cstr!("__bson_type")) {
+                Some(bson_type) => parse_tagged_table(l, abs_index, &bson_type, encoder),
+                None => encode_table(l, abs_index, encoder),
+            }
         }
 
+        LUA_TUSERDATA => to_object_id(l, index)
+            .map(Bson::ObjectId)
+            .or_else(|| to_int64(l, index).map(Bson::Int64))
+            .ok_or_else(|| LuaError::TypeConversion {
+                expected: "ObjectId or Int64".to_string(),
+                actual: "userdata".to_string(),
+            }),
+
         _ => Err(LuaError::TypeConversion {
-            expected: "nil, boolean, number, string, or table".to_string(),
+            expected: "nil, boolean, number, string, table, ObjectId or Int64".to_string(),
             actual: lua_typename(l, value_type)
                 .map(|s| s.to_string())
                 .unwrap_or_else(|| "unknown".to_string()),
@@ -131,45 +202,58 @@ unsafe fn lua_value_to_bson(l: LuaState, index: i32) -> LuaResult<Bson> {
     }
 }
 
-unsafe fn lua_table_to_bson_value(l: LuaState, index: i32) -> LuaResult<Bson> {
+unsafe fn encode_table(l: LuaState, index: i32, encoder: &mut Encoder) -> LuaResult<Bson> {
     let abs = if index < 0 {
         lua_gettop(l) + index + 1
     } else {
         index
     };
 
-    if lua_table_is_array(l, abs) {
-        let len = lua_objlen(l, abs);
-        let mut arr = Vec::with_capacity(len as usize);
+    match table_shape(l, abs) {
+        Some(TableShape::Array) => encode_array(l, abs, encoder),
+        Some(TableShape::Document) => Ok(Bson::Document(encode_document(l, abs, encoder)?)),
+        None if lua_table_is_array(l, abs) => encode_array(l, abs, encoder),
+        None => Ok(Bson::Document(encode_document(l, abs, encoder)?)),
+    }
+}
 
-        for i in 1..=len {
-            lua_rawgeti(l, abs, i as i32);
-            let val = lua_value_to_bson(l, -1)?;
-            arr.push(val);
-            lua_pop(l, 1);
-        }
+/// Encodes the table at the absolute `index` as an array, even when it is empty
+unsafe fn encode_array(l: LuaState, index: i32, encoder: &mut Encoder) -> LuaResult<Bson> {
+    if !lua_table_has_sequence_keys(l, index) {
+        return Err(LuaError::TableConversion(format!(
+            "Array tables may only have the keys 1 to n, at '{}'",
+            encoder.key_path()
+        )));
+    }
 
-        Ok(Bson::Array(arr))
-    } else {
-        let doc = lua_table_to_bson(l, abs)?;
-        Ok(Bson::Document(doc))
+    encoder.enter(l, index)?;
+
+    let len = lua_objlen(l, index);
+    let mut arr = Vec::with_capacity(len as usize);
+
+    for i in 1..=len {
+        lua_rawgeti(l, index, i as i32);
+        encoder.path.push(i.to_string());
+        let val = encode_value(l, -1, encoder)?;
+        encoder.path.pop();
+        arr.push(val);
+        lua_pop(l, 1);
     }
+
+    encoder.leave();
+    Ok(Bson::Array(arr))
 }
 
 unsafe fn lua_table_is_array(l: LuaState, index: i32) -> bool {
-    let abs = if index < 0 {
-        lua_gettop(l) + index + 1
-    } else {
-        index
-    };
+    lua_objlen(l, index) != 0 && lua_table_has_sequence_keys(l, index)
+}
 
-    let len = lua_objlen(l, abs);
-    if len == 0 {
-        return false;
-    }
+/// Whether every key of the table at the absolute `index` is an integer from 1 to its length
+unsafe fn lua_table_has_sequence_keys(l: LuaState, index: i32) -> bool {
+    let len = lua_objlen(l, index);
 
     lua_pushnil(l);
-    while lua_next(l, abs) != 0 {
+    while lua_next(l, index) != 0 {
         let key_type = lua_type(l, -2);
         if key_type != LUA_TNUMBER {
             lua_pop(l, 2);
@@ -217,24 +301,170 @@ unsafe fn get_table_key(l: LuaState, index: i32) -> LuaResult<String> {
     }
 }
 
-unsafe fn parse_date_table(l: LuaState, index: i32) -> LuaResult<Bson> {
-    lua_pushstring(l, cstr!("timestamp"));
-    lua_gettable(l, index);
+/// Reads `t[field]` as a string from the table at the absolute `index`
+unsafe fn get_string_field(l: LuaState, index: i32, field: LuaString) -> Option<String> {
+    lua_getfield(l, index, field);
+    let value = if lua_type(l, -1) == LUA_TSTRING {
+        let mut len = 0;
+        let ptr = lua_tolstring(l, -1, &mut len);
+        (!ptr.is_null()).then(|| {
+            String::from_utf8_lossy(std::slice::from_raw_parts(ptr as *const u8, len)).into_owned()
+        })
+    } else {
+        None
+    };
+    lua_pop(l, 1);
+    value
+}
 
-    if lua_type(l, -1) == LUA_TNUMBER {
-        let timestamp = lua_tonumber(l, -1) as i64;
-        lua_pop(l, 1);
+unsafe fn get_bytes_field(l: LuaState, index: i32, field: LuaString) -> Option<Vec<u8>> {
+    lua_getfield(l, index, field);
+    let value = if lua_type(l, -1) == LUA_TSTRING {
+        let mut len = 0;
+        let ptr = lua_tolstring(l, -1, &mut len);
+        (!ptr.is_null()).then(|| std::slice::from_raw_parts(ptr as *const u8, len).to_vec())
+    } else {
+        None
+    };
+    lua_pop(l, 1);
+    value
+}
 
-        let dt = DateTime::from_millis(timestamp);
-        return Ok(Bson::DateTime(dt));
-    }
+unsafe fn get_number_field(l: LuaState, index: i32, field: LuaString) -> Option<f64> {
+    lua_getfield(l, index, field);
+    let value = (lua_type(l, -1) == LUA_TNUMBER).then(|| lua_tonumber(l, -1));
+    lua_pop(l, 1);
+    value
+}
 
+unsafe fn get_document_field(l: LuaState, index: i32, field: LuaString, encoder: &mut Encoder) -> LuaResult<Option<Document>> {
+    lua_getfield(l, index, field);
+    let value = if lua_type(l, -1) == LUA_TTABLE {
+        encode_document(l, -1, encoder).map(Some)
+    } else {
+        Ok(None)
+    };
     lua_pop(l, 1);
-    Err(LuaError::TableConversion("Invalid date table format".to_string()))
+    value
+}
+
+fn invalid_tagged(bson_type: &str, expected: &str) -> LuaError {
+    LuaError::TableConversion(format!("Invalid {} table: expected {}", bson_type, expected))
+}
+
+/// `value` of the `int32` table an Int32 decodes to
+fn int32_to_number(value: i32) -> f64 {
+    value as f64
+}
+
+/// The Int32 a tagged `int32` table stands for, if its value fits
+fn int32_from_number(value: f64) -> Option<Bson> {
+    (value.fract() == 0.0 && value >= i32::MIN as f64 && value <= i32::MAX as f64).then(|| Bson::Int32(value as i32))
+}
+
+/// Converts a table carrying a `__bson_type` tag back into the BSON value it describes
+unsafe fn parse_tagged_table(l: LuaState, index: i32, bson_type: &str, encoder: &mut Encoder) -> LuaResult<Bson> {
+    match bson_type {
+        "date" => get_number_field(l, index, cstr!("timestamp"))
+            .map(|ms| Bson::DateTime(DateTime::from_millis(ms as i64)))
+            .ok_or_else(|| invalid_tagged(bson_type, "numeric 'timestamp'")),
+
+        "int32" => get_number_field(l, index, cstr!("value"))
+            .and_then(int32_from_number)
+            .ok_or_else(|| invalid_tagged(bson_type, "32-bit integer 'value'")),
+
+        "int64" => match get_string_field(l, index, cstr!("value")) {
+            Some(value) => value
+                .parse::<i64>()
+                .map(Bson::Int64)
+                .map_err(|_| invalid_tagged(bson_type, "integer 'value'")),
+            None => get_number_field(l, index, cstr!("value"))
+                .filter(|n| n.fract() == 0.0)
+                .map(|n| Bson::Int64(n as i64))
+                .ok_or_else(|| invalid_tagged(bson_type, "integer 'value'")),
+        },
+
+        "double" => get_number_field(l, index, cstr!("value"))
+            .map(Bson::Double)
+            .ok_or_else(|| invalid_tagged(bson_type, "numeric 'value'")),
+
+        "decimal128" => get_string_field(l, index, cstr!("value"))
+            .and_then(|value| value.parse::<Decimal128>().ok())
+            .map(Bson::Decimal128)
+            .ok_or_else(|| invalid_tagged(bson_type, "decimal string 'value'")),
+
+        "timestamp" => match (get_number_field(l, index, cstr!("t")), get_number_field(l, index, cstr!("i"))) {
+            (Some(time), Some(increment)) => Ok(Bson::Timestamp(Timestamp {
+                time: time as u32,
+                increment: increment as u32,
+            })),
+            _ => Err(invalid_tagged(bson_type, "numeric 't' and 'i'")),
+        },
+
+        "regex" => get_string_field(l, index, cstr!("pattern"))
+            .map(|pattern| {
+                // BSON requires the option flags in alphabetical order
+                let mut options: Vec<char> = get_string_field(l, index, cstr!("options")).unwrap_or_default().chars().collect();
+                options.sort_unstable();
+                Bson::RegularExpression(Regex { pattern, options: options.into_iter().collect() })
+            })
+            .ok_or_else(|| invalid_tagged(bson_type, "string 'pattern'")),
+
+        "javascript" => {
+            let code = get_string_field(l, index, cstr!("code"))
+                .ok_or_else(|| invalid_tagged(bson_type, "string 'code'"))?;
+            match get_document_field(l, index, cstr!("scope"), encoder)? {
+                Some(scope) => Ok(Bson::JavaScriptCodeWithScope(JavaScriptCodeWithScope { code, scope })),
+                None => Ok(Bson::JavaScriptCode(code)),
+            }
+        }
+
+        "symbol" => get_string_field(l, index, cstr!("value"))
+            .map(Bson::Symbol)
+            .ok_or_else(|| invalid_tagged(bson_type, "string 'value'")),
+
+        "array" | "document" => {
+            lua_getfield(l, index, cstr!("value"));
+            let value = match lua_type(l, -1) {
+                LUA_TTABLE if bson_type == "array" => encode_array(l, lua_gettop(l), encoder),
+                LUA_TTABLE => encode_document(l, -1, encoder).map(Bson::Document),
+                LUA_TNIL if bson_type == "array" => Ok(Bson::Array(Vec::new())),
+                LUA_TNIL => Ok(Bson::Document(Document::new())),
+                _ => Err(invalid_tagged(bson_type, "table 'value'")),
+            };
+            lua_pop(l, 1);
+            value
+        }
+
+        "minkey" => Ok(Bson::MinKey),
+        "maxkey" => Ok(Bson::MaxKey),
+
+        "dbpointer" => {
+            let (Some(namespace), Some(id)) = (get_string_field(l, index, cstr!("ref")), get_string_field(l, index, cstr!("id"))) else {
+                return Err(invalid_tagged(bson_type, "string 'ref' and 'id'"));
+            };
+            db_pointer_from_parts(&namespace, &id)
+                .map_err(|e| LuaError::TableConversion(format!("Invalid dbpointer table: {}", e)))
+        }
+
+        "binary" => {
+            let bytes = match get_bytes_field(l, index, cstr!("data")) {
+                Some(bytes) => bytes,
+                None => get_string_field(l, index, cstr!("hex"))
+                    .and_then(|hex| hex::decode(hex).ok())
+                    .ok_or_else(|| invalid_tagged(bson_type, "string 'data' or 'hex'"))?,
+            };
+            let subtype = get_number_field(l, index, cstr!("subtype")).unwrap_or(0.0) as u8;
+            Ok(Bson::Binary(Binary { subtype: BinarySubtype::from(subtype), bytes }))
+        }
+
+        other => Err(LuaError::TableConversion(format!("Unknown __bson_type '{}'", other))),
+    }
 }
 
 pub unsafe fn bson_to_lua_table(l: LuaState, doc: &Document) {
     lua_newtable(l);
+    set_table_shape(l, TableShape::Document);
 
     for (key, value) in doc.iter() {
         let key_cstr = CString::new(key.as_str()).unwrap();
@@ -247,13 +477,19 @@ pub unsafe fn bson_to_lua_table(l: LuaState, doc: &Document) {
 }
 
 /// Convert a BSON value to a Lua value
-unsafe fn bson_value_to_lua(l: LuaState, value: &Bson) {
+pub unsafe fn bson_value_to_lua(l: LuaState, value: &Bson) {
     match value {
         Bson::Null | Bson::Undefined => lua_pushnil(l),
 
         Bson::Boolean(b) => lua_pushboolean(l, *b as i32),
 
-        Bson::Int32(i) => lua_pushnumber(l, *i as f64),
+        Bson::Int32(i) => {
+            // A plain number would be written back as Int64
+            push_tagged(l, cstr!("int32"));
+            lua_pushnumber(l, int32_to_number(*i));
+            lua_setfield(l, -2, cstr!("value"));
+        }
+        Bson::Int64(i) if int64_mode() == Int64Mode::Precise => push_int64(l, *i),
         Bson::Int64(i) => lua_pushnumber(l, *i as f64),
         Bson::Double(d) => lua_pushnumber(l, *d),
 
@@ -268,17 +504,14 @@ unsafe fn bson_value_to_lua(l: LuaState, value: &Bson) {
 
         Bson::Array(arr) => {
             lua_newtable(l);
+            set_table_shape(l, TableShape::Array);
             for (i, item) in arr.iter().enumerate() {
                 bson_value_to_lua(l, item);
                 lua_rawseti(l, -2, (i + 1) as i32);
             }
         }
 
-        Bson::ObjectId(oid) => {
-            let oid_str = format!("ObjectId({})", oid.to_hex());
-            let cstr = CString::new(oid_str).unwrap();
-            lua_pushstring(l, cstr.as_ptr());
-        }
+        Bson::ObjectId(oid) => push_object_id(l, oid),
 
         Bson::DateTime(dt) => {
             lua_newtable(l);
@@ -293,17 +526,88 @@ unsafe fn bson_value_to_lua(l: LuaState, value: &Bson) {
         }
 
         Bson::Binary(bin) => {
-            let hex = hex::encode(&bin.bytes);
-            let cstr = CString::new(hex).unwrap_or_else(|_| CString::new("").unwrap());
-            lua_pushstring(l, cstr.as_ptr());
+            push_tagged(l, cstr!("binary"));
+            lua_pushnumber(l, u8::from(bin.subtype) as f64);
+            lua_setfield(l, -2, cstr!("subtype"));
+            lua_pushlstring(l, bin.bytes.as_ptr() as _, bin.bytes.len());
+            lua_setfield(l, -2, cstr!("data"));
         }
 
-        _ => {
-            lua_pushnil(l);
+        Bson::Decimal128(d) => {
+            push_tagged(l, cstr!("decimal128"));
+            push_string_field(l, cstr!("value"), &d.to_string());
+        }
+
+        Bson::Timestamp(ts) => {
+            push_tagged(l, cstr!("timestamp"));
+            lua_pushnumber(l, ts.time as f64);
+            lua_setfield(l, -2, cstr!("t"));
+            lua_pushnumber(l, ts.increment as f64);
+            lua_setfield(l, -2, cstr!("i"));
+        }
+
+        Bson::RegularExpression(regex) => {
+            push_tagged(l, cstr!("regex"));
+            push_string_field(l, cstr!("pattern"), &regex.pattern);
+            push_string_field(l, cstr!("options"), &regex.options);
+        }
+
+        Bson::JavaScriptCode(code) => {
+            push_tagged(l, cstr!("javascript"));
+            push_string_field(l, cstr!("code"), code);
+        }
+
+        Bson::JavaScriptCodeWithScope(js) => {
+            push_tagged(l, cstr!("javascript"));
+            push_string_field(l, cstr!("code"), &js.code);
+            bson_to_lua_table(l, &js.scope);
+            lua_setfield(l, -2, cstr!("scope"));
+        }
+
+        Bson::Symbol(symbol) => {
+            push_tagged(l, cstr!("symbol"));
+            push_string_field(l, cstr!("value"), symbol);
+        }
+
+        Bson::MinKey => push_tagged(l, cstr!("minkey")),
+        Bson::MaxKey => push_tagged(l, cstr!("maxkey")),
+
+        Bson::DbPointer(_) => {
+            let (namespace, id) = db_pointer_parts(value);
+            push_tagged(l, cstr!("dbpointer"));
+            push_string_field(l, cstr!("ref"), &namespace);
+            push_string_field(l, cstr!("id"), &id);
         }
     }
 }
 
+// The driver keeps DbPointer's fields private, extended JSON is the only way in and out
+fn db_pointer_parts(value: &Bson) -> (String, String) {
+    let extjson = value.clone().into_relaxed_extjson();
+    let pointer = &extjson["$dbPointer"];
+    (
+        pointer["$ref"].as_str().unwrap_or_default().to_string(),
+        pointer["$id"]["$oid"].as_str().unwrap_or_default().to_string(),
+    )
+}
+
+fn db_pointer_from_parts(namespace: &str, id: &str) -> Result<Bson, mongodb::bson::extjson::de::Error> {
+    Bson::try_from(serde_json::json!({ "$dbPointer": { "$ref": namespace, "$id": { "$oid": id } } }))
+}
+
+/// Pushes a new table tagged with `__bson_type`
+unsafe fn push_tagged(l: LuaState, bson_type: LuaString) {
+    lua_newtable(l);
+    lua_pushstring(l, bson_type);
+    lua_setfield(l, -2, cstr!("__bson_type"));
+}
+
+/// Sets `t[field] = value` on the table at the top of the stack
+unsafe fn push_string_field(l: LuaState, field: LuaString, value: &str) {
+    lua_pushlstring(l, value.as_ptr() as _, value.len());
+    lua_setfield(l, -2, field);
+}
+
 unsafe fn lua_typename(_l: LuaState, tp: i32) -> Option<&'static str> {
     match tp {
         LUA_TNIL => Some("nil"),
@@ -321,6 +625,7 @@ unsafe fn lua_typename(_l: LuaState, tp: i32) -> Option<&'static str> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use mongodb::bson::oid::ObjectId;
 
     #[test]
     fn test_bson_to_document() {
@@ -331,4 +636,39 @@ mod tests {
 
         assert_eq!(doc.len(), 3);
     }
+
+    #[test]
+    fn test_encoder_key_path() {
+        let mut encoder = Encoder::new();
+        assert_eq!(encoder.max_depth, DEFAULT_MAX_DEPTH);
+        assert_eq!(encoder.key_path(), "<root>");
+
+        encoder.path.extend(["owner".to_string(), "friends".to_string(), "3".to_string()]);
+        assert_eq!(encoder.key_path(), "owner.friends.3");
+    }
+
+    #[test]
+    fn test_max_depth_clamped() {
+        set_max_depth(1_000_000);
+        assert_eq!(max_depth(), DEFAULT_MAX_DEPTH);
+    }
+
+    #[test]
+    fn test_db_pointer_round_trip() {
+        let id = ObjectId::new().to_hex();
+        let pointer = db_pointer_from_parts("game.players", &id).unwrap();
+        assert!(matches!(pointer, Bson::DbPointer(_)));
+        assert_eq!(db_pointer_parts(&pointer), ("game.players".to_string(), id));
+    }
+
+    #[test]
+    fn test_int32_round_trip() {
+        for value in [0, 20, -7, i32::MIN, i32::MAX] {
+            // bson_value_to_lua decodes to { __bson_type = "int32", value = ... },
+            // parse_tagged_table reads that value back
+            assert_eq!(int32_from_number(int32_to_number(value)), Some(Bson::Int32(value)));
+        }
+        assert_eq!(int32_from_number(i32::MAX as f64 + 1.0), None);
+        assert_eq!(int32_from_number(1.5), None);
+    }
 }
@@ -0,0 +1,154 @@
+//! Exact 64-bit integers as Lua userdata
+//!
+//! Lua numbers are doubles and lose precision above 2^53, which corrupts
+//! SteamID64s and large balances. `MongoDBInt64` userdata keep the exact
+//! value and support arithmetic and comparisons through metamethods.
+
+use super::conversion::{LUA_TNUMBER, LUA_TSTRING};
+use crate::utils::{is_udata, write_userdata};
+use rglua::lua::LuaState;
+use rglua::prelude::*;
+use std::cell::Cell;
+
+/// How `Int64` values coming back from the server are handed to Lua
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Int64Mode {
+    /// Plain Lua numbers, which round beyond 2^53
+    #[default]
+    Number,
+    /// Every `Int64` becomes a `MongoDBInt64` userdata
+    Precise,
+}
+
+impl Int64Mode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "number" => Some(Self::Number),
+            "precise" => Some(Self::Precise),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Number => "number",
+            Self::Precise => "precise",
+        }
+    }
+}
+
+thread_local! {
+    static INT64_MODE: Cell<Int64Mode> = const { Cell::new(Int64Mode::Number) };
+}
+
+pub fn int64_mode() -> Int64Mode {
+    INT64_MODE.with(|mode| mode.get())
+}
+
+/// Switches the decode mode until dropped
+pub struct Int64ModeGuard {
+    previous: Int64Mode,
+}
+
+impl Int64ModeGuard {
+    pub fn new(mode: Int64Mode) -> Self {
+        let previous = INT64_MODE.with(|current| current.replace(mode));
+        Self { previous }
+    }
+}
+
+impl Drop for Int64ModeGuard {
+    fn drop(&mut self) {
+        INT64_MODE.with(|current| current.set(self.previous));
+    }
+}
+
+pub unsafe fn push_int64(l: LuaState, value: i64) {
+    write_userdata(l, value);
+    luaL_getmetatable(l, cstr!("MongoDBInt64"));
+    lua_setmetatable(l, -2);
+}
+
+/// Returns the Int64 userdata at `index`, or `None` if the value is anything else
+pub unsafe fn to_int64(l: LuaState, index: i32) -> Option<i64> {
+    if !is_udata(l, index, cstr!("MongoDBInt64")) {
+        return None;
+    }
+    let ptr = lua_touserdata(l, index) as *const i64;
+    (!ptr.is_null()).then(|| *ptr)
+}
+
+/// Reads an Int64 operand: userdata, a whole number or a decimal string
+pub unsafe fn check_int64_operand(l: LuaState, index: i32) -> Option<i64> {
+    if let Some(value) = to_int64(l, index) {
+        return Some(value);
+    }
+
+    match lua_type(l, index) {
+        LUA_TNUMBER => {
+            let n = lua_tonumber(l, index);
+            (n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64).then_some(n as i64)
+        }
+        LUA_TSTRING => {
+            let s = std::ffi::CStr::from_ptr(lua_tostring(l, index)).to_string_lossy();
+            parse_int64(&s)
+        }
+        _ => None,
+    }
+}
+
+pub fn parse_int64(value: &str) -> Option<i64> {
+    value.trim().parse().ok()
+}
+
+/// Integer division rounding toward negative infinity, like `math.floor(a / b)`
+pub fn floor_div(a: i64, b: i64) -> Option<i64> {
+    let q = a.checked_div(b)?;
+    if a % b != 0 && ((a < 0) != (b < 0)) {
+        Some(q - 1)
+    } else {
+        Some(q)
+    }
+}
+
+/// Remainder taking the sign of the divisor, matching Lua's `%`
+pub fn floor_mod(a: i64, b: i64) -> Option<i64> {
+    let r = a.checked_rem(b)?;
+    if r != 0 && ((r < 0) != (b < 0)) {
+        Some(r + b)
+    } else {
+        Some(r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_int64() {
+        assert_eq!(parse_int64("76561198000000001"), Some(76_561_198_000_000_001));
+        assert_eq!(parse_int64(" -9223372036854775808 "), Some(i64::MIN));
+        assert_eq!(parse_int64("1.5"), None);
+    }
+
+    #[test]
+    fn test_floor_division() {
+        assert_eq!(floor_div(7, 2), Some(3));
+        assert_eq!(floor_div(-7, 2), Some(-4));
+        assert_eq!(floor_mod(-7, 2), Some(1));
+        assert_eq!(floor_mod(7, -2), Some(-1));
+        assert_eq!(floor_div(1, 0), None);
+        assert_eq!(floor_div(i64::MIN, -1), None);
+    }
+
+    #[test]
+    fn test_mode_guard_restores() {
+        assert_eq!(int64_mode(), Int64Mode::Number);
+        {
+            let _guard = Int64ModeGuard::new(Int64Mode::Precise);
+            assert_eq!(int64_mode(), Int64Mode::Precise);
+        }
+        assert_eq!(int64_mode(), Int64Mode::Number);
+    }
+}
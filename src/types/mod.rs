@@ -1,3 +1,9 @@
 pub mod conversion;
+pub mod int64;
+pub mod object_id;
+pub mod shape;
 
-pub use conversion::{lua_table_to_bson, bson_to_lua_table};
+pub use conversion::{lua_table_to_bson, bson_to_lua_table, lua_value_to_bson, bson_value_to_lua};
+pub use int64::{check_int64_operand, push_int64, to_int64, Int64Mode, Int64ModeGuard};
+pub use object_id::{object_id_from_time, push_object_id, to_object_id};
+pub use shape::{set_table_shape, TableShape};
@@ -0,0 +1,43 @@
+//! ObjectIds as Lua userdata
+//!
+//! Stored by value in a full userdata carrying the `MongoDBObjectId`
+//! metatable, so the conversion layer can tell them apart from strings.
+
+use crate::utils::{is_udata, write_userdata};
+use mongodb::bson::oid::ObjectId;
+use rglua::lua::LuaState;
+use rglua::prelude::*;
+
+pub unsafe fn push_object_id(l: LuaState, oid: &ObjectId) {
+    write_userdata(l, *oid);
+    luaL_getmetatable(l, cstr!("MongoDBObjectId"));
+    lua_setmetatable(l, -2);
+}
+
+/// Returns the ObjectId at `index`, or `None` if the value is anything else
+pub unsafe fn to_object_id(l: LuaState, index: i32) -> Option<ObjectId> {
+    if !is_udata(l, index, cstr!("MongoDBObjectId")) {
+        return None;
+    }
+    let ptr = lua_touserdata(l, index) as *const ObjectId;
+    (!ptr.is_null()).then(|| *ptr)
+}
+
+/// Smallest ObjectId created at `seconds`, for range queries on creation time
+pub fn object_id_from_time(seconds: u32) -> ObjectId {
+    let mut bytes = [0u8; 12];
+    bytes[..4].copy_from_slice(&seconds.to_be_bytes());
+    ObjectId::from_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_object_id_from_time() {
+        let oid = object_id_from_time(1_700_000_000);
+        assert_eq!(oid.timestamp().timestamp_millis(), 1_700_000_000_000);
+        assert!(oid < ObjectId::new());
+    }
+}
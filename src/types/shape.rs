@@ -0,0 +1,46 @@
+//! Array and document markers for Lua tables
+//!
+//! An empty Lua table could be either BSON type, so tables can carry the
+//! shared `MongoDBArray` or `MongoDBDocument` metatable to say which one
+//! they are. Decoded arrays and documents get the same marker, so a
+//! document read, modified and saved again keeps its shape.
+
+use rglua::lua::LuaState;
+use rglua::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableShape {
+    Array,
+    Document,
+}
+
+impl TableShape {
+    fn metatable(&self) -> LuaString {
+        match self {
+            Self::Array => cstr!("MongoDBArray"),
+            Self::Document => cstr!("MongoDBDocument"),
+        }
+    }
+}
+
+/// Marks the table on top of the stack with `shape`
+pub unsafe fn set_table_shape(l: LuaState, shape: TableShape) {
+    luaL_getmetatable(l, shape.metatable());
+    lua_setmetatable(l, -2);
+}
+
+/// Returns the marker of the table at `index`, or `None` if it has none
+pub unsafe fn table_shape(l: LuaState, index: i32) -> Option<TableShape> {
+    if !lua_istable(l, index) || lua_getmetatable(l, index) == 0 {
+        return None;
+    }
+
+    let shape = [TableShape::Array, TableShape::Document].into_iter().find(|shape| {
+        luaL_getmetatable(l, shape.metatable());
+        let matches = lua_rawequal(l, -1, -2) != 0;
+        lua_pop(l, 1);
+        matches
+    });
+    lua_pop(l, 1);
+    shape
+}
@@ -1,7 +1,12 @@
 use std::ptr;
+use std::time::Duration;
 use rglua::lua::LuaState;
 use rglua::prelude::*;
-use crate::error::{LuaError, LuaResult};
+use crate::config::ClientSettings;
+use crate::core::queue::Limiter;
+use crate::core::session::SessionHandle;
+use crate::error::{ErrorCategory, ErrorDetails, LuaError, LuaResult};
+use crate::types::Int64Mode;
 
 pub unsafe fn write_userdata<T: Clone>(l: LuaState, data: T) {
     let size = std::mem::size_of::<T>();
@@ -25,6 +30,164 @@ pub unsafe fn is_userdata(l: LuaState, index: i32) -> bool {
     lua_type(l, index) == 7 // LUA_TUSERDATA
 }
 
+/// Returns whether the value at `index` is userdata carrying the named metatable
+pub unsafe fn is_udata(l: LuaState, index: i32, name: LuaString) -> bool {
+    if !is_userdata(l, index) || lua_getmetatable(l, index) == 0 {
+        return false;
+    }
+
+    luaL_getmetatable(l, name);
+    let matches = lua_rawequal(l, -1, -2) != 0;
+    lua_pop(l, 2);
+    matches
+}
+
+/// `__gc` metamethod releasing a value stored with [`write_userdata`]
+pub extern "C" fn gc_userdata<T>(l: LuaState) -> i32 {
+    let ptr = lua_touserdata(l, 1) as *mut T;
+    if !ptr.is_null() {
+        unsafe { ptr::drop_in_place(ptr) };
+    }
+    0
+}
+
+/// Reads an optional session passed to a CRUD function
+pub unsafe fn opt_session(l: LuaState, index: i32) -> Option<SessionHandle> {
+    #[cfg(feature = "transactions")]
+    if is_udata(l, index, cstr!("MongoDBSession")) {
+        return read_userdata(l, index).ok();
+    }
+    #[cfg(not(feature = "transactions"))]
+    let _ = (l, index);
+    None
+}
+
+/// Applies the settings keys found in the option table at `index`
+pub unsafe fn read_settings_table(l: LuaState, index: i32, settings: &mut ClientSettings) -> LuaResult<()> {
+    lua_getfield(l, index, cstr!("int64"));
+    if lua_isstring(l, -1) != 0 {
+        let name = std::ffi::CStr::from_ptr(lua_tostring(l, -1)).to_string_lossy().into_owned();
+        lua_pop(l, 1);
+        settings.int64 = Int64Mode::from_name(&name).ok_or_else(|| LuaError::InvalidArgument {
+            position: index.unsigned_abs() as usize,
+            message: format!("Unknown int64 mode '{}', expected 'number' or 'precise'", name),
+        })?;
+    } else {
+        lua_pop(l, 1);
+    }
+
+    lua_getfield(l, index, cstr!("log_errors"));
+    if lua_isboolean(l, -1) {
+        settings.log_errors = lua_toboolean(l, -1) != 0;
+    }
+    lua_pop(l, 1);
+
+    // 0 turns an inherited timeout off for this call
+    lua_getfield(l, index, cstr!("timeout_ms"));
+    if lua_isnumber(l, -1) != 0 {
+        let ms = lua_tonumber(l, -1);
+        settings.timeout = (ms > 0.0).then(|| Duration::from_millis(ms as u64));
+    } else if !lua_isnil(l, -1) {
+        lua_pop(l, 1);
+        return Err(LuaError::InvalidArgument {
+            position: index.unsigned_abs() as usize,
+            message: "Option 'timeout_ms' must be a number".to_string(),
+        });
+    }
+    lua_pop(l, 1);
+    Ok(())
+}
+
+/// Stores `settings` in the environment table of the userdata on top of the
+/// stack. The concurrency limit is shared with the userdata at `from`; a
+/// client passes `None` and gets a new token for its limit.
+pub unsafe fn attach_settings(l: LuaState, settings: &ClientSettings, from: Option<i32>) {
+    lua_newtable(l);
+    lua_pushboolean(l, 1);
+    lua_setfield(l, -2, cstr!("__mongodb_settings"));
+    let int64 = settings.int64.name();
+    lua_pushlstring(l, int64.as_ptr() as LuaString, int64.len());
+    lua_setfield(l, -2, cstr!("int64"));
+    lua_pushboolean(l, settings.log_errors as i32);
+    lua_setfield(l, -2, cstr!("log_errors"));
+    if let Some(timeout) = settings.timeout {
+        lua_pushinteger(l, timeout.as_millis() as _);
+        lua_setfield(l, -2, cstr!("timeout_ms"));
+    }
+
+    match (from, &settings.limiter) {
+        (Some(from), _) if is_userdata(l, from) => {
+            lua_getfenv(l, from);
+            lua_getfield(l, -1, cstr!("__mongodb_limiter"));
+            lua_setfield(l, -3, cstr!("__mongodb_limiter"));
+            lua_pop(l, 1);
+        }
+        (None, Some(limiter)) => {
+            write_userdata(l, limiter.clone());
+            luaL_getmetatable(l, cstr!("MongoDBLimiter"));
+            lua_setmetatable(l, -2);
+            lua_setfield(l, -2, cstr!("__mongodb_limiter"));
+        }
+        _ => {}
+    }
+    lua_setfenv(l, -2);
+}
+
+/// Gives the userdata on top of the stack the settings of the userdata at `from`
+pub unsafe fn inherit_settings(l: LuaState, from: i32) {
+    let settings = read_settings(l, from);
+    attach_settings(l, &settings, Some(from));
+}
+
+/// Settings carried by the client, database or collection userdata at `index`
+pub unsafe fn read_settings(l: LuaState, index: i32) -> ClientSettings {
+    let mut settings = ClientSettings::default();
+    if !is_userdata(l, index) {
+        return settings;
+    }
+
+    lua_getfenv(l, index);
+    lua_getfield(l, -1, cstr!("__mongodb_settings"));
+    let attached = lua_toboolean(l, -1) != 0;
+    lua_pop(l, 1);
+    if attached {
+        // Stored values were validated when the client was created
+        let _ = read_settings_table(l, -1, &mut settings);
+        lua_getfield(l, -1, cstr!("__mongodb_limiter"));
+        if is_udata(l, -1, cstr!("MongoDBLimiter")) {
+            settings.limiter = read_userdata::<Limiter>(l, -1).ok();
+        }
+        lua_pop(l, 1);
+    }
+    lua_pop(l, 1);
+    settings
+}
+
+/// Reads the last argument of a call, which is either a session or a table
+/// of call options such as `{ session = s, timeout_ms = 500 }`, applying
+/// the options on top of `settings`
+pub unsafe fn call_options(l: LuaState, mut settings: ClientSettings, index: i32) -> LuaResult<(ClientSettings, Option<SessionHandle>)> {
+    if !lua_istable(l, index) {
+        return Ok((settings, opt_session(l, index)));
+    }
+
+    read_settings_table(l, index, &mut settings)?;
+    lua_getfield(l, index, cstr!("session"));
+    let session = opt_session(l, -1);
+    lua_pop(l, 1);
+    Ok((settings, session))
+}
+
+/// Settings for a single call: those of the userdata at `index`, overridden by
+/// the keys of the option table at `options` when one is given
+pub unsafe fn call_settings(l: LuaState, index: i32, options: i32) -> LuaResult<ClientSettings> {
+    let mut settings = read_settings(l, index);
+    if lua_istable(l, options) {
+        read_settings_table(l, options, &mut settings)?;
+    }
+    Ok(settings)
+}
+
 pub unsafe fn check_string(l: LuaState, index: i32) -> LuaResult<String> {
     let ptr = luaL_checkstring(l, index);
     if ptr.is_null() {
@@ -43,6 +206,111 @@ pub unsafe fn check_string(l: LuaState, index: i32) -> LuaResult<String> {
         })
 }
 
+/// Reads a Lua string as raw bytes, keeping embedded zeros
+pub unsafe fn check_bytes(l: LuaState, index: i32) -> LuaResult<Vec<u8>> {
+    if lua_isstring(l, index) == 0 {
+        return Err(LuaError::InvalidArgument {
+            position: index as usize,
+            message: "Expected string".to_string(),
+        });
+    }
+
+    let mut len = 0;
+    let ptr = lua_tolstring(l, index, &mut len);
+    Ok(std::slice::from_raw_parts(ptr as *const u8, len).to_vec())
+}
+
+pub unsafe fn push_bytes(l: LuaState, bytes: &[u8]) {
+    lua_pushlstring(l, bytes.as_ptr() as LuaString, bytes.len());
+}
+
+unsafe fn set_string_field(l: LuaState, field: LuaString, value: &str) {
+    push_bytes(l, value.as_bytes());
+    lua_setfield(l, -2, field);
+}
+
+/// Pushes `error` as a `MongoDBError` table: `code`, `code_name`, `message`,
+/// `labels`, `category` and `write_errors`, whose `index` counts from 1
+pub unsafe fn push_error_object(l: LuaState, error: &ErrorDetails) {
+    lua_createtable(l, 0, 7);
+
+    if let Some(code) = error.code {
+        lua_pushinteger(l, code as _);
+        lua_setfield(l, -2, cstr!("code"));
+    }
+    if let Some(code_name) = &error.code_name {
+        set_string_field(l, cstr!("code_name"), code_name);
+    }
+    set_string_field(l, cstr!("message"), &error.message);
+    set_string_field(l, cstr!("category"), error.category.as_str());
+    if let Some(kind) = error.kind {
+        set_string_field(l, cstr!("kind"), kind);
+    }
+
+    lua_createtable(l, error.labels.len() as i32, 0);
+    for (i, label) in error.labels.iter().enumerate() {
+        push_bytes(l, label.as_bytes());
+        lua_rawseti(l, -2, (i + 1) as i32);
+    }
+    lua_setfield(l, -2, cstr!("labels"));
+
+    lua_createtable(l, error.write_errors.len() as i32, 0);
+    for (i, failure) in error.write_errors.iter().enumerate() {
+        lua_createtable(l, 0, 4);
+        lua_pushinteger(l, (failure.index + 1) as _);
+        lua_setfield(l, -2, cstr!("index"));
+        lua_pushinteger(l, failure.code as _);
+        lua_setfield(l, -2, cstr!("code"));
+        if let Some(code_name) = &failure.code_name {
+            set_string_field(l, cstr!("code_name"), code_name);
+        }
+        set_string_field(l, cstr!("message"), &failure.message);
+        lua_rawseti(l, -2, (i + 1) as i32);
+    }
+    lua_setfield(l, -2, cstr!("write_errors"));
+
+    luaL_getmetatable(l, cstr!("MongoDBError"));
+    lua_setmetatable(l, -2);
+}
+
+/// Ends a failed call the Lua way, returning `nil, err`; the failure is also
+/// logged unless the call's `settings` turned `log_errors` off
+pub unsafe fn push_failure(l: LuaState, settings: &ClientSettings, context: &str, error: impl Into<ErrorDetails>) -> i32 {
+    let error = error.into();
+    if settings.log_errors {
+        log::error!("{}: {}", context, error);
+    }
+    lua_pushnil(l);
+    push_error_object(l, &error);
+    2
+}
+
+/// Like `push_failure`, for arguments that could not be converted; the
+/// message keeps `context` since there is no server message to go by
+pub unsafe fn push_invalid(l: LuaState, settings: &ClientSettings, context: &str, error: impl std::fmt::Display) -> i32 {
+    let error = invalid_details(settings, context, error);
+    lua_pushnil(l);
+    push_error_object(l, &error);
+    2
+}
+
+/// Like `push_invalid` for async calls, which return `false, err` when no job
+/// was queued
+pub unsafe fn push_rejected(l: LuaState, settings: &ClientSettings, context: &str, error: impl std::fmt::Display) -> i32 {
+    let error = invalid_details(settings, context, error);
+    lua_pushboolean(l, 0);
+    push_error_object(l, &error);
+    2
+}
+
+fn invalid_details(settings: &ClientSettings, context: &str, error: impl std::fmt::Display) -> ErrorDetails {
+    let error = ErrorDetails::new(ErrorCategory::Validation, format!("{}: {}", context, error));
+    if settings.log_errors {
+        log::error!("{}", error);
+    }
+    error
+}
+
 pub unsafe fn opt_string(l: LuaState, index: i32) -> LuaResult<Option<String>> {
     if lua_isnoneornil(l, index) {
         return Ok(None);